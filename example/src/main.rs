@@ -1,65 +1,14 @@
 use wasmedge_stable_diffusion::stable_diffusion_interface::{ImageType, SdTypeT, RngTypeT, SampleMethodT, ScheduleT};
-use wasmedge_stable_diffusion::{BaseFunction, Context, Quantization, StableDiffusion, Task};
-use clap::{crate_version, Arg, ArgAction, Command};
+use wasmedge_stable_diffusion::gguf::GgufInfo;
+use wasmedge_stable_diffusion::model_file::{ModelFileInfo, ModelFormat};
+use wasmedge_stable_diffusion::{BaseFunction, Context, GenerationParams, GuidanceParams, ImageMetadata, ImageOutput, Outpaint, OutpaintFill, PrecisionPlan, Quantization, StableDiffusion, Task};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::parser::ValueSource;
+use clap::{crate_version, Arg, ArgAction, ArgMatches, Command};
 
 use std::str::FromStr;
 
 use rand::Rng;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-const WTYPE_METHODS: [&str; 32] = [
-    "f32",
-    "f16",
-    "q4_0",
-    "q4_1",
-    "",
-    "",
-    "q5_0",
-    "q5_1",
-    "q8_0",
-    "q8_1",
-    "q2k",
-    "q3k",
-    "q4k",
-    "q5k",
-    "q6k",
-    "q8k",
-    "iq2Xxs",
-    "iq2Xs",
-    "iq3Xxs",
-    "iq1S",
-    "iq4N1",
-    "iq3S",
-    "iq2S",
-    "iq4Xs",
-    "i8",
-    "i16",
-    "i32",
-    "i64",
-    "f64",
-    "iq1M",
-    "bf16",
-    "count"
-];
-//Sampling Methods
-const SAMPLE_METHODS: [&str; 8] = [
-    "euler_a",
-    "euler",
-    "heun",
-    "dpm2",
-    "dpm++2s_a",
-    "dpm++2m",
-    "dpm++2mv2",
-    "lcm",
-];
-
-// Names of the sigma schedule overrides, same order as sample_schedule in stable-diffusion.h
-const SCHEDULE_STR: [&str; 4] = [
-    "default",
-    "discrete",
-    "karras",
-    "ays",
-];
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("wasmedge-stable-diffusion")
@@ -72,9 +21,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .value_parser([
                     "txt2img",
                     "img2img",
+                    "inpaint",
+                    "outpaint",
+                    "upscale",
                     "convert",
+                    "info",
+                    "rerun",
                 ])
-                .help("run mode (txt2img or img2img or convert, default: txt2img).")
+                .help("run mode (txt2img or img2img or inpaint or outpaint or upscale or convert or rerun or info, default: txt2img). upscale only runs the ESRGAN model of --upscale-model over --init-img. info describes the .gguf, .safetensors or .ckpt file of --model without loading it. convert checks --model and prints the estimated output size before converting. rerun repeats the generation recorded in --params-from, with the options given on the command line as overrides.")
                 .default_value("txt2img"),
         )
         .arg(
@@ -94,6 +48,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("path to model.")
                 .default_value("stable-diffusion-v1-4-Q8_0.gguf"),
         )
+        .arg(
+            Arg::new("diffusion_model_path")
+                .long("diffusion-model")
+                .value_name("PATH")
+                .help("path to the standalone diffusion model of SD3 or Flux, used instead of --model. Needs a build with the host-v2 feature.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("clip_l_path")
+                .long("clip_l")
+                .value_name("PATH")
+                .help("path to the clip-l text encoder.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("clip_g_path")
+                .long("clip_g")
+                .value_name("PATH")
+                .help("path to the clip-g text encoder.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("t5xxl_path")
+                .long("t5xxl")
+                .value_name("PATH")
+                .help("path to the t5xxl text encoder.")
+                .default_value(""),
+        )
         .arg(
             Arg::new("vae_path")
                 .long("vae")
@@ -157,46 +139,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Run the ESRGAN upscaler this many times (default 1).")
                 .default_value("1"),
         )
+        .arg(
+            Arg::new("convert_types")
+                .long("convert-types")
+                .value_name("TYPES")
+                .help("comma-separated weight types to convert to in one run, e.g. q8_0,q4_k. Each output is named after --output with the type appended.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("tensor_type_rules")
+                .long("tensor-type-rules")
+                .value_name("RULES")
+                .help("per-tensor weight types for convert, as comma-separated prefix=type rules, e.g. first_stage_model.=f16. The first matching prefix wins. Needs a build with the host-v2 feature.")
+                .default_value(""),
+        )
         .arg(
             Arg::new("type")
                 .long("type")
                 .value_name("TYPE")
-                .value_parser([
-                    "f32",
-                    "f16",
-                    "q4_0",
-                    "q4_1",
-                    "q5_0",
-                    "q5_1",
-                    "q8_0",
-
-                    "q8_1",
-                    "q2k",
-                    "q3k",
-                    "q4k",
-                    "q5k",
-                    "q6k",
-                    "q8k",
-                    "iq2Xxs",
-                    "iq2Xs",
-                    "iq3Xxs",
-                    "iq1S",
-                    "iq4N1",
-                    "iq3S",
-                    "iq2S",
-                    "iq4Xs",
-                    "i8",
-                    "i16",
-                    "i32",
-                    "i64",
-                    "f64",
-                    "iq1M",
-                    "bf16",
-
-                    "count"
-                ])
-                .help("weight type (f32, f16, q4_0, q4_1, q5_0, q5_1, q8_0)If not specified, the default is the type of the weight file.")
-                .default_value("count"),
+                .value_parser(PossibleValuesParser::new(
+                    SdTypeT::all().map(|wtype| PossibleValue::new(wtype.name()).aliases(wtype.aliases().iter().copied())),
+                ))
+                .help("weight type (f32, f16, q4_0, q4_1, q5_0, q5_1, q8_0, q2_k ... bf16). default keeps the type of the weight file. Older spellings such as q4k and count are still accepted.")
+                .default_value("default"),
         )
         .arg(
             Arg::new("lora_model_dir")
@@ -210,8 +175,59 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short('i')
                 .long("init-img")
                 .value_name("IMAGE")
-                .help("path to the input image, required by img2img.")
+                .help("path to the input image, required by img2img, inpaint, outpaint and upscale. outpaint only reads PNG input images, not JPEG.")
                 .default_value("./output.png"),
+        )
+        .arg(
+            Arg::new("mask")
+                .long("mask")
+                .value_name("IMAGE")
+                .help("path to the mask image, required by inpaint. White pixels are regenerated; it must be the size of the input image.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("mask_blur")
+                .long("mask-blur")
+                .value_parser(clap::value_parser!(i32))
+                .value_name("PIXELS")
+                .help("blur radius of the mask edges (default: 4).")
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("keep_unmasked")
+                .long("keep-unmasked")
+                .action(ArgAction::SetTrue)
+                .help("copy the pixels outside the mask from the input image unchanged."),
+        )
+        .arg(
+            Arg::new("extend")
+                .long("extend")
+                .value_name("LEFT,TOP,RIGHT,BOTTOM")
+                .help("pixels to add on each side of the input image, used by outpaint.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("aspect")
+                .long("aspect")
+                .value_name("W:H")
+                .help("pad the input image evenly to this aspect ratio, e.g. 16:9, used by outpaint instead of --extend.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("feather")
+                .long("feather")
+                .value_parser(clap::value_parser!(u32))
+                .value_name("PIXELS")
+                .help("width of the mask fade into the input image, used by outpaint (default: 32).")
+                .default_value("32"),
+        )
+        .arg(
+            Arg::new("outpaint_fill")
+                .long("outpaint-fill")
+                .value_name("FILL")
+                .value_parser(["stretch", "noise"])
+                .help("what the new area holds before generation, used by outpaint (default: stretch).")
+                .default_value("stretch"),
         )             
         .arg(
             Arg::new("control_image")
@@ -252,6 +268,52 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("unconditional guidance scale: (default: 7.0).")
                 .default_value("7.0"),
         )
+        .arg(
+            Arg::new("guidance")
+                .long("guidance")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("SCALE")
+                .help("distilled guidance scale for Flux models (default: 3.5).")
+                .default_value("3.5"),
+        )
+        .arg(
+            Arg::new("img_cfg_scale")
+                .long("img-cfg-scale")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("SCALE")
+                .help("image guidance scale of instruct models (default: same as --cfg-scale)."),
+        )
+        .arg(
+            Arg::new("skip_layers")
+                .long("skip-layers")
+                .value_name("LAYERS")
+                .help("comma-separated layers to skip for skip-layer guidance (default: 7,8,9).")
+                .default_value("7,8,9"),
+        )
+        .arg(
+            Arg::new("skip_layer_start")
+                .long("skip-layer-start")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("START")
+                .help("fraction of the steps at which skip-layer guidance starts (default: 0.01).")
+                .default_value("0.01"),
+        )
+        .arg(
+            Arg::new("skip_layer_end")
+                .long("skip-layer-end")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("END")
+                .help("fraction of the steps at which skip-layer guidance ends (default: 0.2).")
+                .default_value("0.2"),
+        )
+        .arg(
+            Arg::new("slg_scale")
+                .long("slg-scale")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("SCALE")
+                .help("skip-layer guidance scale, only for DiT models like SD3.5 medium; 0 disables it (default: 0). Needs a build with the host-v2 feature.")
+                .default_value("0"),
+        )
         .arg(
             Arg::new("strength")
                 .long("strength")
@@ -297,18 +359,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .arg(
             Arg::new("sampling_method")
                 .long("sampling-method")
-                .value_parser([
-                    "euler_a",
-                    "euler",
-                    "heun",
-                    "dpm2",
-                    "dpm++2s_a",
-                    "dpm++2m",
-                    "dpm++2mv2",
-                    "lcm",
-                ])
+                .value_parser(SampleMethodT::NAMES.to_vec())
                 .value_name("SAMPLING_METHOD")
-                .help("the sampling method, include values {euler, euler_a, heun, dpm2, dpm++2s_a, dpm++2m, dpm++2mv2, lcm},  sampling method (default: euler_a)")
+                .help("the sampling method, include values {euler, euler_a, heun, dpm2, dpm++2s_a, dpm++2m, dpm++2mv2, lcm, ipndm, ipndm_v, ddim_trailing, tcd},  sampling method (default: euler_a). The last four need a build with the host-v2 feature.")
                 .default_value("euler_a"),
         )
         .arg(
@@ -319,14 +372,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("number of sample steps (default: 20).")
                 .default_value("20"),
         )           
+        .arg(
+            Arg::new("eta")
+                .long("eta")
+                .value_parser(clap::value_parser!(f32))
+                .value_name("ETA")
+                .help("eta of ddim_trailing and tcd, from 0 to 1 (default: 0).")
+                .default_value("0"),
+        )
         .arg(
             Arg::new("rng_type")
                 .long("rng")
                 .value_name("RNG")
-                .value_parser([
-                    "std_default",
-                    "cuda",
-                ])
+                .value_parser(RngTypeT::NAMES.to_vec())
                 .help("RNG (default: std_default).")
                 .default_value("std_default"),
         )
@@ -334,9 +392,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arg::new("seed")
                 .short('s')
                 .long("seed")
-                .value_parser(clap::value_parser!(i32))
+                .allow_negative_numbers(true)
                 .value_name("SEED")
-                .help("RNG seed (default: 42, use random seed for < 0).")
+                .help("RNG seed, 0 to 2^64-1 (default: 42, use random seed for < 0).")
                 .default_value("42"),
         )
         .arg(
@@ -352,12 +410,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arg::new("schedule")
                 .long("schedule")
                 .value_name("SCHEDULE")
-                .value_parser([
-                    "default",
-                    "discrete",
-                    "karras",
-                    "ays",
-                ])
+                .value_parser(ScheduleT::NAMES.to_vec())
                 .help("Denoiser sigma schedule")
                 .default_value("default"),
         )
@@ -398,6 +451,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("vae-on-cpu")
                 .help("vae on cpu.")
                 .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("params_from")
+                .long("params-from")
+                .value_name("IMAGE")
+                .help("png whose embedded generation parameters are rerun, required by rerun. The model still comes from --model; a warning is printed if it is not the recorded one.")
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("no_metadata")
+                .long("no-metadata")
+                .help("don't embed the generation parameters in the output png.")
+                .action(ArgAction::SetTrue),
         )                                    
         .after_help("run at the dir of .wasm, Example:wasmedge --dir .:. ./target/wasm32-wasi/release/wasmedge_stable_diffusion_example.wasm -m ../../models/stable-diffusion-v1-4-Q8_0.gguf -M img2img\n")
         .get_matches();
@@ -406,17 +472,66 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut options = Options::default();
 
     //mode, include "txt2img","img2img",----------"convert" is not yet-------.
-    let sd_mode = matches.get_one::<String>("mode").unwrap();
-    let task = Task::from_str(sd_mode)?;
+    let mut sd_mode = matches.get_one::<String>("mode").unwrap().as_str();
+
+    //info: describe the model file and stop
+    if sd_mode == "info" {
+        print_model_info(matches.get_one::<String>("model").unwrap())?;
+        return Ok(());
+    }
+
+    //rerun: start from the parameters embedded in an image
+    let mut rerun = None;
+    if sd_mode == "rerun" {
+        let params_from = matches.get_one::<String>("params_from").unwrap();
+        if params_from.is_empty() {
+            return Err("Error: rerun needs --params-from".into());
+        }
+        let parsed = ImageMetadata::read(&std::fs::read(params_from)?)?;
+        for field in &parsed.unmapped {
+            println!("[WARN] not applied: {}", field);
+        }
+        sd_mode = match parsed.metadata.task() {
+            Task::TextToImage => "txt2img",
+            Task::ImageToImage => "img2img",
+            Task::Inpaint => "inpaint",
+            Task::Upscale => "upscale",
+        };
+        rerun = Some(parsed.metadata);
+    }
+    //outpaint runs as an inpaint on the padded image
+    let task = match sd_mode {
+        "outpaint" => Task::Inpaint,
+        mode => Task::from_str(mode)?,
+    };
     options.mode = sd_mode.to_string();
     
     //n_threads
     let n_threads = matches.get_one::<i32>("n_threads").unwrap();
     options.n_threads = *n_threads as i32;
 
-    //model
-    let sd_model = matches.get_one::<String>("model").unwrap();
+    //diffusion_model_path
+    let diffusion_model_path = matches.get_one::<String>("diffusion_model_path").unwrap();
+    options.diffusion_model_path = diffusion_model_path.to_string();
+
+    //model, unless only the diffusion model is given
+    let sd_model = match matches.value_source("model") {
+        Some(ValueSource::DefaultValue) if !diffusion_model_path.is_empty() => "",
+        _ => matches.get_one::<String>("model").unwrap(),
+    };
     options.model_path = sd_model.to_string();
+
+    //clip_l_path
+    let clip_l_path = matches.get_one::<String>("clip_l_path").unwrap();
+    options.clip_l_path = clip_l_path.to_string();
+
+    //clip_g_path
+    let clip_g_path = matches.get_one::<String>("clip_g_path").unwrap();
+    options.clip_g_path = clip_g_path.to_string();
+
+    //t5xxl_path
+    let t5xxl_path = matches.get_one::<String>("t5xxl_path").unwrap();
+    options.t5xxl_path = t5xxl_path.to_string();
     
     //vae_path
     let vae_path = matches.get_one::<String>("vae_path").unwrap();
@@ -458,25 +573,66 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Err("Error: the upscale_repeats must be greater than 0".into());
     }
     options.upscale_repeats = *upscale_repeats as i32;
+    if sd_mode == "upscale" && upscale_model.is_empty() {
+        return Err("Error: upscale needs --upscale-model".into());
+    }
 
     //type
-    let wtype_selected = matches.get_one::<String>("type").unwrap();
-    let wtype_found = WTYPE_METHODS
-        .iter()
-        .position(|&method| method == wtype_selected)
-        .ok_or(format!("Invalid wtype: {}",wtype_selected))?;
-    let wtype = SdTypeT::from_index(wtype_found)?;
+    let wtype = SdTypeT::from_str(matches.get_one::<String>("type").unwrap())?;
     options.wtype = wtype;
 
     //lora_model_dir
     let lora_model_dir = matches.get_one::<String>("lora_model_dir").unwrap();
     options.lora_model_dir = lora_model_dir.to_string();
 
-    //init_img, used only for img2img
+    //init_img, used only for img2img and inpaint
     let img = matches.get_one::<String>("init_img").unwrap();
-    if sd_mode == "img2img" {
+    if ["img2img", "inpaint", "outpaint", "upscale"].contains(&sd_mode) {
         options.init_img = img.to_string();
     };
+
+    //mask, mask_blur and keep_unmasked, used only for inpaint
+    let mask = matches.get_one::<String>("mask").unwrap();
+    if sd_mode == "inpaint" && mask.is_empty() {
+        return Err("Error: inpaint needs --mask".into());
+    }
+    options.mask = mask.to_string();
+    let mask_blur = matches.get_one::<i32>("mask_blur").unwrap();
+    if *mask_blur < 0 {
+        return Err("Error: mask_blur must not be negative".into());
+    }
+    options.mask_blur = *mask_blur;
+    options.keep_unmasked = matches.get_flag("keep_unmasked");
+
+    //extend, aspect, feather and outpaint_fill, used only for outpaint
+    let extend = matches.get_one::<String>("extend").unwrap();
+    let aspect = matches.get_one::<String>("aspect").unwrap();
+    let mut outpaint = None;
+    if sd_mode == "outpaint" {
+        let source = ImageType::Path(&options.init_img);
+        let mut padding = if !aspect.is_empty() {
+            let (width, height) = source
+                .dimensions()
+                .ok_or("Error: cannot read the size of --init-img")?;
+            let (ratio_width, ratio_height) = aspect
+                .split_once(':')
+                .ok_or("Error: --aspect must look like 16:9")?;
+            Outpaint::to_aspect(width, height, ratio_width.parse()?, ratio_height.parse()?)?
+        } else {
+            let sides = extend
+                .split(',')
+                .map(|side| side.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let [left, top, right, bottom] = sides[..] else {
+                return Err("Error: outpaint needs --aspect or --extend LEFT,TOP,RIGHT,BOTTOM".into());
+            };
+            Outpaint::new(left, top, right, bottom)
+        };
+        padding = padding
+            .feather(*matches.get_one::<u32>("feather").unwrap())
+            .fill(OutpaintFill::from_str(matches.get_one::<String>("outpaint_fill").unwrap())?);
+        outpaint = Some(padding);
+    }
     
     //control_image
     let control_image = matches.get_one::<String>("control_image").unwrap();
@@ -498,6 +654,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cfg_scale = matches.get_one::<f32>("cfg_scale").unwrap();
     options.cfg_scale = *cfg_scale as f32;
 
+    //guidance
+    let guidance = matches.get_one::<f32>("guidance").unwrap();
+    options.guidance = *guidance;
+
+    //img_cfg_scale
+    options.img_cfg_scale = matches.get_one::<f32>("img_cfg_scale").copied();
+
+    //skip-layer guidance
+    let skip_layers = matches.get_one::<String>("skip_layers").unwrap();
+    options.skip_layers = skip_layers
+        .split(',')
+        .map(str::trim)
+        .filter(|layer| !layer.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()?;
+    options.skip_layer_start = *matches.get_one::<f32>("skip_layer_start").unwrap();
+    options.skip_layer_end = *matches.get_one::<f32>("skip_layer_end").unwrap();
+    options.slg_scale = *matches.get_one::<f32>("slg_scale").unwrap();
+
     //strength
     let strength = matches.get_one::<f32>("strength").unwrap();
     if *strength < 0.0  || *strength > 1.0 {
@@ -528,12 +703,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     options.width = *width as i32;
 
     //sampling_method
-    let sampling_method_selected = matches .get_one::<String>("sampling_method").unwrap();
-    let sample_method_found = SAMPLE_METHODS
-        .iter()
-        .position(|&method| method == sampling_method_selected)
-        .ok_or(format!("Invalid sampling method: {}",sampling_method_selected))?;
-    let sample_method = SampleMethodT::from_index(sample_method_found)?;
+    let sample_method = SampleMethodT::from_str(matches.get_one::<String>("sampling_method").unwrap())?;
     options.sample_method = sample_method;
 
     //sample_steps
@@ -543,27 +713,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     options.sample_steps = *sample_steps as i32;
 
+    //eta
+    options.eta = *matches.get_one::<f32>("eta").unwrap();
+
     //rng_type
-    let mut rng_type = RngTypeT::StdDefaultRng;
-    let rng_type_str = matches.get_one::<String>("rng_type").unwrap();
-    if rng_type_str == "cuda"{
-        rng_type =  RngTypeT::CUDARng;
-    }
+    let rng_type = RngTypeT::from_str(matches.get_one::<String>("rng_type").unwrap())?;
     options.rng_type = rng_type;
 
     //seed
-    let seed_str = matches.get_one::<i32>("seed").unwrap();
-    let mut seed  = *seed_str;
-    // let mut seed: i32 = seed_str.parse().expect("Failed to parse seed as i32");
-    if seed < 0 {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let current_time_secs = current_time.as_secs() as u32;
-
-        let mut rng = rand::thread_rng();
-        seed = ((rng.gen::<u32>() ^ current_time_secs) & i32::MAX as u32) as i32; // 将结果限制在 i32 范围内
-    }
+    let seed_str = matches.get_one::<String>("seed").unwrap();
+    let seed = if seed_str.starts_with('-') {
+        seed_str
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid seed: {}", seed_str))?;
+        rand::thread_rng().gen::<u64>()
+    } else {
+        seed_str
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid seed: {}", seed_str))?
+    };
     options.seed = seed;
 
     //batch_count
@@ -571,13 +739,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     options.batch_count = *batch_count as i32;
 
     //schedule
-    let schedule_selected = matches.get_one::<String>("schedule").unwrap();
-    let schedule_found = SCHEDULE_STR
-        .iter()
-        .position(|&method| method == schedule_selected)
-        .ok_or(format!("Invalid sampling method: {}",schedule_selected))?;
-    // Convert an index to an enumeration value
-    let schedule = ScheduleT::from_index(schedule_found)?;
+    let schedule = ScheduleT::from_str(matches.get_one::<String>("schedule").unwrap())?;
     options.schedule = schedule;
     
     //clip_skip
@@ -604,89 +766,190 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let vae_on_cpu = matches.get_flag("vae_on_cpu");
     options.vae_on_cpu = vae_on_cpu;
 
+    //no_metadata
+    options.embed_metadata = !matches.get_flag("no_metadata");
+
+
+    if let Some(metadata) = &rerun {
+        // The model is not recorded as a path, so it cannot be reloaded; only
+        // flag a run with a different one.
+        let model_path = match task {
+            Task::Upscale => &options.upscale_model,
+            _ if options.model_path.is_empty() => &options.diffusion_model_path,
+            _ => &options.model_path,
+        };
+        let model = std::path::Path::new(model_path).file_stem().unwrap_or_default().to_string_lossy();
+        if !metadata.model.is_empty() && metadata.model != model {
+            let params_from = matches.get_one::<String>("params_from").unwrap();
+            println!("[WARN] {} was made with model {}, running {}", params_from, metadata.model, model);
+        }
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if !given("schedule") {
+            options.schedule = metadata.schedule;
+        }
+        if let (Some(strength), false) = (metadata.strength, given("strength")) {
+            options.strength = strength;
+        }
+        if let (Some(mask_blur), false) = (metadata.mask_blur, given("mask_blur")) {
+            options.mask_blur = mask_blur;
+        }
+    }
+    let schedule = options.schedule;
 
     //DEBUG: print options from CL
     print_params(&mut options);
     
     //------------------------------- run the model ----------------------------------------
-    let context = StableDiffusion::new(task, sd_model,
-        vae_path,
-        taesd_path, 
-        control_net_path, 
-        lora_model_dir, embeddings_path, stacked_id_embd_dir,
-        vae_tiling, 
-        *n_threads,
-        wtype,
-        rng_type,
-        schedule,
-        clip_on_cpu,
-        control_net_cpu,
-        vae_on_cpu
-    );
-    match sd_mode.as_str(){
+    let context = StableDiffusion::builder()
+        .task(task)
+        .model_path(sd_model)
+        .diffusion_model_path(diffusion_model_path)
+        .clip_l_path(clip_l_path)
+        .clip_g_path(clip_g_path)
+        .t5xxl_path(t5xxl_path)
+        .upscale_model_path(upscale_model)
+        .vae_path(vae_path)
+        .taesd_path(taesd_path)
+        .control_net_path(control_net_path)
+        .lora_model_dir(lora_model_dir)
+        .embed_dir(embeddings_path)
+        .id_embed_dir(stacked_id_embd_dir)
+        .vae_tiling(vae_tiling)
+        .n_threads(*n_threads)
+        .wtype(wtype)
+        .rng_type(rng_type)
+        .schedule(schedule)
+        .clip_on_cpu(clip_on_cpu)
+        .control_net_cpu(control_net_cpu)
+        .vae_on_cpu(vae_on_cpu)
+        .build()?;
+    let params = GenerationParams {
+        prompt: options.prompt,
+        width: options.width,
+        height: options.height,
+        control_image: ImageType::Path(&options.control_image),
+        negative_prompt: options.negative_prompt,
+        clip_skip: options.clip_skip,
+        guidance: GuidanceParams {
+            cfg_scale: options.cfg_scale,
+            img_cfg_scale: options.img_cfg_scale,
+            distilled_guidance: options.guidance,
+            slg_layers: options.skip_layers,
+            slg_start: options.skip_layer_start,
+            slg_end: options.skip_layer_end,
+            slg_scale: options.slg_scale,
+        },
+        sample_method: options.sample_method,
+        sample_steps: options.sample_steps,
+        eta: options.eta,
+        seed: options.seed,
+        batch_count: options.batch_count,
+        control_strength: options.control_strength,
+        style_ratio: options.style_ratio,
+        normalize_input: options.normalize_input,
+        input_id_images_dir: options.input_id_images_dir,
+        canny_preprocess: options.canny,
+        upscale_model: options.upscale_model,
+        upscale_repeats: options.upscale_repeats,
+        output_path: options.output_path,
+    };
+    let params = match &rerun {
+        Some(metadata) => rerun_params(metadata, params, &matches),
+        None => params,
+    };
+    match sd_mode {
         "txt2img" => {
             println!("txt2img");
             if let Context::TextToImage(mut text_to_image) = context.create_context().unwrap() {
-                text_to_image
-                    .set_base_params(options.prompt, 
-                        options.width,
-                        options.height,
-                        ImageType::Path(&options.control_image),
-                        options.negative_prompt,
-                        options.clip_skip,
-                        options.cfg_scale,
-                        options.sample_method,
-                        options.sample_steps,
-                        options.seed,
-                        options.batch_count,
-                        options.control_strength,
-                        options.style_ratio,
-                        options.normalize_input,
-                        options.input_id_images_dir,
-                        options.canny,
-                        options.upscale_model,
-                        options.upscale_repeats,
-                        options.output_path
-                    )
-                    .generate()
+                let outputs = text_to_image
+                    .set_params(params)
+                    .set_embed_metadata(options.embed_metadata)
+                    .generate_images()
                     .unwrap();
+                print_outputs(&outputs);
             }
         },
         "img2img" => {
             println!("img2img");
             if let Context::ImageToImage(mut image_to_image) = context.create_context().unwrap() {
-                image_to_image
-                    .set_base_params(options.prompt, 
-                        options.width,
-                        options.height,
-                        ImageType::Path(&options.control_image),
-                        options.negative_prompt,
-                        options.clip_skip,
-                        options.cfg_scale,
-                        options.sample_method,
-                        options.sample_steps,
-                        options.seed,
-                        options.batch_count,
-                        options.control_strength,
-                        options.style_ratio,
-                        options.normalize_input,
-                        options.input_id_images_dir,
-                        options.canny,
-                        options.upscale_model,
-                        options.upscale_repeats,
-                        options.output_path
-                    )
+                let outputs = image_to_image
+                    .set_params(params)
+                    .set_embed_metadata(options.embed_metadata)
                     .set_image(ImageType::Path(&options.init_img))
                     .set_strength(options.strength)
-                    .generate()
+                    .generate_images()
                     .unwrap();
+                print_outputs(&outputs);
+            }
+        },
+        "inpaint" => {
+            println!("inpaint");
+            if let Context::Inpaint(mut inpaint) = context.create_context().unwrap() {
+                let outputs = inpaint
+                    .set_params(params)
+                    .set_embed_metadata(options.embed_metadata)
+                    .set_image(ImageType::Path(&options.init_img))
+                    .set_mask(ImageType::Path(&options.mask))
+                    .set_strength(options.strength)
+                    .set_mask_blur(options.mask_blur)
+                    .set_keep_unmasked(options.keep_unmasked)
+                    .generate_images()
+                    .unwrap();
+                print_outputs(&outputs);
+            }
+        },
+        "outpaint" => {
+            println!("outpaint");
+            let outpaint = outpaint.unwrap().seed(params.seed);
+            let canvas = outpaint.prepare(&ImageType::Path(&options.init_img))?;
+            println!("[INFO] canvas:            {}x{}", canvas.width, canvas.height);
+            if let Context::Inpaint(mut inpaint) = context.create_context().unwrap() {
+                let outputs = inpaint
+                    .set_params(params)
+                    .set_embed_metadata(options.embed_metadata)
+                    .set_canvas(&canvas)
+                    .set_strength(options.strength)
+                    .set_mask_blur(options.mask_blur)
+                    .set_keep_unmasked(options.keep_unmasked)
+                    .generate_images()
+                    .unwrap();
+                print_outputs(&outputs);
+            }
+        },
+        "upscale" => {
+            println!("upscale");
+            if let Context::Upscale(mut upscale) = context.create_context().unwrap() {
+                let output = upscale
+                    .set_image(ImageType::Path(&options.init_img))
+                    .set_repeats(options.upscale_repeats)
+                    .set_output_path(output_path)
+                    .upscale()
+                    .unwrap();
+                println!("[INFO] wrote {} bytes to {}", output.bytes_written, output.output_path);
             }
         },
         "convert" => {
             println!("into Mode: Convert!");
             // Quantization::new("./sd-v1-4.ckpt", "stable-diffusion-v1-4-Q8_0.gguf", SdTypeT::SdTypeQ8_0);
-            let quantization = Quantization::new( sd_model, output_path, wtype);
-            quantization.convert().unwrap();
+            let plan = PrecisionPlan::from_str(matches.get_one::<String>("tensor_type_rules").unwrap())?;
+            let quantization = Quantization::new( sd_model, output_path, wtype)
+                .with_vae_model_path(vae_path)
+                .with_precision_plan(plan);
+            let convert_types = matches.get_one::<String>("convert_types").unwrap();
+            let mut outputs = Vec::new();
+            if convert_types.is_empty() {
+                outputs.push((output_path.to_string(), wtype));
+            }
+            for name in convert_types.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                let wtype = SdTypeT::from_str(name)?;
+                outputs.push((typed_output_path(output_path, name), wtype));
+            }
+            for (path, wtype) in &outputs {
+                let size = quantization.estimate_size_as(*wtype)?;
+                println!("[INFO] {}: estimated size {:.1} MiB", path, size as f64 / (1024.0 * 1024.0));
+            }
+            let outputs: Vec<_> = outputs.iter().map(|(path, wtype)| (path.as_str(), *wtype)).collect();
+            quantization.convert_all(&outputs)?;
         },
         _ => {
             println!("Error: this mode isn't supported!");
@@ -696,14 +959,64 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     return Ok(());
 }
 
+/// The parameters recorded in an image, except for the ones given on the
+/// command line.
+fn rerun_params<'a>(
+    metadata: &ImageMetadata,
+    params: GenerationParams<'a>,
+    matches: &ArgMatches,
+) -> GenerationParams<'a> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let mut rerun = GenerationParams {
+        control_image: params.control_image,
+        output_path: params.output_path,
+        ..metadata.params.clone()
+    };
+    macro_rules! keep_given {
+        ($($id:literal => $($field:ident).+),* $(,)?) => {
+            $(if given($id) {
+                rerun.$($field).+ = params.$($field).+;
+            })*
+        };
+    }
+    keep_given!(
+        "prompt" => prompt,
+        "negative_prompt" => negative_prompt,
+        "width" => width,
+        "height" => height,
+        "clip_skip" => clip_skip,
+        "cfg_scale" => guidance.cfg_scale,
+        "img_cfg_scale" => guidance.img_cfg_scale,
+        "guidance" => guidance.distilled_guidance,
+        "skip_layers" => guidance.slg_layers,
+        "skip_layer_start" => guidance.slg_start,
+        "skip_layer_end" => guidance.slg_end,
+        "slg_scale" => guidance.slg_scale,
+        "sampling_method" => sample_method,
+        "sample_steps" => sample_steps,
+        "eta" => eta,
+        "seed" => seed,
+        "batch_count" => batch_count,
+        "control_strength" => control_strength,
+        "style_ratio" => style_ratio,
+        "normalize_input" => normalize_input,
+        "input_id_images_dir" => input_id_images_dir,
+        "canny" => canny_preprocess,
+        "upscale_model" => upscale_model,
+        "upscale_repeats" => upscale_repeats,
+    );
+    rerun
+}
+
 #[derive(Debug)]
 struct Options {
     n_threads: i32,
     mode: String,
     model_path: String,
-    //clip_l_path
-    //t5xxl_path
-    //diffusion_model_path
+    clip_l_path: String,
+    clip_g_path: String,
+    t5xxl_path: String,
+    diffusion_model_path: String,
     vae_path: String,
     taesd_path: String,
     control_net_path: String,
@@ -716,12 +1029,18 @@ struct Options {
     output_path: String,//output
     init_img: String,
     control_image: String,
+    mask: String,
 
 
     prompt: String,
     negative_prompt: String,
     cfg_scale: f32,
-    //guidance
+    img_cfg_scale: Option<f32>,
+    guidance: f32,
+    skip_layers: Vec<i32>,
+    skip_layer_start: f32,
+    skip_layer_end: f32,
+    slg_scale: f32,
     style_ratio: f32,
     clip_skip: i32,
     width: i32,
@@ -732,10 +1051,13 @@ struct Options {
     sample_method: SampleMethodT,
     schedule: ScheduleT,
     sample_steps: i32,
+    eta: f32,
     strength: f32,
+    mask_blur: i32,
+    keep_unmasked: bool,
     control_strength: f32,
     rng_type: RngTypeT,
-    seed: i32,
+    seed: u64,
     vae_tiling: bool,
     control_net_cpu: bool,
     normalize_input: bool,
@@ -743,6 +1065,7 @@ struct Options {
     vae_on_cpu: bool,
     canny: bool,
     upscale_repeats: i32, 
+    embed_metadata: bool,
 }
 
 impl Default for Options {
@@ -751,9 +1074,10 @@ impl Default for Options {
             n_threads: -1,
             mode: String::from("txt2img"),
             model_path: String::from(""),
-            //clip_l_path
-            //t5xxl_path
-            //diffusion_model_path
+            clip_l_path: String::from(""),
+            clip_g_path: String::from(""),
+            t5xxl_path: String::from(""),
+            diffusion_model_path: String::from(""),
             vae_path: String::from(""),
             taesd_path: String::from(""),
             control_net_path: String::from(""),
@@ -766,12 +1090,18 @@ impl Default for Options {
             output_path: String::from(""),
             init_img: String::from(""),
             control_image: String::from(""),
+            mask: String::from(""),
         
         
             prompt: String::from(""),
             negative_prompt: String::from(""),
             cfg_scale: 7.0,
-            //guidance
+            img_cfg_scale: None,
+            guidance: 3.5,
+            skip_layers: vec![7, 8, 9],
+            skip_layer_start: 0.01,
+            skip_layer_end: 0.2,
+            slg_scale: 0.0,
             style_ratio: 20.0,
             clip_skip: -1,
             width: 512,
@@ -782,7 +1112,10 @@ impl Default for Options {
             sample_method: SampleMethodT::EULERA,
             schedule: ScheduleT::DEFAULT,
             sample_steps: 20,
+            eta: 0.0,
             strength: 0.75,
+            mask_blur: 4,
+            keep_unmasked: false,
             control_strength: 0.9,
             rng_type: RngTypeT::StdDefaultRng,
             seed: 42,
@@ -793,16 +1126,62 @@ impl Default for Options {
             vae_on_cpu: false,
             canny: false,
             upscale_repeats: 1, 
+            embed_metadata: true,
         }
     }
 }
 
 
+fn print_model_info(model_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let info = ModelFileInfo::read(model_path)?;
+    println!("[INFO] model:             {}", model_path);
+    println!("[INFO] format:            {}", info.format);
+    if info.format == ModelFormat::Gguf {
+        println!("[INFO] gguf version:      {}", GgufInfo::read(model_path)?.version);
+    }
+    println!("[INFO] architecture:      {}", info.model_version());
+    println!("[INFO] tensors:           {}", info.tensors.len());
+    for (dtype, count) in info.dtype_counts() {
+        println!("[INFO]   {:<16} {}", dtype, count);
+    }
+    let size = info.estimated_size(SdTypeT::SdTypeCount);
+    println!("[INFO] total size:        {:.1} MiB", size as f64 / (1024.0 * 1024.0));
+    println!("[INFO] embedded vae:      {}", info.has_vae());
+    for tensor in info.unsupported() {
+        println!("[WARN] cannot be loaded:  {} ({})", tensor.name, tensor.dtype);
+    }
+    Ok(())
+}
+
+/// `output_path` with `-<type>` inserted before the extension.
+fn typed_output_path(output_path: &str, type_name: &str) -> String {
+    let path = std::path::Path::new(output_path);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!("{}-{}.{}", stem.to_string_lossy(), type_name, extension.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}-{}", output_path, type_name),
+    }
+}
+
+fn print_outputs(outputs: &[ImageOutput]) {
+    for output in outputs {
+        if output.file_written {
+            println!("[INFO] wrote {} (seed {})", output.output_path, output.seed);
+        }
+    }
+}
+
 fn print_params(params: &mut Options) {
     println!("Option:");
     println!("[INFO] n_threads:         {}", params.n_threads);
     println!("[INFO] mode:              {}", params.mode);
     println!("[INFO] model_path:        {}", params.model_path);
+    println!("[INFO] diffusion_model:   {}", params.diffusion_model_path);
+    println!("[INFO] clip_l_path:       {}", params.clip_l_path);
+    println!("[INFO] clip_g_path:       {}", params.clip_g_path);
+    println!("[INFO] t5xxl_path:        {}", params.t5xxl_path);
     println!("[INFO] vae_path:          {}", params.vae_path);
     println!("[INFO] taesd_path:        {}", params.taesd_path);
     println!("[INFO] control_net_path:  {}", params.control_net_path);
@@ -810,25 +1189,37 @@ fn print_params(params: &mut Options) {
     println!("[INFO] embeddings_path:   {}", params.embeddings_path);
     println!("[INFO] stacked_id_embd:   {}", params.stacked_id_embd_dir);
     println!("[INFO] input_id_images:   {}", params.input_id_images_dir);
-    println!("[INFO] wtype:             {:?}", params.wtype);
+    println!("[INFO] wtype:             {}", params.wtype);
     println!("[INFO] lora_model_dir:    {}", params.lora_model_dir);
     println!("[INFO] output_path:       {}", params.output_path);
     println!("[INFO] init_img:          {}", params.init_img);
     println!("[INFO] control_image:     {}", params.control_image);
+    println!("[INFO] mask:              {}", params.mask);
     println!("[INFO] prompt:            {}", params.prompt);
     println!("[INFO] negative_prompt:   {}", params.negative_prompt);
     println!("[INFO] cfg_scale:         {}", params.cfg_scale);
+    if let Some(img_cfg_scale) = params.img_cfg_scale {
+        println!("[INFO] img_cfg_scale:     {}", img_cfg_scale);
+    }
+    println!("[INFO] guidance:          {}", params.guidance);
+    println!("[INFO] skip_layers:       {:?}", params.skip_layers);
+    println!("[INFO] skip_layer_start:  {}", params.skip_layer_start);
+    println!("[INFO] skip_layer_end:    {}", params.skip_layer_end);
+    println!("[INFO] slg_scale:         {}", params.slg_scale);
     println!("[INFO] style_ratio:       {}", params.style_ratio);
     println!("[INFO] clip_skip:         {}", params.clip_skip);
     println!("[INFO] width:             {}", params.width);
     println!("[INFO] height:            {}", params.height);
     println!("[INFO] batch_count:       {}", params.batch_count);
-    println!("[INFO] sample_method:   {:?}", params.sample_method);
-    println!("[INFO] schedule:          {:?}", params.schedule);
+    println!("[INFO] sample_method:     {}", params.sample_method);
+    println!("[INFO] schedule:          {}", params.schedule);
     println!("[INFO] sample_steps:      {}", params.sample_steps);
+    println!("[INFO] eta:               {}", params.eta);
     println!("[INFO] strength:          {}", params.strength);
+    println!("[INFO] mask_blur:         {}", params.mask_blur);
+    println!("[INFO] keep_unmasked:     {}", params.keep_unmasked);
     println!("[INFO] control_strength:  {}", params.control_strength);
-    println!("[INFO] rng_type:          {:?}", params.rng_type);
+    println!("[INFO] rng_type:          {}", params.rng_type);
     println!("[INFO] seed:              {}", params.seed);
     println!("[INFO] vae_tiling:        {}", params.vae_tiling);
     println!("[INFO] control_net_cpu:   {}", params.control_net_cpu);
@@ -837,6 +1228,7 @@ fn print_params(params: &mut Options) {
     println!("[INFO] vae_on_cpu:        {}", params.vae_on_cpu);
     println!("[INFO] canny:             {}", params.canny);
     println!("[INFO] upscale_repeats:   {}", params.upscale_repeats);
+    println!("[INFO] embed_metadata:    {}", params.embed_metadata);
 }
 
     // if you downloaded ckpt weights, you can use convert() to quantize the ckpt weight to gguf.
@@ -0,0 +1,828 @@
+//! The host the crate talks to.
+//!
+//! [`WasmEdgeBackend`] forwards to the `wasmedge_stablediffusion` import module
+//! and is what every context uses unless told otherwise. On native targets
+//! [`MockBackend`] stands in for the plugin so the crate can be tested with a
+//! plain `cargo test`.
+use crate::stable_diffusion_interface::{
+    self, ImageType, SampleMethodT, ScheduleT, SdTypeT, WasmedgeSdErrno,
+};
+use crate::{seed, ContextConfig, GenerationParams};
+
+/// Sampling methods and schedules a host runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub sample_methods: Vec<SampleMethodT>,
+    pub schedules: Vec<ScheduleT>,
+}
+
+impl Capabilities {
+    /// What every plugin runs: the sampling methods and schedules with a
+    /// [`legacy_id`](SampleMethodT::legacy_id), up to `LCM` and `AYS`.
+    pub fn baseline() -> Capabilities {
+        Capabilities {
+            sample_methods: SampleMethodT::all()
+                .filter(|method| method.legacy_id().is_some())
+                .collect(),
+            schedules: ScheduleT::all()
+                .filter(|schedule| schedule.legacy_id().is_some())
+                .collect(),
+        }
+    }
+
+    /// Every sampling method and schedule this crate knows.
+    pub fn all() -> Capabilities {
+        Capabilities {
+            sample_methods: SampleMethodT::ALL.to_vec(),
+            schedules: ScheduleT::ALL.to_vec(),
+        }
+    }
+
+    /// Decodes the host bitmasks, where bit `i` stands for stable-diffusion.cpp
+    /// id `i`, the enum value. Ids this crate does not know are dropped.
+    pub fn from_masks(sample_methods: u32, schedules: u32) -> Capabilities {
+        let has = |mask: u32, id: i32| id < 32 && mask & (1 << id) != 0;
+        Capabilities {
+            sample_methods: SampleMethodT::all()
+                .filter(|&method| has(sample_methods, method as i32))
+                .collect(),
+            schedules: ScheduleT::all()
+                .filter(|&schedule| has(schedules, schedule as i32))
+                .collect(),
+        }
+    }
+
+    pub fn supports_sample_method(&self, method: SampleMethodT) -> bool {
+        self.sample_methods.contains(&method)
+    }
+
+    pub fn supports_schedule(&self, schedule: ScheduleT) -> bool {
+        self.schedules.contains(&schedule)
+    }
+}
+
+/// Operations the crate needs from a stable diffusion host.
+///
+/// The generation calls follow the host protocol: the encoded image goes into
+/// `output_buf` and its length into `bytes_written`. With a `batch_count`
+/// above one the images are written back to back. A buffer that is too small
+/// is reported with `MISSING_MEMORY` and, if known, the needed size in
+/// `bytes_written`.
+pub trait Backend: Send + Sync {
+    /// Loads the models described by `config` and returns the new session id.
+    fn create_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno>;
+
+    /// Releases a session returned by `create_context`.
+    fn free_context(&self, session_id: u32) -> Result<(), WasmedgeSdErrno>;
+
+    /// Whether `free_context` can release sessions. Contexts of backends that
+    /// cannot report [`SdError::Unsupported`](crate::SdError::Unsupported) on
+    /// close instead of calling it.
+    fn frees_sessions(&self) -> bool {
+        true
+    }
+
+    fn text_to_image(
+        &self,
+        session_id: u32,
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno>;
+
+    fn image_to_image(
+        &self,
+        session_id: u32,
+        image: &ImageType,
+        strength: f32,
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno>;
+
+    /// Regenerates the white parts of `mask` in `image`. Backends without
+    /// inpainting fail with `RUNTIME_ERROR`.
+    #[allow(clippy::too_many_arguments)]
+    fn inpaint(
+        &self,
+        _session_id: u32,
+        _image: &ImageType,
+        _mask: &ImageType,
+        _strength: f32,
+        _mask_blur: i32,
+        _keep_unmasked: bool,
+        _params: &GenerationParams,
+        _output_buf: &mut [u8],
+        _bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_RUNTIME_ERROR)
+    }
+
+    /// Loads only the upscaler named by `config.upscale_model_path` and
+    /// returns the new session id. Backends without a standalone upscaler fail
+    /// with `RUNTIME_ERROR`.
+    fn create_upscaler_context(&self, _config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+        Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_RUNTIME_ERROR)
+    }
+
+    /// Upscales `image` `upscale_repeats` times on a session returned by
+    /// `create_upscaler_context`.
+    fn upscale(
+        &self,
+        _session_id: u32,
+        _image: &ImageType,
+        _upscale_repeats: i32,
+        _output_path: &str,
+        _output_buf: &mut [u8],
+        _bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_RUNTIME_ERROR)
+    }
+
+    fn convert(
+        &self,
+        model_path: &str,
+        vae_model_path: &str,
+        output_path: &str,
+        wtype: SdTypeT,
+    ) -> Result<(), WasmedgeSdErrno>;
+
+    /// Like `convert`, with `tensor_type_rules` overriding `wtype` for the
+    /// tensors it matches. Backends without per-tensor types fail with
+    /// `RUNTIME_ERROR`.
+    fn convert_with_rules(
+        &self,
+        _model_path: &str,
+        _vae_model_path: &str,
+        _output_path: &str,
+        _wtype: SdTypeT,
+        _tensor_type_rules: &str,
+    ) -> Result<(), WasmedgeSdErrno> {
+        Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_RUNTIME_ERROR)
+    }
+
+    /// Sampling methods and schedules the host runs. Contexts check them
+    /// before calling the host, since a plugin given an id it does not know
+    /// may do anything. Defaults to [`Capabilities::baseline`].
+    fn capabilities(&self) -> Result<Capabilities, WasmedgeSdErrno> {
+        Ok(Capabilities::baseline())
+    }
+}
+
+/// Sends the settings the generation calls have no argument for ahead of
+/// the call.
+#[cfg(feature = "host-v2")]
+unsafe fn set_extended_params(session_id: u32, params: &GenerationParams) -> Result<(), WasmedgeSdErrno> {
+    let guidance = &params.guidance;
+    stable_diffusion_interface::set_guidance_params(
+        session_id,
+        guidance.cfg_scale,
+        guidance.img_cfg_scale(),
+        guidance.distilled_guidance,
+        &guidance.slg_layers,
+        guidance.slg_start,
+        guidance.slg_end,
+        guidance.slg_scale,
+    )?;
+    stable_diffusion_interface::set_eta(session_id, params.eta)
+}
+
+/// Backend calling the `wasmedge_stablediffusion` host functions.
+///
+/// Without the `host-v2` feature it only imports `create_context`,
+/// `text_to_image`, `image_to_image` and `convert`, which every plugin has.
+/// Configs with separate model components then fail with `INVALID_ARGUMENT`;
+/// inpainting, upscaling and conversions with per-tensor types fail with
+/// `RUNTIME_ERROR`, and sessions are never freed. Only the `cfg_scale` of
+/// `GenerationParams::guidance` is sent, `eta` is not sent and
+/// [`Capabilities::baseline`] is all it offers.
+///
+/// Outside WasmEdge every call fails with `RUNTIME_ERROR`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WasmEdgeBackend;
+
+impl Backend for WasmEdgeBackend {
+    #[cfg(not(feature = "host-v2"))]
+    fn create_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+        // Separate components need `create_context_v2`.
+        if config.has_components() {
+            return Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+        }
+        let mut session_id = 0u32;
+        unsafe {
+            stable_diffusion_interface::create_context(
+                &config.model_path,
+                &config.vae_path,
+                &config.taesd_path,
+                &config.control_net_path,
+                &config.lora_model_dir,
+                &config.embed_dir,
+                &config.id_embed_dir,
+                config.vae_decode_only,
+                config.vae_tiling,
+                config.n_threads,
+                config.wtype,
+                config.rng_type,
+                config.schedule,
+                config.clip_on_cpu,
+                config.control_net_cpu,
+                config.vae_on_cpu,
+                &mut session_id,
+            )?;
+        }
+        Ok(session_id)
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn create_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+        let mut session_id = 0u32;
+        unsafe {
+            stable_diffusion_interface::create_context_v2(
+                &config.model_path,
+                &config.clip_l_path,
+                &config.clip_g_path,
+                &config.t5xxl_path,
+                &config.diffusion_model_path,
+                &config.vae_path,
+                &config.taesd_path,
+                &config.control_net_path,
+                &config.lora_model_dir,
+                &config.embed_dir,
+                &config.id_embed_dir,
+                config.vae_decode_only,
+                config.vae_tiling,
+                config.n_threads,
+                config.wtype,
+                config.rng_type,
+                config.schedule,
+                config.clip_on_cpu,
+                config.control_net_cpu,
+                config.vae_on_cpu,
+                &mut session_id,
+            )?;
+        }
+        Ok(session_id)
+    }
+
+    #[cfg(not(feature = "host-v2"))]
+    fn free_context(&self, _session_id: u32) -> Result<(), WasmedgeSdErrno> {
+        // Older plugins cannot free a session; it lives until the module exits.
+        Err(stable_diffusion_interface::WASMEDGE_SD_ERRNO_RUNTIME_ERROR)
+    }
+
+    fn frees_sessions(&self) -> bool {
+        cfg!(feature = "host-v2")
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn free_context(&self, session_id: u32) -> Result<(), WasmedgeSdErrno> {
+        unsafe { stable_diffusion_interface::free_context(session_id) }
+    }
+
+    fn text_to_image(
+        &self,
+        session_id: u32,
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe {
+            #[cfg(feature = "host-v2")]
+            set_extended_params(session_id, params)?;
+            stable_diffusion_interface::text_to_image(
+                &params.prompt,
+                session_id,
+                &params.control_image,
+                &params.negative_prompt,
+                params.width,
+                params.height,
+                params.clip_skip,
+                params.guidance.cfg_scale,
+                params.sample_method,
+                params.sample_steps,
+                seed::host_seed(params.seed),
+                params.batch_count,
+                params.control_strength,
+                params.style_ratio,
+                params.normalize_input,
+                &params.input_id_images_dir,
+                params.canny_preprocess,
+                &params.upscale_model,
+                params.upscale_repeats,
+                &params.output_path,
+                output_buf,
+                bytes_written,
+            )
+        }
+    }
+
+    fn image_to_image(
+        &self,
+        session_id: u32,
+        image: &ImageType,
+        strength: f32,
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe {
+            #[cfg(feature = "host-v2")]
+            set_extended_params(session_id, params)?;
+            stable_diffusion_interface::image_to_image(
+                image,
+                session_id,
+                params.width,
+                params.height,
+                &params.control_image,
+                &params.prompt,
+                &params.negative_prompt,
+                params.clip_skip,
+                params.guidance.cfg_scale,
+                params.sample_method,
+                params.sample_steps,
+                strength,
+                seed::host_seed(params.seed),
+                params.batch_count,
+                params.control_strength,
+                params.style_ratio,
+                params.normalize_input,
+                &params.input_id_images_dir,
+                params.canny_preprocess,
+                &params.upscale_model,
+                params.upscale_repeats,
+                &params.output_path,
+                output_buf,
+                bytes_written,
+            )
+        }
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn inpaint(
+        &self,
+        session_id: u32,
+        image: &ImageType,
+        mask: &ImageType,
+        strength: f32,
+        mask_blur: i32,
+        keep_unmasked: bool,
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe {
+            set_extended_params(session_id, params)?;
+            stable_diffusion_interface::inpaint(
+                image,
+                mask,
+                session_id,
+                params.width,
+                params.height,
+                &params.control_image,
+                &params.prompt,
+                &params.negative_prompt,
+                params.clip_skip,
+                params.guidance.cfg_scale,
+                params.sample_method,
+                params.sample_steps,
+                strength,
+                mask_blur,
+                keep_unmasked,
+                seed::host_seed(params.seed),
+                params.batch_count,
+                params.control_strength,
+                params.style_ratio,
+                params.normalize_input,
+                &params.input_id_images_dir,
+                params.canny_preprocess,
+                &params.upscale_model,
+                params.upscale_repeats,
+                &params.output_path,
+                output_buf,
+                bytes_written,
+            )
+        }
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn create_upscaler_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+        let mut session_id = 0u32;
+        unsafe {
+            stable_diffusion_interface::create_upscaler_context(
+                &config.upscale_model_path,
+                config.n_threads,
+                config.wtype,
+                &mut session_id,
+            )?;
+        }
+        Ok(session_id)
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn upscale(
+        &self,
+        session_id: u32,
+        image: &ImageType,
+        upscale_repeats: i32,
+        output_path: &str,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe {
+            stable_diffusion_interface::upscale(
+                session_id,
+                image,
+                upscale_repeats,
+                output_path,
+                output_buf,
+                bytes_written,
+            )
+        }
+    }
+
+    fn convert(
+        &self,
+        model_path: &str,
+        vae_model_path: &str,
+        output_path: &str,
+        wtype: SdTypeT,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe { stable_diffusion_interface::convert(model_path, vae_model_path, output_path, wtype) }
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn convert_with_rules(
+        &self,
+        model_path: &str,
+        vae_model_path: &str,
+        output_path: &str,
+        wtype: SdTypeT,
+        tensor_type_rules: &str,
+    ) -> Result<(), WasmedgeSdErrno> {
+        unsafe {
+            stable_diffusion_interface::convert_with_rules(
+                model_path,
+                vae_model_path,
+                output_path,
+                wtype,
+                tensor_type_rules,
+            )
+        }
+    }
+
+    #[cfg(feature = "host-v2")]
+    fn capabilities(&self) -> Result<Capabilities, WasmedgeSdErrno> {
+        let (mut sample_methods, mut schedules) = (0u32, 0u32);
+        unsafe { stable_diffusion_interface::capabilities(&mut sample_methods, &mut schedules)? };
+        Ok(Capabilities::from_masks(sample_methods, schedules))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use mock::{MockBackend, MockCall};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod mock {
+    use super::{Backend, Capabilities};
+    use crate::marshal::encode_image;
+    use crate::png;
+    use crate::stable_diffusion_interface::*;
+    use crate::{ContextConfig, GenerationParams};
+    use std::collections::BTreeSet;
+    use std::sync::Mutex;
+
+    /// Largest image the mock draws, so an oversized request fails instead of
+    /// exhausting memory.
+    const MAX_PIXELS: u64 = 1 << 26;
+
+    /// A call received by [`MockBackend`].
+    ///
+    /// Images are recorded in their marshalled form (`path:...`, `bytes:...`,
+    /// `rgb:...`) and `params.control_image` is replaced by `control_image`.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum MockCall {
+        CreateContext {
+            session_id: u32,
+            config: ContextConfig,
+        },
+        FreeContext {
+            session_id: u32,
+        },
+        TextToImage {
+            session_id: u32,
+            params: GenerationParams<'static>,
+            control_image: Vec<u8>,
+        },
+        ImageToImage {
+            session_id: u32,
+            params: GenerationParams<'static>,
+            control_image: Vec<u8>,
+            image: Vec<u8>,
+            strength: f32,
+        },
+        Inpaint {
+            session_id: u32,
+            params: GenerationParams<'static>,
+            control_image: Vec<u8>,
+            image: Vec<u8>,
+            mask: Vec<u8>,
+            strength: f32,
+            mask_blur: i32,
+            keep_unmasked: bool,
+        },
+        CreateUpscalerContext {
+            session_id: u32,
+            config: ContextConfig,
+        },
+        Upscale {
+            session_id: u32,
+            image: Vec<u8>,
+            upscale_repeats: i32,
+            output_path: String,
+        },
+        Convert {
+            model_path: String,
+            vae_model_path: String,
+            output_path: String,
+            wtype: SdTypeT,
+        },
+        ConvertWithRules {
+            model_path: String,
+            vae_model_path: String,
+            output_path: String,
+            wtype: SdTypeT,
+            tensor_type_rules: String,
+        },
+    }
+
+    #[derive(Default)]
+    struct State {
+        next_session_id: u32,
+        live_sessions: BTreeSet<u32>,
+        calls: Vec<MockCall>,
+        fail_next: Option<WasmedgeSdErrno>,
+        capabilities: Option<Capabilities>,
+        keeps_sessions: bool,
+    }
+
+    /// In-memory host that records every call and answers generations with
+    /// `batch_count` solid gray PNGs of the requested size. Upscaling answers
+    /// with one gray PNG four times the input size per repeat. Images above
+    /// 2^26 pixels fail with `INVALID_ARGUMENT`.
+    ///
+    /// It follows the buffer protocol of the real host, so a small output
+    /// buffer gets `MISSING_MEMORY` along with the size it needs.
+    #[derive(Default)]
+    pub struct MockBackend {
+        state: Mutex<State>,
+    }
+
+    impl MockBackend {
+        pub fn new() -> MockBackend {
+            MockBackend::default()
+        }
+
+        /// Every call received so far, oldest first.
+        pub fn calls(&self) -> Vec<MockCall> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        /// Sessions created and not freed yet.
+        pub fn live_sessions(&self) -> Vec<u32> {
+            self.state.lock().unwrap().live_sessions.iter().copied().collect()
+        }
+
+        /// Limits what the mock reports from `capabilities`, to stand in for an
+        /// older plugin. It reports [`Capabilities::all`] otherwise.
+        pub fn set_capabilities(&self, capabilities: Capabilities) {
+            self.state.lock().unwrap().capabilities = Some(capabilities);
+        }
+
+        /// Makes `frees_sessions` report false, to stand in for an older
+        /// plugin.
+        pub fn keep_sessions(&self) {
+            self.state.lock().unwrap().keeps_sessions = true;
+        }
+
+        /// Makes the next call fail with `errno` instead of succeeding.
+        pub fn fail_next(&self, errno: WasmedgeSdErrno) {
+            self.state.lock().unwrap().fail_next = Some(errno);
+        }
+
+        fn record(&self, call: MockCall) -> Result<(), WasmedgeSdErrno> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(call);
+            match state.fail_next.take() {
+                Some(errno) => Err(errno),
+                None => Ok(()),
+            }
+        }
+
+        fn check_session(&self, session_id: u32) -> Result<(), WasmedgeSdErrno> {
+            if self.state.lock().unwrap().live_sessions.contains(&session_id) {
+                Ok(())
+            } else {
+                Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
+            }
+        }
+    }
+
+    fn write_images(
+        params: &GenerationParams,
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        let (width, height) = (params.width, params.height);
+        if width <= 0 || height <= 0 || params.batch_count <= 0 {
+            return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+        }
+        let image = gray_png(width as u32, height as u32)?.repeat(params.batch_count as usize);
+        write_output(&image, output_buf, bytes_written)
+    }
+
+    /// A solid gray PNG, or `INVALID_ARGUMENT` above [`MAX_PIXELS`].
+    fn gray_png(width: u32, height: u32) -> Result<Vec<u8>, WasmedgeSdErrno> {
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+        }
+        let pixels = vec![128u8; width as usize * height as usize * 3];
+        Ok(png::encode(width, height, 3, &pixels))
+    }
+
+    fn write_output(
+        image: &[u8],
+        output_buf: &mut [u8],
+        bytes_written: &mut u32,
+    ) -> Result<(), WasmedgeSdErrno> {
+        *bytes_written = image.len() as u32;
+        if image.len() > output_buf.len() {
+            return Err(WASMEDGE_SD_ERRNO_MISSING_MEMORY);
+        }
+        output_buf[..image.len()].copy_from_slice(image);
+        Ok(())
+    }
+
+    impl Backend for MockBackend {
+        fn create_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+            let session_id = self.state.lock().unwrap().next_session_id;
+            self.record(MockCall::CreateContext {
+                session_id,
+                config: config.clone(),
+            })?;
+            let mut state = self.state.lock().unwrap();
+            state.next_session_id += 1;
+            state.live_sessions.insert(session_id);
+            Ok(session_id)
+        }
+
+        fn free_context(&self, session_id: u32) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::FreeContext { session_id })?;
+            if self.state.lock().unwrap().live_sessions.remove(&session_id) {
+                Ok(())
+            } else {
+                Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
+            }
+        }
+
+        fn frees_sessions(&self) -> bool {
+            !self.state.lock().unwrap().keeps_sessions
+        }
+
+        fn text_to_image(
+            &self,
+            session_id: u32,
+            params: &GenerationParams,
+            output_buf: &mut [u8],
+            bytes_written: &mut u32,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::TextToImage {
+                session_id,
+                params: params.without_control_image(),
+                control_image: encode_image(&params.control_image)?,
+            })?;
+            self.check_session(session_id)?;
+            write_images(params, output_buf, bytes_written)
+        }
+
+        fn image_to_image(
+            &self,
+            session_id: u32,
+            image: &ImageType,
+            strength: f32,
+            params: &GenerationParams,
+            output_buf: &mut [u8],
+            bytes_written: &mut u32,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::ImageToImage {
+                session_id,
+                params: params.without_control_image(),
+                control_image: encode_image(&params.control_image)?,
+                image: encode_image(image)?,
+                strength,
+            })?;
+            self.check_session(session_id)?;
+            write_images(params, output_buf, bytes_written)
+        }
+
+        fn inpaint(
+            &self,
+            session_id: u32,
+            image: &ImageType,
+            mask: &ImageType,
+            strength: f32,
+            mask_blur: i32,
+            keep_unmasked: bool,
+            params: &GenerationParams,
+            output_buf: &mut [u8],
+            bytes_written: &mut u32,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::Inpaint {
+                session_id,
+                params: params.without_control_image(),
+                control_image: encode_image(&params.control_image)?,
+                image: encode_image(image)?,
+                mask: encode_image(mask)?,
+                strength,
+                mask_blur,
+                keep_unmasked,
+            })?;
+            self.check_session(session_id)?;
+            write_images(params, output_buf, bytes_written)
+        }
+
+        fn create_upscaler_context(&self, config: &ContextConfig) -> Result<u32, WasmedgeSdErrno> {
+            let session_id = self.state.lock().unwrap().next_session_id;
+            self.record(MockCall::CreateUpscalerContext {
+                session_id,
+                config: config.clone(),
+            })?;
+            let mut state = self.state.lock().unwrap();
+            state.next_session_id += 1;
+            state.live_sessions.insert(session_id);
+            Ok(session_id)
+        }
+
+        fn upscale(
+            &self,
+            session_id: u32,
+            image: &ImageType,
+            upscale_repeats: i32,
+            output_path: &str,
+            output_buf: &mut [u8],
+            bytes_written: &mut u32,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::Upscale {
+                session_id,
+                image: encode_image(image)?,
+                upscale_repeats,
+                output_path: output_path.to_string(),
+            })?;
+            self.check_session(session_id)?;
+            let (width, height) = image.dimensions().ok_or(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)?;
+            if !(1..=4).contains(&upscale_repeats) {
+                return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+            }
+            let factor = 4u32.pow(upscale_repeats as u32);
+            let width = width.checked_mul(factor).ok_or(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)?;
+            let height = height.checked_mul(factor).ok_or(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)?;
+            write_output(&gray_png(width, height)?, output_buf, bytes_written)
+        }
+
+        fn convert(
+            &self,
+            model_path: &str,
+            vae_model_path: &str,
+            output_path: &str,
+            wtype: SdTypeT,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::Convert {
+                model_path: model_path.to_string(),
+                vae_model_path: vae_model_path.to_string(),
+                output_path: output_path.to_string(),
+                wtype,
+            })
+        }
+
+        fn convert_with_rules(
+            &self,
+            model_path: &str,
+            vae_model_path: &str,
+            output_path: &str,
+            wtype: SdTypeT,
+            tensor_type_rules: &str,
+        ) -> Result<(), WasmedgeSdErrno> {
+            self.record(MockCall::ConvertWithRules {
+                model_path: model_path.to_string(),
+                vae_model_path: vae_model_path.to_string(),
+                output_path: output_path.to_string(),
+                wtype,
+                tensor_type_rules: tensor_type_rules.to_string(),
+            })
+        }
+
+        fn capabilities(&self) -> Result<Capabilities, WasmedgeSdErrno> {
+            let capabilities = self.state.lock().unwrap().capabilities.clone();
+            Ok(capabilities.unwrap_or_else(Capabilities::all))
+        }
+    }
+}
@@ -0,0 +1,789 @@
+//! Reads the tensor table of a PyTorch checkpoint (`.ckpt`, `.pt`).
+//!
+//! `torch.save` writes a zip archive whose `*/data.pkl` entry pickles the
+//! state dict, with each tensor's storage held in a separate entry. Only the
+//! pickle is read, by a small interpreter that understands the opcodes torch
+//! emits and never imports or calls anything.
+use crate::inflate;
+use crate::model_file::{ModelFileInfo, ModelFormat, ReadError, TensorEntry};
+use crate::stable_diffusion_interface::SdTypeT;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Largest `data.pkl` read into memory, before and after decompression. A
+/// state dict pickle is a few megabytes even for the largest models.
+const MAX_PICKLE_LEN: u64 = 256 << 20;
+/// How far from the end of the archive the end of central directory record
+/// may start: its size plus the longest comment.
+const MAX_EOCD_SEARCH: u64 = 22 + 0xffff;
+/// Deepest nesting of tuples, lists and dicts. A state dict nests a handful
+/// of levels; the limit keeps recursion over values off the end of the stack.
+const MAX_DEPTH: usize = 64;
+/// Most bytes of values copied by DUP and the memo opcodes over a whole
+/// pickle, so a short pickle cannot copy a large container over and over.
+const MAX_COPIED_LEN: usize = MAX_PICKLE_LEN as usize;
+
+/// Reads the tensor table of the zip archive in `reader`.
+pub(crate) fn read(mut reader: impl Read + Seek) -> Result<ModelFileInfo, ReadError> {
+    let pickle = read_data_pkl(&mut reader)?;
+    let root = Machine::new(&pickle).run()?;
+    let mut tensors = Vec::new();
+    collect(&root, "", &mut tensors);
+    Ok(ModelFileInfo {
+        format: ModelFormat::Ckpt,
+        metadata: Vec::new(),
+        tensors,
+    })
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn read_at(reader: &mut (impl Read + Seek), offset: u64, len: usize) -> Result<Vec<u8>, ReadError> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Finds `*/data.pkl` through the central directory and returns its contents.
+fn read_data_pkl(reader: &mut (impl Read + Seek)) -> Result<Vec<u8>, ReadError> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let tail_start = file_len.saturating_sub(MAX_EOCD_SEARCH);
+    let tail = read_at(reader, tail_start, (file_len - tail_start) as usize)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| tail[at..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| ReadError::format("no zip directory; the file is not a checkpoint or is truncated"))?;
+    let mut entries = u16_at(&tail, eocd + 10) as u64;
+    let mut dir_len = u32_at(&tail, eocd + 12) as u64;
+    let mut dir_offset = u32_at(&tail, eocd + 16) as u64;
+    // Large archives keep the real values in a zip64 record, located just
+    // before the classic one.
+    if eocd >= 20 && tail[eocd - 20..].starts_with(b"PK\x06\x07") {
+        let record = read_at(reader, u64_at(&tail, eocd - 20 + 8), 56)?;
+        if !record.starts_with(b"PK\x06\x06") {
+            return Err(ReadError::format("damaged zip64 directory"));
+        }
+        entries = u64_at(&record, 32);
+        dir_len = u64_at(&record, 40);
+        dir_offset = u64_at(&record, 48);
+    }
+    if dir_offset.saturating_add(dir_len) > file_len {
+        return Err(ReadError::format("zip directory past the end of the file"));
+    }
+    let dir = read_at(reader, dir_offset, dir_len as usize)?;
+
+    let mut at = 0;
+    for _ in 0..entries {
+        if dir.len() < at + 46 || !dir[at..].starts_with(b"PK\x01\x02") {
+            return Err(ReadError::format("damaged zip directory"));
+        }
+        let method = u16_at(&dir, at + 10);
+        let mut compressed_len = u32_at(&dir, at + 20) as u64;
+        let name_len = u16_at(&dir, at + 28) as usize;
+        let extra_len = u16_at(&dir, at + 30) as usize;
+        let comment_len = u16_at(&dir, at + 32) as usize;
+        let mut header_offset = u32_at(&dir, at + 42) as u64;
+        let end = at + 46 + name_len + extra_len + comment_len;
+        if dir.len() < end {
+            return Err(ReadError::format("damaged zip directory"));
+        }
+        let name = &dir[at + 46..at + 46 + name_len];
+        if name.ends_with(b"/data.pkl") || name == b"data.pkl" {
+            let extra = &dir[at + 46 + name_len..at + 46 + name_len + extra_len];
+            zip64_sizes(extra, &dir[at..], &mut compressed_len, &mut header_offset);
+            if compressed_len > MAX_PICKLE_LEN {
+                return Err(ReadError::format("data.pkl is too large"));
+            }
+            let header = read_at(reader, header_offset, 30)?;
+            if !header.starts_with(b"PK\x03\x04") {
+                return Err(ReadError::format("damaged zip entry"));
+            }
+            let data_offset = header_offset + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+            let data = read_at(reader, data_offset, compressed_len as usize)?;
+            return match method {
+                0 => Ok(data),
+                8 => inflate::inflate_raw(&data, MAX_PICKLE_LEN as usize)
+                    .ok_or_else(|| ReadError::format("data.pkl does not decompress or is too large")),
+                _ => Err(ReadError::format(format!("data.pkl uses unsupported compression {}", method))),
+            };
+        }
+        at = end;
+    }
+    Err(ReadError::format("no data.pkl entry; the archive is not a PyTorch checkpoint"))
+}
+
+/// Applies the zip64 extra field of a central directory entry, which holds
+/// the fields whose 32-bit slots are saturated, in a fixed order.
+fn zip64_sizes(mut extra: &[u8], entry: &[u8], compressed_len: &mut u64, header_offset: &mut u64) {
+    while extra.len() >= 4 {
+        let (id, len) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+        let Some(field) = extra.get(4..4 + len) else {
+            return;
+        };
+        if id == 1 {
+            let mut values = field.chunks_exact(8).map(|value| u64_at(value, 0));
+            if u32_at(entry, 24) == u32::MAX {
+                values.next();
+            }
+            if *compressed_len == u32::MAX as u64 {
+                *compressed_len = values.next().unwrap_or(*compressed_len);
+            }
+            if *header_offset == u32::MAX as u64 {
+                *header_offset = values.next().unwrap_or(*header_offset);
+            }
+            return;
+        }
+        extra = &extra[4 + len..];
+    }
+}
+
+/// A value on the pickle stack.
+///
+/// Lists and dicts are plain values, so a memo entry does not see items added
+/// after it was stored. torch never reads a container back from the memo, so
+/// the table of contents is unaffected.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// `module.name`.
+    Global(String),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+    /// The storage a persistent id refers to: torch's storage class name.
+    Storage(String),
+    Tensor { dtype: String, shape: Vec<u64> },
+    /// The result of calling anything else; its contents are not needed.
+    Object,
+}
+
+impl Value {
+    /// Levels of tuples, lists and dicts in the value; 0 for anything else.
+    fn depth(&self) -> usize {
+        match self {
+            Value::Tuple(items) | Value::List(items) => 1 + items.iter().map(Value::depth).max().unwrap_or(0),
+            Value::Dict(entries) => {
+                1 + entries
+                    .iter()
+                    .map(|(key, value)| key.depth().max(value.depth()))
+                    .max()
+                    .unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Rough memory use of the value, counting what it holds.
+    fn footprint(&self) -> usize {
+        let held = match self {
+            Value::String(text) | Value::Global(text) | Value::Storage(text) => text.len(),
+            Value::Bytes(bytes) => bytes.len(),
+            Value::Tuple(items) | Value::List(items) => items.iter().map(Value::footprint).sum(),
+            Value::Dict(entries) => entries.iter().map(|(key, value)| key.footprint() + value.footprint()).sum(),
+            Value::Tensor { dtype, shape } => dtype.len() + shape.len() * 8,
+            _ => 0,
+        };
+        std::mem::size_of::<Value>() + held
+    }
+}
+
+struct Machine<'a> {
+    data: &'a [u8],
+    pos: usize,
+    stack: Vec<Value>,
+    /// Stack lengths at each MARK.
+    marks: Vec<usize>,
+    memo: HashMap<u32, Value>,
+    /// Bytes copied by DUP and the memo opcodes so far.
+    copied: usize,
+}
+
+impl<'a> Machine<'a> {
+    fn new(data: &'a [u8]) -> Machine<'a> {
+        Machine {
+            data,
+            pos: 0,
+            stack: Vec::new(),
+            marks: Vec::new(),
+            memo: HashMap::new(),
+            copied: 0,
+        }
+    }
+
+    fn error(&self, message: &str) -> ReadError {
+        ReadError::format(format!("data.pkl: {} at byte {}", message, self.pos))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ReadError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, ReadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ReadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ReadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn line(&mut self) -> Result<String, ReadError> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or_else(|| self.error("unterminated line"))?;
+        let line = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.pos += 1;
+        Ok(line)
+    }
+
+    fn utf8(&mut self, len: u64) -> Result<Value, ReadError> {
+        let bytes = self.take(usize::try_from(len).unwrap_or(usize::MAX))?;
+        let text = std::str::from_utf8(bytes).map_err(|_| self.error("string is not UTF-8"))?;
+        Ok(Value::String(text.to_string()))
+    }
+
+    fn pop(&mut self) -> Result<Value, ReadError> {
+        self.stack.pop().ok_or_else(|| self.error("stack underflow"))
+    }
+
+    fn top(&mut self) -> Result<&mut Value, ReadError> {
+        let pos = self.pos;
+        self.stack
+            .last_mut()
+            .ok_or_else(|| ReadError::format(format!("data.pkl: stack underflow at byte {}", pos)))
+    }
+
+    /// Pops everything pushed since the last MARK.
+    fn pop_mark(&mut self) -> Result<Vec<Value>, ReadError> {
+        let mark = self.marks.pop().ok_or_else(|| self.error("no MARK"))?;
+        if mark > self.stack.len() {
+            return Err(self.error("stack underflow"));
+        }
+        Ok(self.stack.split_off(mark))
+    }
+
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, ReadError> {
+        if self.stack.len() < n {
+            return Err(self.error("stack underflow"));
+        }
+        Ok(self.stack.split_off(self.stack.len() - n))
+    }
+
+    /// Counts `len` more copied bytes, failing once the pickle has copied
+    /// too much.
+    fn charge(&mut self, len: usize) -> Result<(), ReadError> {
+        self.copied = self.copied.saturating_add(len);
+        if self.copied > MAX_COPIED_LEN {
+            return Err(self.error("too much data copied by DUP or the memo"));
+        }
+        Ok(())
+    }
+
+    /// Copies the top of the stack.
+    fn copy_top(&mut self) -> Result<Value, ReadError> {
+        let len = self.top()?.footprint();
+        self.charge(len)?;
+        Ok(self.top()?.clone())
+    }
+
+    fn memo_get(&mut self, key: u32) -> Result<Value, ReadError> {
+        let len = self.memo.get(&key).ok_or_else(|| self.error("unknown memo key"))?.footprint();
+        self.charge(len)?;
+        Ok(self.memo[&key].clone())
+    }
+
+    /// Checks that `items` can go into a container without nesting too deep.
+    fn nest(&self, items: Vec<Value>) -> Result<Vec<Value>, ReadError> {
+        if items.iter().any(|item| item.depth() >= MAX_DEPTH) {
+            return Err(self.error("containers nested too deep"));
+        }
+        Ok(items)
+    }
+
+    fn set_items(&mut self, items: Vec<Value>) -> Result<(), ReadError> {
+        let pos = self.pos;
+        let items = self.nest(items)?;
+        let Value::Dict(entries) = self.top()? else {
+            // SETITEMS on another object sets its state; nothing to keep.
+            return Ok(());
+        };
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            entries.push((key, value));
+        }
+        if items.next().is_some() {
+            return Err(ReadError::format(format!("data.pkl: odd SETITEMS at byte {}", pos)));
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, items: Vec<Value>) -> Result<(), ReadError> {
+        let items = self.nest(items)?;
+        if let Value::List(list) = self.top()? {
+            list.extend(items);
+        }
+        Ok(())
+    }
+
+    /// Runs the pickle to its STOP and returns the unpickled value.
+    fn run(mut self) -> Result<Value, ReadError> {
+        loop {
+            let opcode = self.byte()?;
+            let value = match opcode {
+                0x80 => {
+                    // PROTO
+                    self.byte()?;
+                    continue;
+                }
+                0x95 => {
+                    // FRAME
+                    self.u64()?;
+                    continue;
+                }
+                b'(' => {
+                    self.marks.push(self.stack.len());
+                    continue;
+                }
+                b'.' => return self.pop(),
+                b'0' => {
+                    self.pop()?;
+                    continue;
+                }
+                b'1' => {
+                    self.pop_mark()?;
+                    continue;
+                }
+                b'2' => self.copy_top()?,
+                b'N' => Value::None,
+                0x88 => Value::Bool(true),
+                0x89 => Value::Bool(false),
+                b'}' => Value::Dict(Vec::new()),
+                b']' => Value::List(Vec::new()),
+                b')' => Value::Tuple(Vec::new()),
+                b'J' => Value::Int(self.u32()? as i32 as i64),
+                b'K' => Value::Int(self.byte()? as i64),
+                b'M' => Value::Int(u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as i64),
+                0x8a => {
+                    // LONG1: little-endian two's complement.
+                    let len = self.byte()? as usize;
+                    let bytes = self.take(len)?;
+                    if len > 8 {
+                        Value::Object
+                    } else {
+                        let mut value = 0i64;
+                        for (i, &byte) in bytes.iter().enumerate() {
+                            value |= (byte as i64) << (8 * i);
+                        }
+                        if len > 0 && len < 8 && bytes[len - 1] & 0x80 != 0 {
+                            value -= 1 << (8 * len);
+                        }
+                        Value::Int(value)
+                    }
+                }
+                b'G' => Value::Float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+                b'X' => {
+                    let len = self.u32()? as u64;
+                    self.utf8(len)?
+                }
+                0x8c => {
+                    let len = self.byte()? as u64;
+                    self.utf8(len)?
+                }
+                0x8d => {
+                    let len = self.u64()?;
+                    self.utf8(len)?
+                }
+                b'T' | b'U' => {
+                    // Python 2 strings, read as Latin-1.
+                    let len = if opcode == b'T' { self.u32()? as usize } else { self.byte()? as usize };
+                    Value::String(self.take(len)?.iter().map(|&byte| byte as char).collect())
+                }
+                b'B' | b'C' | 0x8e => {
+                    let len = match opcode {
+                        b'B' => self.u32()? as usize,
+                        b'C' => self.byte()? as usize,
+                        _ => usize::try_from(self.u64()?).unwrap_or(usize::MAX),
+                    };
+                    Value::Bytes(self.take(len)?.to_vec())
+                }
+                b'c' => {
+                    let module = self.line()?;
+                    let name = self.line()?;
+                    Value::Global(format!("{}.{}", module, name))
+                }
+                0x93 => {
+                    // STACK_GLOBAL
+                    let (name, module) = (self.pop()?, self.pop()?);
+                    let (Value::String(module), Value::String(name)) = (module, name) else {
+                        return Err(self.error("STACK_GLOBAL needs two strings"));
+                    };
+                    Value::Global(format!("{}.{}", module, name))
+                }
+                b'q' | b'r' | 0x94 => {
+                    let key = match opcode {
+                        b'q' => self.byte()? as u32,
+                        b'r' => self.u32()?,
+                        _ => self.memo.len() as u32,
+                    };
+                    let value = self.copy_top()?;
+                    self.memo.insert(key, value);
+                    continue;
+                }
+                b'h' => {
+                    let key = self.byte()? as u32;
+                    self.memo_get(key)?
+                }
+                b'j' => {
+                    let key = self.u32()?;
+                    self.memo_get(key)?
+                }
+                b't' => {
+                    let items = self.pop_mark()?;
+                    Value::Tuple(self.nest(items)?)
+                }
+                0x85..=0x87 => {
+                    let items = self.pop_n((opcode - 0x84) as usize)?;
+                    Value::Tuple(self.nest(items)?)
+                }
+                b'l' => {
+                    let items = self.pop_mark()?;
+                    Value::List(self.nest(items)?)
+                }
+                b'd' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(Value::Dict(Vec::new()));
+                    self.set_items(items)?;
+                    continue;
+                }
+                b's' => {
+                    let items = self.pop_n(2)?;
+                    self.set_items(items)?;
+                    continue;
+                }
+                b'u' => {
+                    let items = self.pop_mark()?;
+                    self.set_items(items)?;
+                    continue;
+                }
+                b'a' => {
+                    let item = self.pop()?;
+                    self.append(vec![item])?;
+                    continue;
+                }
+                b'e' => {
+                    let items = self.pop_mark()?;
+                    self.append(items)?;
+                    continue;
+                }
+                b'Q' => persistent_load(self.pop()?),
+                b'R' => {
+                    let args = self.pop()?;
+                    let callable = self.pop()?;
+                    reduce(callable, args)
+                }
+                0x81 => {
+                    // NEWOBJ
+                    let args = self.pop()?;
+                    let class = self.pop()?;
+                    reduce(class, args)
+                }
+                b'b' => {
+                    // BUILD sets state on the object below; torch only uses
+                    // it for attributes that do not matter here.
+                    self.pop()?;
+                    continue;
+                }
+                _ => return Err(self.error(&format!("unsupported opcode 0x{:02x}", opcode))),
+            };
+            self.stack.push(value);
+        }
+    }
+}
+
+/// Resolves torch's persistent id `("storage", class, key, location, numel)`.
+fn persistent_load(pid: Value) -> Value {
+    match pid {
+        Value::Tuple(fields) => match fields.as_slice() {
+            [Value::String(kind), Value::Global(class), ..] if kind == "storage" => {
+                Value::Storage(class.rsplit('.').next().unwrap_or_default().to_string())
+            }
+            _ => Value::Object,
+        },
+        _ => Value::Object,
+    }
+}
+
+/// Evaluates the few calls a state dict pickle makes.
+fn reduce(callable: Value, args: Value) -> Value {
+    let Value::Global(name) = callable else {
+        return Value::Object;
+    };
+    let Value::Tuple(args) = args else {
+        return Value::Object;
+    };
+    match name.as_str() {
+        "torch._utils._rebuild_tensor_v2" | "torch._utils._rebuild_tensor" => {
+            match args.as_slice() {
+                [Value::Storage(class), _, Value::Tuple(shape), ..] => {
+                    let dims = shape
+                        .iter()
+                        .map(|dim| match dim {
+                            Value::Int(dim) => u64::try_from(*dim).ok(),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    match dims {
+                        Some(shape) => Value::Tensor {
+                            dtype: class.clone(),
+                            shape,
+                        },
+                        None => Value::Object,
+                    }
+                }
+                _ => Value::Object,
+            }
+        }
+        // The tensor a parameter wraps.
+        "torch._utils._rebuild_parameter" => match args.into_iter().next() {
+            Some(tensor @ Value::Tensor { .. }) => tensor,
+            _ => Value::Object,
+        },
+        "collections.OrderedDict" => Value::Dict(Vec::new()),
+        _ => Value::Object,
+    }
+}
+
+/// torch's name for the dtype of a storage class, and how the host loads it.
+fn storage_dtype(class: &str) -> (&str, Option<SdTypeT>) {
+    match class {
+        "FloatStorage" => ("float32", Some(SdTypeT::SdTypeF32)),
+        "HalfStorage" => ("float16", Some(SdTypeT::SdTypeF16)),
+        "BFloat16Storage" => ("bfloat16", Some(SdTypeT::SdTypeBf16)),
+        "DoubleStorage" => ("float64", Some(SdTypeT::SdTypeF64)),
+        "LongStorage" => ("int64", Some(SdTypeT::SdTypeI64)),
+        "IntStorage" => ("int32", Some(SdTypeT::SdTypeI32)),
+        "ShortStorage" => ("int16", Some(SdTypeT::SdTypeI16)),
+        "CharStorage" => ("int8", Some(SdTypeT::SdTypeI8)),
+        "ByteStorage" => ("uint8", None),
+        "BoolStorage" => ("bool", None),
+        other => (other, None),
+    }
+}
+
+/// Collects the tensors of `value`, naming nested entries `outer.inner`.
+/// A top-level `state_dict` wrapper adds no prefix.
+fn collect(value: &Value, prefix: &str, out: &mut Vec<TensorEntry>) {
+    let Value::Dict(entries) = value else {
+        return;
+    };
+    for (key, value) in entries {
+        let key = match key {
+            Value::String(key) => key.clone(),
+            Value::Int(key) => key.to_string(),
+            _ => continue,
+        };
+        let name = match prefix {
+            "" => key,
+            _ => format!("{}.{}", prefix, key),
+        };
+        match value {
+            Value::Tensor { dtype, shape } => {
+                let (dtype, sd_type) = storage_dtype(dtype);
+                out.push(TensorEntry {
+                    name,
+                    dtype: dtype.to_string(),
+                    sd_type,
+                    shape: shape.clone(),
+                });
+            }
+            Value::Dict(_) if prefix.is_empty() && name == "state_dict" => collect(value, "", out),
+            Value::Dict(_) => collect(value, &name, out),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Pickles `{"state_dict": OrderedDict(name: tensor), "epoch": 3}` the
+    /// way torch does, for (name, storage class, shape) triples.
+    fn pickle(tensors: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, text: &str) {
+            out.push(0x8c);
+            out.push(text.len() as u8);
+            out.extend_from_slice(text.as_bytes());
+        }
+        let mut out = vec![0x80, 2, b'}', b'q', 0, b'('];
+        string(&mut out, "state_dict");
+        out.extend_from_slice(b"ccollections\nOrderedDict\nq\x01)R(");
+        for (key, (name, class, shape)) in tensors.iter().enumerate() {
+            string(&mut out, name);
+            out.extend_from_slice(b"ctorch._utils\n_rebuild_tensor_v2\n(");
+            // Persistent id: ("storage", class, key, location, numel).
+            out.push(b'(');
+            string(&mut out, "storage");
+            out.extend_from_slice(format!("ctorch\n{}\n", class).as_bytes());
+            string(&mut out, &key.to_string());
+            string(&mut out, "cpu");
+            out.extend_from_slice(&[b'K', 4, b't', b'Q', b'K', 0, b'(']);
+            for &dim in *shape {
+                out.extend_from_slice(&[b'K', dim]);
+            }
+            // Stride, requires_grad and backward hooks.
+            out.extend_from_slice(b"t)\x89h\x01)Rtr\x10\x00\x00\x00R");
+        }
+        out.push(b'u');
+        string(&mut out, "epoch");
+        out.extend_from_slice(&[b'K', 3, b'u', b'.']);
+        out
+    }
+
+    /// A zip holding `archive/data.pkl`, stored, plus a dummy data entry.
+    fn zip(pickle: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in [("archive/data/0", &b"\0\0\0\0"[..]), ("archive/data.pkl", pickle)] {
+            let offset = out.len() as u32;
+            out.extend_from_slice(b"PK\x03\x04");
+            out.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            directory.extend_from_slice(b"PK\x01\x02");
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let dir_offset = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(b"PK\x05\x06\0\0\0\0\x02\0\x02\0");
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&dir_offset.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    fn message(err: ReadError) -> String {
+        match err {
+            ReadError::Format(message) => message,
+            ReadError::Io(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_the_state_dict() {
+        let data = zip(&pickle(&[
+            ("model.diffusion_model.input_blocks.0.0.weight", "HalfStorage", &[64, 4, 3, 3]),
+            ("cond_stage_model.transformer.text_model.embeddings.position_ids", "LongStorage", &[1, 77]),
+            ("mask", "BoolStorage", &[2]),
+        ]));
+        let info = read(Cursor::new(data)).unwrap();
+        assert_eq!(info.format, ModelFormat::Ckpt);
+        let tensors: Vec<_> = info
+            .tensors
+            .iter()
+            .map(|tensor| (tensor.name.as_str(), tensor.dtype.as_str(), tensor.sd_type, tensor.shape.as_slice()))
+            .collect();
+        assert_eq!(
+            tensors,
+            [
+                (
+                    "model.diffusion_model.input_blocks.0.0.weight",
+                    "float16",
+                    Some(SdTypeT::SdTypeF16),
+                    &[64, 4, 3, 3][..]
+                ),
+                (
+                    "cond_stage_model.transformer.text_model.embeddings.position_ids",
+                    "int64",
+                    Some(SdTypeT::SdTypeI64),
+                    &[1, 77][..]
+                ),
+                ("mask", "bool", None, &[2][..]),
+            ]
+        );
+        assert_eq!(info.model_version(), crate::model_file::ModelVersion::Sd1);
+    }
+
+    #[test]
+    fn rejects_other_archives() {
+        let mut data = zip(b"\x80\x02}q\x00.");
+        let name = data.windows(8).rposition(|window| window == b"data.pkl").unwrap();
+        data[name..name + 8].copy_from_slice(b"data.txt");
+        assert_eq!(
+            message(read(Cursor::new(data)).unwrap_err()),
+            "no data.pkl entry; the archive is not a PyTorch checkpoint"
+        );
+        let data = zip(&pickle(&[("w", "FloatStorage", &[2])]));
+        assert!(message(read(Cursor::new(&data[..data.len() - 30])).unwrap_err()).starts_with("no zip directory"));
+        let err = message(read(Cursor::new(zip(b"\x80\x02\xff."))).unwrap_err());
+        assert!(err.starts_with("data.pkl: unsupported opcode 0xff"), "{}", err);
+        let err = message(read(Cursor::new(zip(b"\x80\x02}q\x00"))).unwrap_err());
+        assert!(err.starts_with("data.pkl: unexpected end"), "{}", err);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        fn nested(levels: usize) -> Vec<u8> {
+            let mut pickle = vec![0x80, 2];
+            pickle.resize(pickle.len() + levels, b']');
+            pickle.resize(pickle.len() + levels - 1, b'a');
+            pickle.push(b'.');
+            pickle
+        }
+        let value = Machine::new(&nested(MAX_DEPTH)).run().unwrap();
+        assert_eq!(value.depth(), MAX_DEPTH);
+        let err = message(Machine::new(&nested(100_000)).run().unwrap_err());
+        assert!(err.starts_with("data.pkl: containers nested too deep"), "{}", err);
+    }
+
+    #[test]
+    fn limits_copies() {
+        let len = 1 << 20;
+        let mut pickle = vec![0x80, 2, b'B'];
+        pickle.extend_from_slice(&(len as u32).to_le_bytes());
+        pickle.resize(pickle.len() + len, 0);
+        for _ in 0..MAX_COPIED_LEN / len {
+            pickle.extend_from_slice(b"20");
+        }
+        pickle.push(b'.');
+        let err = message(Machine::new(&pickle).run().unwrap_err());
+        assert!(err.starts_with("data.pkl: too much data copied"), "{}", err);
+    }
+}
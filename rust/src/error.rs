@@ -0,0 +1,237 @@
+//! Errors returned by the public API.
+use crate::stable_diffusion_interface::WasmedgeSdErrno;
+use crate::validation::Violation;
+use core::fmt;
+use std::error::Error;
+
+/// Error code reported by the host, decoded from [`WasmedgeSdErrno`].
+///
+/// Codes added to the plugin after this crate was written end up in
+/// `Unknown` instead of being misread.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HostErrno {
+    InvalidArgument,
+    InvalidEncoding,
+    MissingMemory,
+    Busy,
+    RuntimeError,
+    Unknown(u32),
+}
+
+impl HostErrno {
+    pub fn raw(&self) -> u32 {
+        match self {
+            HostErrno::InvalidArgument => 1,
+            HostErrno::InvalidEncoding => 2,
+            HostErrno::MissingMemory => 3,
+            HostErrno::Busy => 4,
+            HostErrno::RuntimeError => 5,
+            HostErrno::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<WasmedgeSdErrno> for HostErrno {
+    fn from(errno: WasmedgeSdErrno) -> Self {
+        match errno.raw() {
+            1 => HostErrno::InvalidArgument,
+            2 => HostErrno::InvalidEncoding,
+            3 => HostErrno::MissingMemory,
+            4 => HostErrno::Busy,
+            5 => HostErrno::RuntimeError,
+            code => HostErrno::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for HostErrno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errno = WasmedgeSdErrno::from_raw(self.raw());
+        write!(f, "{} (error {}): {}", errno.name(), self.raw(), errno.message())
+    }
+}
+
+/// Host call that failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    CreateContext,
+    FreeContext,
+    TextToImage,
+    ImageToImage,
+    Inpaint,
+    Upscale,
+    Convert,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::CreateContext => "create_context",
+            Operation::FreeContext => "free_context",
+            Operation::TextToImage => "text_to_image",
+            Operation::ImageToImage => "image_to_image",
+            Operation::Inpaint => "inpaint",
+            Operation::Upscale => "upscale",
+            Operation::Convert => "convert",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned by the crate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SdError {
+    /// The host rejected or failed `operation`.
+    Host {
+        operation: Operation,
+        errno: HostErrno,
+    },
+    /// A value was rejected on the guest side before reaching the host.
+    InvalidArgument {
+        field: &'static str,
+        reason: String,
+    },
+    /// Guest-side validation found at least one error. Warnings found in the
+    /// same pass are included.
+    Validation(Vec<Violation>),
+    /// The image did not fit in the output buffer limit.
+    OutputTooLarge {
+        operation: Operation,
+        needed: Option<usize>,
+        limit: usize,
+    },
+    /// Reading or writing a file failed.
+    Io {
+        path: String,
+        message: String,
+    },
+    /// Data could not be parsed as, or written in, `format`.
+    Format {
+        format: &'static str,
+        message: String,
+    },
+    /// The host has no way to run `operation`, for the reason given.
+    Unsupported {
+        operation: Operation,
+        reason: &'static str,
+    },
+}
+
+impl SdError {
+    pub fn host(operation: Operation, errno: WasmedgeSdErrno) -> SdError {
+        SdError::Host {
+            operation,
+            errno: errno.into(),
+        }
+    }
+
+    pub fn invalid(field: &'static str, reason: impl Into<String>) -> SdError {
+        SdError::InvalidArgument {
+            field,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn io(path: impl AsRef<std::path::Path>, err: std::io::Error) -> SdError {
+        SdError::Io {
+            path: path.as_ref().display().to_string(),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn format(format: &'static str, message: impl ToString) -> SdError {
+        SdError::Format {
+            format,
+            message: message.to_string(),
+        }
+    }
+
+    /// Host error code, if the error came from the host.
+    pub fn errno(&self) -> Option<HostErrno> {
+        match self {
+            SdError::Host { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SdError::Host { operation, errno } => write!(f, "{} failed: {}", operation, errno),
+            SdError::InvalidArgument { field, reason } => {
+                write!(f, "invalid {}: {}", field, reason)
+            }
+            SdError::Validation(violations) => {
+                f.write_str("invalid generation parameters")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    f.write_str(if i == 0 { ": " } else { "; " })?;
+                    write!(f, "{}", violation)?;
+                }
+                Ok(())
+            }
+            SdError::OutputTooLarge {
+                operation,
+                needed: Some(needed),
+                limit,
+            } => write!(
+                f,
+                "{} output needs {} bytes, more than the {} byte buffer limit",
+                operation, needed, limit
+            ),
+            SdError::OutputTooLarge {
+                operation,
+                needed: None,
+                limit,
+            } => write!(
+                f,
+                "{} output does not fit in the {} byte buffer limit",
+                operation, limit
+            ),
+            SdError::Io { path, message } => write!(f, "{}: {}", path, message),
+            SdError::Format { format, message } => write!(f, "invalid {}: {}", format, message),
+            SdError::Unsupported { operation, reason } => write!(f, "{} is not supported: {}", operation, reason),
+        }
+    }
+}
+
+impl Error for SdError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stable_diffusion_interface::*;
+
+    #[test]
+    fn unknown_codes_are_kept() {
+        let errno = WasmedgeSdErrno::from_raw(42);
+        assert_eq!(errno.name(), "UNKNOWN");
+        assert_eq!(HostErrno::from(errno), HostErrno::Unknown(42));
+        assert_eq!(HostErrno::Unknown(42).raw(), 42);
+    }
+
+    #[test]
+    fn messages_name_the_operation_and_field() {
+        let err = SdError::host(Operation::TextToImage, WASMEDGE_SD_ERRNO_BUSY);
+        assert_eq!(
+            err.to_string(),
+            "text_to_image failed: BUSY (error 4): the host is busy with another request"
+        );
+        assert_eq!(err.errno(), Some(HostErrno::Busy));
+        assert_eq!(
+            SdError::invalid("prompt", "must not be empty").to_string(),
+            "invalid prompt: must not be empty"
+        );
+        let err = SdError::Unsupported {
+            operation: Operation::FreeContext,
+            reason: "needs the `host-v2` feature",
+        };
+        assert_eq!(err.to_string(), "free_context is not supported: needs the `host-v2` feature");
+        assert_eq!(err.errno(), None);
+    }
+}
@@ -0,0 +1,408 @@
+//! Reads what a `.gguf` model file contains without loading its weights.
+//!
+//! Only the header, the metadata and the tensor info table are parsed, so
+//! inspecting a multi-gigabyte model reads a few hundred kilobytes. Tensor
+//! types are the ggml type ids, which [`SdTypeT`] mirrors.
+//!
+//! See <https://github.com/ggerganov/ggml/blob/master/docs/gguf.md> for the
+//! format.
+use crate::model_file::block_layout;
+pub use crate::model_file::ModelVersion;
+use crate::stable_diffusion_interface::SdTypeT;
+use crate::SdError;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// The four bytes every GGUF file starts with.
+pub const MAGIC: [u8; 4] = *b"GGUF";
+/// Tensor data alignment when the file does not set `general.alignment`.
+pub const DEFAULT_ALIGNMENT: u64 = 32;
+
+/// Header, metadata and tensor table of a GGUF file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GgufInfo {
+    pub version: u32,
+    /// Metadata key-value pairs, in file order.
+    pub metadata: Vec<(String, MetadataValue)>,
+    pub tensors: Vec<TensorInfo>,
+}
+
+/// A metadata value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<MetadataValue>),
+}
+
+impl MetadataValue {
+    /// The value as an unsigned integer, if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            MetadataValue::U8(value) => Some(value as u64),
+            MetadataValue::U16(value) => Some(value as u64),
+            MetadataValue::U32(value) => Some(value as u64),
+            MetadataValue::U64(value) => Some(value),
+            MetadataValue::I8(value) => u64::try_from(value).ok(),
+            MetadataValue::I16(value) => u64::try_from(value).ok(),
+            MetadataValue::I32(value) => u64::try_from(value).ok(),
+            MetadataValue::I64(value) => u64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MetadataValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of the tensor table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    /// Dimensions, innermost first.
+    pub dims: Vec<u64>,
+    /// ggml type id.
+    pub ggml_type: u32,
+    /// Offset of the data from the start of the tensor data section.
+    pub offset: u64,
+}
+
+impl TensorInfo {
+    /// The tensor type, or `None` for a type id this crate does not know.
+    pub fn sd_type(&self) -> Option<SdTypeT> {
+        match SdTypeT::from_index(self.ggml_type as usize) {
+            Ok(SdTypeT::SdTypeCount) | Err(_) => None,
+            Ok(sd_type) => Some(sd_type),
+        }
+    }
+
+    /// Number of elements, or `None` if it does not fit in a `u64`.
+    pub fn elements(&self) -> Option<u64> {
+        self.dims.iter().try_fold(1u64, |n, &dim| n.checked_mul(dim))
+    }
+
+    /// Size of the tensor data in bytes, or `None` for an unknown type or a
+    /// size that does not fit in a `u64`.
+    pub fn size(&self) -> Option<u64> {
+        let (block_elements, block_bytes) = block_layout(self.sd_type()?);
+        self.elements()?.div_ceil(block_elements).checked_mul(block_bytes)
+    }
+}
+
+impl GgufInfo {
+    /// Reads the header and tensor table of the file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<GgufInfo, SdError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|err| SdError::io(path, err))?;
+        GgufInfo::parse(BufReader::new(file)).map_err(|err| match err {
+            GgufError::Io(err) => SdError::io(path, err),
+            GgufError::Format(message) => SdError::format("GGUF", message),
+        })
+    }
+
+    /// Parses the header and tensor table from the start of a GGUF stream.
+    /// Reading stops before the tensor data.
+    pub fn from_reader(reader: impl Read) -> Result<GgufInfo, SdError> {
+        GgufInfo::parse(reader).map_err(|err| match err {
+            GgufError::Io(err) => SdError::format("GGUF", err),
+            GgufError::Format(message) => SdError::format("GGUF", message),
+        })
+    }
+
+    fn parse(reader: impl Read) -> Result<GgufInfo, GgufError> {
+        let mut reader = Reader {
+            inner: reader,
+            wide: true,
+        };
+        if reader.array::<4>()? != MAGIC {
+            return Err(GgufError::Format("not a GGUF file".to_string()));
+        }
+        let version = reader.u32()?;
+        if !(1..=3).contains(&version) {
+            return Err(GgufError::Format(format!("unsupported version {}", version)));
+        }
+        // Version 1 stored counts and lengths as u32.
+        reader.wide = version >= 2;
+        let tensor_count = reader.count()?;
+        let metadata_count = reader.count()?;
+        let mut metadata = Vec::new();
+        for _ in 0..metadata_count {
+            let key = reader.string()?;
+            let value_type = reader.u32()?;
+            metadata.push((key, reader.value(value_type, 0)?));
+        }
+        let mut tensors = Vec::new();
+        for _ in 0..tensor_count {
+            let name = reader.string()?;
+            let n_dims = reader.u32()?;
+            if n_dims > 4 {
+                return Err(GgufError::Format(format!("tensor {} has {} dimensions", name, n_dims)));
+            }
+            let dims = (0..n_dims).map(|_| reader.count()).collect::<Result<_, _>>()?;
+            let ggml_type = reader.u32()?;
+            let offset = reader.u64()?;
+            tensors.push(TensorInfo {
+                name,
+                dims,
+                ggml_type,
+                offset,
+            });
+        }
+        Ok(GgufInfo {
+            version,
+            metadata,
+            tensors,
+        })
+    }
+
+    /// The metadata value stored under `key`.
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.metadata.iter().find(|(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    /// Tensor data alignment, from `general.alignment`.
+    pub fn alignment(&self) -> u64 {
+        self.get("general.alignment")
+            .and_then(MetadataValue::as_u64)
+            .filter(|&alignment| alignment > 0)
+            .unwrap_or(DEFAULT_ALIGNMENT)
+    }
+
+    pub fn model_version(&self) -> ModelVersion {
+        ModelVersion::detect(self.tensors.iter().map(|tensor| tensor.name.as_str()))
+    }
+
+    /// Whether the file carries its own VAE weights.
+    pub fn has_vae(&self) -> bool {
+        self.tensors.iter().any(|tensor| {
+            tensor.name.starts_with("first_stage_model.") || tensor.name.starts_with("vae.")
+        })
+    }
+
+    /// Total size of the tensor data in bytes, counting tensors of unknown
+    /// type or impossible size as empty.
+    pub fn total_size(&self) -> u64 {
+        self.tensors
+            .iter()
+            .filter_map(TensorInfo::size)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Number of tensors of each type. Unknown types are counted under
+    /// `None`.
+    pub fn type_counts(&self) -> BTreeMap<Option<&'static str>, usize> {
+        let mut counts = BTreeMap::new();
+        for tensor in &self.tensors {
+            *counts.entry(tensor.sd_type().map(|sd_type| sd_type.name())).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+enum GgufError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl From<std::io::Error> for GgufError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            GgufError::Format("truncated file".to_string())
+        } else {
+            GgufError::Io(err)
+        }
+    }
+}
+
+/// Little-endian reader for the GGUF primitives.
+struct Reader<R> {
+    inner: R,
+    /// Whether counts and lengths are u64 (version 2 and later).
+    wide: bool,
+}
+
+/// Nesting limit for array values.
+const MAX_ARRAY_DEPTH: u32 = 8;
+
+impl<R: Read> Reader<R> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GgufError> {
+        let mut bytes = [0; N];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, GgufError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, GgufError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn count(&mut self) -> Result<u64, GgufError> {
+        if self.wide {
+            self.u64()
+        } else {
+            self.u32().map(u64::from)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, GgufError> {
+        let len = self.count()?;
+        let mut bytes = Vec::new();
+        // `take` keeps a corrupt length from allocating up front.
+        (&mut self.inner).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(GgufError::Format("truncated file".to_string()));
+        }
+        String::from_utf8(bytes).map_err(|_| GgufError::Format("string is not UTF-8".to_string()))
+    }
+
+    /// Reads a value of `value_type`, `depth` arrays deep.
+    fn value(&mut self, value_type: u32, depth: u32) -> Result<MetadataValue, GgufError> {
+        Ok(match value_type {
+            0 => MetadataValue::U8(u8::from_le_bytes(self.array()?)),
+            1 => MetadataValue::I8(i8::from_le_bytes(self.array()?)),
+            2 => MetadataValue::U16(u16::from_le_bytes(self.array()?)),
+            3 => MetadataValue::I16(i16::from_le_bytes(self.array()?)),
+            4 => MetadataValue::U32(self.u32()?),
+            5 => MetadataValue::I32(i32::from_le_bytes(self.array()?)),
+            6 => MetadataValue::F32(f32::from_le_bytes(self.array()?)),
+            7 => MetadataValue::Bool(self.array::<1>()?[0] != 0),
+            8 => MetadataValue::String(self.string()?),
+            9 if depth < MAX_ARRAY_DEPTH => {
+                let item_type = self.u32()?;
+                let len = self.count()?;
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.value(item_type, depth + 1)?);
+                }
+                MetadataValue::Array(items)
+            }
+            10 => MetadataValue::U64(self.u64()?),
+            11 => MetadataValue::I64(i64::from_le_bytes(self.array()?)),
+            12 => MetadataValue::F64(f64::from_le_bytes(self.array()?)),
+            _ => {
+                return Err(GgufError::Format(format!("unknown metadata type {}", value_type)));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a version 3 GGUF header with the given tensors and an alignment
+    /// entry.
+    fn gguf(tensors: &[(&str, &[u64], u32)]) -> Vec<u8> {
+        fn string(out: &mut Vec<u8>, text: &str) {
+            out.extend_from_slice(&(text.len() as u64).to_le_bytes());
+            out.extend_from_slice(text.as_bytes());
+        }
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&3u32.to_le_bytes());
+        out.extend_from_slice(&(tensors.len() as u64).to_le_bytes());
+        out.extend_from_slice(&2u64.to_le_bytes());
+        string(&mut out, "general.alignment");
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(&64u32.to_le_bytes());
+        string(&mut out, "general.tags");
+        out.extend_from_slice(&9u32.to_le_bytes());
+        out.extend_from_slice(&8u32.to_le_bytes());
+        out.extend_from_slice(&2u64.to_le_bytes());
+        string(&mut out, "sd");
+        string(&mut out, "test");
+        let mut offset = 0;
+        for (name, dims, ggml_type) in tensors {
+            string(&mut out, name);
+            out.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+            for dim in *dims {
+                out.extend_from_slice(&dim.to_le_bytes());
+            }
+            out.extend_from_slice(&ggml_type.to_le_bytes());
+            out.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += 64;
+        }
+        out
+    }
+
+    #[test]
+    fn reads_the_tensor_table() {
+        let data = gguf(&[
+            ("model.diffusion_model.input_blocks.0.0.weight", &[3, 3, 4, 320], 8),
+            ("cond_stage_model.transformer.text_model.embeddings", &[768, 49408], 1),
+            ("first_stage_model.decoder.conv_in.weight", &[3, 3, 4, 512], 0),
+        ]);
+        let info = GgufInfo::from_reader(&data[..]).unwrap();
+        assert_eq!(info.version, 3);
+        assert_eq!(info.alignment(), 64);
+        assert_eq!(
+            info.get("general.tags"),
+            Some(&MetadataValue::Array(vec![
+                MetadataValue::String("sd".to_string()),
+                MetadataValue::String("test".to_string()),
+            ]))
+        );
+        assert_eq!(info.tensors.len(), 3);
+        assert_eq!(info.tensors[0].sd_type(), Some(SdTypeT::SdTypeQ8_0));
+        assert_eq!(info.tensors[0].elements(), Some(11520));
+        assert_eq!(info.tensors[0].size(), Some(11520 / 32 * 34));
+        assert_eq!(info.tensors[1].size(), Some(768 * 49408 * 2));
+        assert_eq!(info.total_size(), 11520 / 32 * 34 + 768 * 49408 * 2 + 3 * 3 * 4 * 512 * 4);
+        assert_eq!(info.model_version(), ModelVersion::Sd1);
+        assert!(info.has_vae());
+        assert_eq!(
+            info.type_counts().into_iter().collect::<Vec<_>>(),
+            [(Some("f16"), 1), (Some("f32"), 1), (Some("q8_0"), 1)]
+        );
+    }
+
+    #[test]
+    fn unknown_types_have_no_size() {
+        let info = GgufInfo::from_reader(&gguf(&[("t", &[4], 4), ("u", &[4], 99)])[..]).unwrap();
+        assert_eq!(info.tensors[0].sd_type(), None);
+        assert_eq!(info.tensors[1].size(), None);
+        assert_eq!(info.total_size(), 0);
+        assert_eq!(info.type_counts().into_iter().collect::<Vec<_>>(), [(None, 2)]);
+    }
+
+    #[test]
+    fn overflowing_shapes_have_no_size() {
+        let data = gguf(&[("t", &[u64::MAX, 2], 0), ("u", &[1 << 62, 1], 0), ("v", &[4], 0)]);
+        let info = GgufInfo::from_reader(&data[..]).unwrap();
+        assert_eq!(info.tensors[0].elements(), None);
+        assert_eq!(info.tensors[0].size(), None);
+        assert_eq!(info.tensors[1].elements(), Some(1 << 62));
+        assert_eq!(info.tensors[1].size(), None);
+        assert_eq!(info.total_size(), 16);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let data = gguf(&[("t", &[4], 0)]);
+        let err = GgufInfo::from_reader(&data[..data.len() - 3]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid GGUF: truncated file");
+        let err = GgufInfo::from_reader(&b"GGML\x01\0\0\0"[..]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid GGUF: not a GGUF file");
+        assert!(matches!(
+            GgufInfo::read("/nonexistent/model.gguf"),
+            Err(SdError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+//! Reads the pixel size of an encoded image from its header, without decoding
+//! it.
+
+/// Width and height of a PNG or JPEG, or `None` for other or truncated data.
+pub(crate) fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(&crate::png::SIGNATURE) {
+        png_dimensions(data)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        jpeg_dimensions(data)
+    } else {
+        None
+    }
+}
+
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let (kind, ihdr) = crate::png::chunks(data)?.next()?;
+    if &kind != b"IHDR" || ihdr.len() < 8 {
+        return None;
+    }
+    let width = u32::from_be_bytes(ihdr[..4].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr[4..8].try_into().unwrap());
+    Some((width, height))
+}
+
+/// Walks the JPEG segments up to the first start-of-frame marker.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        // Markers may be padded with any number of 0xff bytes.
+        while *data.get(pos)? == 0xff {
+            pos += 1;
+        }
+        let marker = data[pos];
+        pos += 1;
+        if marker == 0x01 || (0xd0..=0xd9).contains(&marker) {
+            continue;
+        }
+        let len = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        // SOF0 to SOF15, except DHT (c4), JPG (c8) and DAC (cc).
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let frame = data.get(pos + 2..pos + 7)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]) as u32;
+            let width = u16::from_be_bytes([frame[3], frame[4]]) as u32;
+            return Some((width, height));
+        }
+        pos += len;
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_png_and_jpeg_headers() {
+        assert_eq!(dimensions(&crate::png::encode(3, 2, 1, &[0; 6])), Some((3, 2)));
+
+        // SOI, an APP0 segment, then SOF0 for a 640x480 image.
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x4a, 0x46];
+        jpeg.extend_from_slice(&[0xff, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xe0, 0x02, 0x80]);
+        assert_eq!(dimensions(&jpeg), Some((640, 480)));
+
+        assert_eq!(dimensions(&jpeg[..12]), None);
+        assert_eq!(dimensions(b"GIF89a"), None);
+    }
+}
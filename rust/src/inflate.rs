@@ -0,0 +1,320 @@
+//! zlib/DEFLATE decompression (RFC 1950/1951), enough to read PNG image data
+//! and zip entries.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a zlib stream, checking its header and Adler-32 checksum.
+/// `None` if the data is malformed or inflates past `max_len` bytes.
+pub(crate) fn zlib_decompress(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let (&cmf, &flg) = (data.first()?, data.get(1)?);
+    let header = (cmf as u16) << 8 | flg as u16;
+    // Deflate only, and no preset dictionary.
+    if cmf & 0x0f != 8 || !header.is_multiple_of(31) || flg & 0x20 != 0 {
+        return None;
+    }
+    let mut bits = Bits::new(&data[2..]);
+    let out = inflate(&mut bits, max_len)?;
+    let end = 2 + bits.byte_pos();
+    let checksum = u32::from_be_bytes(data.get(end..end + 4)?.try_into().unwrap());
+    (checksum == crate::png::adler32(&out)).then_some(out)
+}
+
+/// Decompresses a raw DEFLATE stream, as stored in zip entries. `None` if the
+/// data is malformed or inflates past `max_len` bytes.
+pub(crate) fn inflate_raw(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    inflate(&mut Bits::new(data), max_len)
+}
+
+/// DEFLATE expands up to about 1000:1, so the output is capped at `max_len`
+/// rather than trusting the input size.
+fn inflate(bits: &mut Bits, max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => stored(bits, &mut out, max_len)?,
+            1 => {
+                let (lit, dist) = fixed_tables();
+                codes(bits, &mut out, max_len, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(bits)?;
+                codes(bits, &mut out, max_len, &lit, &dist)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, max_len: usize) -> Option<()> {
+    bits.align();
+    let len = bits.read(16)? as u16;
+    let nlen = bits.read(16)? as u16;
+    if len != !nlen || out.len() + len as usize > max_len {
+        return None;
+    }
+    for _ in 0..len {
+        out.push(bits.read(8)? as u8);
+    }
+    Some(())
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let lit = Huffman::new(&lengths).unwrap();
+    let dist = Huffman::new(&[5; 30]).unwrap();
+    (lit, dist)
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    let lit_count = bits.read(5)? as usize + 257;
+    let dist_count = bits.read(5)? as usize + 1;
+    let code_count = bits.read(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(lit_count + dist_count);
+    while lengths.len() < lit_count + dist_count {
+        let (value, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != lit_count + dist_count || lengths[256] == 0 {
+        return None;
+    }
+    let lit = Huffman::new(&lengths[..lit_count])?;
+    let dist = Huffman::new(&lengths[lit_count..])?;
+    Some((lit, dist))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, max_len: usize, lit: &Huffman, dist: &Huffman) -> Option<()> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        if symbol < 256 {
+            if out.len() >= max_len {
+                return None;
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Some(());
+        }
+        let index = symbol - 257;
+        let len = *LENGTH_BASE.get(index)? as usize + bits.read(LENGTH_EXTRA[index])? as usize;
+        let index = dist.decode(bits)? as usize;
+        let back = *DIST_BASE.get(index)? as usize + bits.read(DIST_EXTRA[index])? as usize;
+        if back > out.len() || out.len() + len > max_len {
+            return None;
+        }
+        // The copy may overlap the bytes it produces.
+        let start = out.len() - back;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    /// Number of codes of each bit length.
+    counts: [u16; 16],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// `None` if the lengths describe an over-subscribed code. Incomplete
+    /// codes are allowed, as zlib does.
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Some(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+/// Reads bits least significant first, as DEFLATE stores them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Bits<'a> {
+        Bits { data, pos: 0, bit: 0 }
+    }
+
+    fn read(&mut self, count: u8) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Some(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    /// Offset of the first byte after the bits read so far.
+    fn byte_pos(&self) -> usize {
+        self.pos + (self.bit != 0) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reads_fixed_huffman_blocks() {
+        let data = hex("78da4b4c4a4e44420a19a93939f91012008def0a27");
+        assert_eq!(zlib_decompress(&data, 1 << 20).unwrap(), b"abcabcabcabcabc hello hello");
+        // The same stream without the zlib header and checksum.
+        assert_eq!(inflate_raw(&data[2..data.len() - 4], 1 << 20).unwrap(), b"abcabcabcabcabc hello hello");
+    }
+
+    #[test]
+    fn reads_dynamic_huffman_blocks() {
+        let data = hex(
+            "78da4d8ec111003108026b0dd07f0db7e0e7cc44c120d126f41a0198aadfb9f494221f8619fc44\
+             975186dc4e9f338ab84e9a82deb4ce78b2bf3040606198336dba4d9463ab0cd68a8bc1d628eb68\
+             de8c0bb1fb000deb4c8c",
+        );
+        let mut x: u32 = 12345;
+        let expected: Vec<u8> = (0..200)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff;
+                b"aaaabbbccd"[((x >> 16) % 10) as usize]
+            })
+            .collect();
+        assert_eq!(zlib_decompress(&data, 1 << 20).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_corrupt_streams() {
+        let mut data = hex("78da4b4c4a4e44420a19a93939f91012008def0a27");
+        *data.last_mut().unwrap() ^= 1;
+        assert_eq!(zlib_decompress(&data, 1 << 20), None);
+        assert_eq!(zlib_decompress(&data[..10], 1 << 20), None);
+        assert_eq!(zlib_decompress(b"\x78", 1 << 20), None);
+    }
+
+    #[test]
+    fn stops_at_the_output_limit() {
+        // One fixed Huffman block: a literal `a`, then 1000 copies of 258
+        // bytes at distance 1. About 1.6 KiB inflating to 258001 bytes.
+        let mut bomb = Vec::new();
+        let mut bit = 0;
+        let mut push = |value: u32, count: u32| {
+            for i in 0..count {
+                if bit % 8 == 0 {
+                    bomb.push(0);
+                }
+                *bomb.last_mut().unwrap() |= ((value >> i & 1) as u8) << (bit % 8);
+                bit += 1;
+            }
+        };
+        // BFINAL, then BTYPE 1 for fixed codes.
+        push(1, 1);
+        push(1, 2);
+        // Huffman codes are stored most significant bit first.
+        let mut code = |code: u32, len: u32| push(code.reverse_bits() >> (32 - len), len);
+        code(0x30 + b'a' as u32, 8);
+        for _ in 0..1000 {
+            code(0xc5, 8);
+            code(0, 5);
+        }
+        code(0, 7);
+        assert_eq!(inflate_raw(&bomb, 258_001).map(|out| out.len()), Some(258_001));
+        assert_eq!(inflate_raw(&bomb, 258_000), None);
+        assert_eq!(inflate_raw(&bomb, 1 << 16), None);
+    }
+}
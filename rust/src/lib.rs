@@ -1,53 +1,360 @@
+pub mod backend;
+pub mod error;
+pub mod gguf;
+pub mod metadata;
+pub mod model_file;
+pub mod outpaint;
+#[cfg(feature = "serde")]
+pub mod preset;
+pub mod seed;
 pub mod stable_diffusion_interface;
-use core::mem::MaybeUninit;
+pub mod validation;
+mod ckpt;
+mod image_size;
+mod inflate;
+mod json;
+mod marshal;
+#[cfg(not(target_arch = "wasm32"))]
+mod no_host;
+mod png;
+mod safetensors;
+#[cfg(test)]
+mod test_util;
+use backend::{Backend, Capabilities, WasmEdgeBackend};
+use core::cell::RefCell;
+use std::borrow::Cow;
+pub use error::{HostErrno, Operation, SdError};
+pub use metadata::{ImageMetadata, ParsedMetadata, UnmappedField};
+use model_file::ModelFileInfo;
+pub use model_file::PrecisionPlan;
+pub use outpaint::{Outpaint, OutpaintCanvas, OutpaintFill};
+pub use validation::{Severity, Violation};
+#[cfg(feature = "serde")]
+pub use preset::Preset;
+use std::path::Path;
+use std::sync::Arc;
 use stable_diffusion_interface::*;
-const BUF_LEN: i32 = 1000000;
+/// Size of the output buffer allocated before the first generation.
+pub const DEFAULT_OUTPUT_BUFFER_LEN: usize = 1 << 20;
+/// Largest output buffer a context will grow to unless configured otherwise.
+pub const DEFAULT_OUTPUT_BUFFER_LIMIT: usize = 256 << 20;
+/// img2img strength of a new context.
+pub const DEFAULT_IMG2IMG_STRENGTH: f32 = 0.75;
+/// Mask blur of a new inpainting context, in pixels.
+pub const DEFAULT_MASK_BLUR: i32 = 4;
+/// Distilled guidance of new generation parameters, the value Flux dev was
+/// distilled around.
+pub const DEFAULT_GUIDANCE: f32 = 3.5;
+/// Layers skipped by skip-layer guidance unless set otherwise, the ones
+/// SD3.5 Medium was tuned with.
+pub const DEFAULT_SLG_LAYERS: [i32; 3] = [7, 8, 9];
 pub struct Quantization {
     pub model_path: String,
     pub vae_model_path: String,
     pub output_path: String,
     pub wtype: SdTypeT,
+    /// Per-tensor types that override `wtype`. Plans with rules need the
+    /// `host-v2` feature unless a custom backend is set.
+    pub precision_plan: PrecisionPlan,
+    /// `None` for [`WasmEdgeBackend`].
+    backend: Option<Arc<dyn Backend>>,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Task {
+    #[cfg_attr(feature = "serde", serde(rename = "txt2img"))]
     TextToImage,
+    #[cfg_attr(feature = "serde", serde(rename = "img2img"))]
     ImageToImage,
+    /// Needs the `host-v2` feature with the WasmEdge host.
+    #[cfg_attr(feature = "serde", serde(rename = "inpaint"))]
+    Inpaint,
+    /// ESRGAN upscaling only; no diffusion model is loaded. Needs the
+    /// `host-v2` feature with the WasmEdge host.
+    #[cfg_attr(feature = "serde", serde(rename = "upscale"))]
+    Upscale,
 }
 pub enum Context<'a> {
     TextToImage(TextToImage<'a>),
     ImageToImage(ImageToImage<'a>),
+    Inpaint(Inpaint<'a>),
+    Upscale(Upscale<'a>),
 }
 pub struct StableDiffusion {
     task: Task,
-    model_path: String,
-    vae_path: String,
-    taesd_path: String,
-    control_net_path: String,
-    lora_model_dir: String,
-    embed_dir: String,
-    id_embed_dir: String,
-    vae_decode_only: bool,
-    vae_tiling: bool,
-    n_threads: i32,
-    wtype: SdTypeT,
-    rng_type: RngTypeT,
-    schedule: ScheduleT,
-    clip_on_cpu: bool,
-    control_net_cpu: bool,
-    vae_on_cpu: bool,
+    config: ContextConfig,
+    backend: Arc<dyn Backend>,
+}
+/// Everything the host needs to load a model into a session.
+///
+/// With the `serde` feature, missing fields deserialize to their defaults.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ContextConfig {
+    /// Full checkpoint. SD3 and Flux models can instead be loaded from their
+    /// components below, which need a plugin with the `host-v2` functions.
+    pub model_path: String,
+    /// Standalone diffusion model, such as the Flux transformer.
+    pub diffusion_model_path: String,
+    pub clip_l_path: String,
+    pub clip_g_path: String,
+    pub t5xxl_path: String,
+    pub vae_path: String,
+    pub taesd_path: String,
+    pub control_net_path: String,
+    pub lora_model_dir: String,
+    pub embed_dir: String,
+    pub id_embed_dir: String,
+    /// ESRGAN model loaded by `Task::Upscale`, which ignores the other paths.
+    pub upscale_model_path: String,
+    pub vae_decode_only: bool,
+    pub vae_tiling: bool,
+    pub n_threads: i32,
+    pub wtype: SdTypeT,
+    pub rng_type: RngTypeT,
+    pub schedule: ScheduleT,
+    pub clip_on_cpu: bool,
+    pub control_net_cpu: bool,
+    pub vae_on_cpu: bool,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            model_path: "".to_string(),
+            diffusion_model_path: "".to_string(),
+            clip_l_path: "".to_string(),
+            clip_g_path: "".to_string(),
+            t5xxl_path: "".to_string(),
+            vae_path: "".to_string(),
+            taesd_path: "".to_string(),
+            control_net_path: "".to_string(),
+            lora_model_dir: "".to_string(),
+            embed_dir: "".to_string(),
+            id_embed_dir: "".to_string(),
+            upscale_model_path: "".to_string(),
+            vae_decode_only: true,
+            vae_tiling: false,
+            n_threads: -1,
+            wtype: SdTypeT::SdTypeCount,
+            rng_type: RngTypeT::StdDefaultRng,
+            schedule: ScheduleT::DEFAULT,
+            clip_on_cpu: false,
+            control_net_cpu: false,
+            vae_on_cpu: false,
+        }
+    }
+}
+
+impl ContextConfig {
+    /// Whether any of the separate SD3 and Flux components is set.
+    pub fn has_components(&self) -> bool {
+        [&self.diffusion_model_path, &self.clip_l_path, &self.clip_g_path, &self.t5xxl_path]
+            .iter()
+            .any(|path| !path.is_empty())
+    }
+
+    /// File the model is named after in image metadata.
+    fn main_model_path(&self) -> &str {
+        if self.model_path.is_empty() {
+            &self.diffusion_model_path
+        } else {
+            &self.model_path
+        }
+    }
 }
+/// State shared by every task context.
+///
+/// The host session behind `session_id` is released when the context is
+/// dropped. Use [`BaseFunction::close`] to release it early and see the error.
+/// Without the `host-v2` feature the WasmEdge host cannot release sessions:
+/// they stay loaded until the module exits, however the context ends.
 pub struct BaseContext<'a> {
     pub session_id: u32,
+    pub params: GenerationParams<'a>,
+    pub output_buffer: OutputBuffer,
+    /// Whether generated PNGs get a `parameters` chunk, see [`metadata`].
+    /// On by default.
+    pub embed_metadata: bool,
+    model: String,
+    schedule: ScheduleT,
+    capabilities: Capabilities,
+    backend: Arc<dyn Backend>,
+    closed: bool,
+}
+
+impl BaseContext<'_> {
+    /// Frees the host session. Calling it again after success is a no-op.
+    /// Without the `host-v2` feature the WasmEdge host cannot free sessions
+    /// and this fails with [`SdError::Unsupported`].
+    pub fn close(&mut self) -> Result<(), SdError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        if !self.backend.frees_sessions() {
+            return Err(SdError::Unsupported {
+                operation: Operation::FreeContext,
+                reason: "needs the `host-v2` feature",
+            });
+        }
+        self.backend
+            .free_context(self.session_id)
+            .map_err(|errno| SdError::host(Operation::FreeContext, errno))
+    }
+}
+
+impl<'a> BaseContext<'a> {
+    /// Checks the generation parameters, and that the host runs the chosen
+    /// sampling method, without calling the host.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = self.params.validate();
+        let method = self.params.sample_method;
+        if method != SampleMethodT::NSAMPLEMETHODS && !self.capabilities.supports_sample_method(method) {
+            violations.push(Violation::error(
+                "sample_method",
+                format!("{} is not supported by the host plugin", method),
+            ));
+        }
+        violations
+    }
+
+    /// Settings recorded in generated images. The output path is left out.
+    pub fn metadata(&self) -> ImageMetadata {
+        let mut params = self.params.without_control_image();
+        params.output_path.clear();
+        ImageMetadata {
+            params,
+            model: self.model.clone(),
+            schedule: self.schedule,
+            strength: None,
+            mask_blur: None,
+        }
+    }
+
+    /// Whether the guest writes the output files instead of the host, which
+    /// it does to embed metadata and to name the images of a batch.
+    fn guest_writes_files(&self) -> bool {
+        !self.params.output_path.is_empty() && (self.embed_metadata || self.params.batch_count > 1)
+    }
+
+    /// Parameters sent to the host. When the guest writes the output files the
+    /// host is not given an output path.
+    fn host_params(&self) -> Cow<'_, GenerationParams<'a>> {
+        if self.guest_writes_files() {
+            let mut params = self.params.clone();
+            params.output_path.clear();
+            Cow::Owned(params)
+        } else {
+            Cow::Borrowed(&self.params)
+        }
+    }
+
+    /// Calls the host for every image of the batch and returns them back to
+    /// back: in one call when the host derives the same per-image seeds as
+    /// [`seed::derive`], otherwise one call per image.
+    fn run<F>(&self, operation: Operation, mut call: F) -> Result<Vec<u8>, SdError>
+    where
+        F: FnMut(&GenerationParams<'a>, &mut [u8], &mut u32) -> Result<(), WasmedgeSdErrno>,
+    {
+        let params = self.host_params();
+        if seed::host_derives_batch(params.seed, params.batch_count) {
+            return self.output_buffer.fill(operation, |buf, bytes_written| {
+                call(&params, buf, bytes_written)
+            });
+        }
+        let mut data = Vec::new();
+        for index in 0..params.batch_count.max(1) as usize {
+            let image_params = GenerationParams {
+                seed: params.seed_for(index),
+                batch_count: 1,
+                ..params.as_ref().clone()
+            };
+            data.extend(self.output_buffer.fill(operation, |buf, bytes_written| {
+                call(&image_params, buf, bytes_written)
+            })?);
+        }
+        Ok(data)
+    }
+
+    /// Splits the host output into one [`ImageOutput`] per image, embedding
+    /// `metadata` with each image's own seed and writing the files if the
+    /// guest is responsible for them.
+    fn outputs(&self, data: &[u8], metadata: ImageMetadata) -> Result<Vec<ImageOutput>, SdError> {
+        let guest_writes_files = self.guest_writes_files();
+        let mut outputs = Vec::new();
+        for (index, image) in png::split(data).into_iter().enumerate() {
+            let seed = self.params.seed_for(index);
+            let output_path = self.params.output_path_for(index);
+            let data = if self.embed_metadata {
+                let mut metadata = metadata.clone();
+                metadata.params.seed = seed;
+                metadata.params.batch_count = 1;
+                metadata.embed(image).unwrap_or_else(|| image.to_vec())
+            } else {
+                image.to_vec()
+            };
+            if guest_writes_files {
+                std::fs::write(&output_path, &data).map_err(|err| SdError::io(&output_path, err))?;
+            }
+            outputs.push(ImageOutput {
+                data,
+                bytes_written: image.len(),
+                file_written: !output_path.is_empty(),
+                output_path,
+                seed,
+            });
+        }
+        Ok(outputs)
+    }
+}
+
+/// Turns a validation pass into an error if it found any.
+fn reject_errors(violations: Vec<Violation>) -> Result<(), SdError> {
+    if violations.iter().any(Violation::is_error) {
+        return Err(SdError::Validation(violations));
+    }
+    Ok(())
+}
+
+/// Frees the session, ignoring errors. A session the host cannot free stays
+/// loaded, see [`BaseContext`].
+impl Drop for BaseContext<'_> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Per-generation settings shared by every task.
+///
+/// Copy one value, tweak it and hand it to [`BaseFunction::set_params`] to run
+/// sweeps or presets without touching the session.
+///
+/// With the `serde` feature, missing fields deserialize to their defaults. The
+/// control image borrows its data and is never serialized.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct GenerationParams<'a> {
     pub prompt: String,
     pub width: i32,
     pub height: i32,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub control_image: ImageType<'a>,
     pub negative_prompt: String,
     pub clip_skip: i32,
-    pub cfg_scale: f32,
+    /// Its fields sit at the top level when serialized.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub guidance: GuidanceParams,
     pub sample_method: SampleMethodT,
     pub sample_steps: i32,
-    pub seed: i32,
+    /// Noise DDIM-trailing and TCD add back at each step, from 0 for none to
+    /// 1. Other samplers ignore it; it needs the `host-v2` feature.
+    pub eta: f32,
+    /// See [`seed`] for how it is passed to the host.
+    pub seed: u64,
     pub batch_count: i32,
     pub control_strength: f32,
     pub style_ratio: f32,
@@ -58,9 +365,237 @@ pub struct BaseContext<'a> {
     pub upscale_repeats: i32,
     pub output_path: String,
 }
+
+impl GenerationParams<'_> {
+    /// Checks these parameters against the rules in [`validation`].
+    pub fn validate(&self) -> Vec<Violation> {
+        validation::validate_params(self)
+    }
+
+    /// Seed of image `index` of a batch, see [`seed::derive`].
+    pub fn seed_for(&self, index: usize) -> u64 {
+        seed::derive(self.seed, index)
+    }
+
+    /// File image `index` of a batch is written to: `output_path` itself for a
+    /// single image, otherwise `{stem}_{index}{.ext}` next to it, such as
+    /// `out/cat_0.png`, `out/cat_1.png`. Empty if there is no output path.
+    pub fn output_path_for(&self, index: usize) -> String {
+        if self.output_path.is_empty() || self.batch_count <= 1 {
+            return self.output_path.clone();
+        }
+        let path = Path::new(&self.output_path);
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("_{}", index));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    /// Copy of these parameters that does not borrow the control image.
+    pub fn without_control_image(&self) -> GenerationParams<'static> {
+        GenerationParams {
+            prompt: self.prompt.clone(),
+            width: self.width,
+            height: self.height,
+            control_image: ImageType::Path(""),
+            negative_prompt: self.negative_prompt.clone(),
+            clip_skip: self.clip_skip,
+            guidance: self.guidance.clone(),
+            sample_method: self.sample_method,
+            sample_steps: self.sample_steps,
+            eta: self.eta,
+            seed: self.seed,
+            batch_count: self.batch_count,
+            control_strength: self.control_strength,
+            style_ratio: self.style_ratio,
+            normalize_input: self.normalize_input,
+            input_id_images_dir: self.input_id_images_dir.clone(),
+            canny_preprocess: self.canny_preprocess,
+            upscale_model: self.upscale_model.clone(),
+            upscale_repeats: self.upscale_repeats,
+            output_path: self.output_path.clone(),
+        }
+    }
+}
+
+impl Default for GenerationParams<'_> {
+    fn default() -> Self {
+        GenerationParams {
+            prompt: "".to_string(),
+            width: 512,
+            height: 512,
+            control_image: ImageType::Path(""),
+            negative_prompt: "".to_string(),
+            clip_skip: -1,
+            guidance: GuidanceParams::default(),
+            sample_method: SampleMethodT::EULERA,
+            sample_steps: 20,
+            eta: 0.0,
+            seed: 42,
+            batch_count: 1,
+            control_strength: 0.9,
+            style_ratio: 20.0,
+            normalize_input: false,
+            input_id_images_dir: "".to_string(),
+            canny_preprocess: false,
+            upscale_model: "".to_string(),
+            upscale_repeats: 1,
+            output_path: "".to_string(),
+        }
+    }
+}
+
+/// How strongly a generation follows its conditioning.
+///
+/// Only `cfg_scale` reaches plugins without the `host-v2` functions; the other
+/// fields are sent to the host before each generation with the feature on.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct GuidanceParams {
+    /// Classifier-free guidance scale of the text condition.
+    pub cfg_scale: f32,
+    /// Guidance scale of the image condition of instruct models; `None` uses
+    /// `cfg_scale`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub img_cfg_scale: Option<f32>,
+    /// Distilled guidance of Flux models, which take it in place of
+    /// `cfg_scale`. Other models ignore it.
+    pub distilled_guidance: f32,
+    /// Layers skip-layer guidance leaves out of the extra unconditioned pass.
+    pub slg_layers: Vec<i32>,
+    /// Fraction of the sampling steps at which skip-layer guidance starts.
+    pub slg_start: f32,
+    /// Fraction of the sampling steps at which skip-layer guidance ends.
+    pub slg_end: f32,
+    /// Skip-layer guidance scale; 0 turns it off.
+    pub slg_scale: f32,
+}
+
+impl GuidanceParams {
+    /// Guidance scale of the image condition, `cfg_scale` unless set.
+    pub fn img_cfg_scale(&self) -> f32 {
+        self.img_cfg_scale.unwrap_or(self.cfg_scale)
+    }
+}
+
+impl Default for GuidanceParams {
+    fn default() -> Self {
+        GuidanceParams {
+            cfg_scale: 7.0,
+            img_cfg_scale: None,
+            distilled_guidance: DEFAULT_GUIDANCE,
+            slg_layers: DEFAULT_SLG_LAYERS.to_vec(),
+            slg_start: 0.01,
+            slg_end: 0.2,
+            slg_scale: 0.0,
+        }
+    }
+}
+
+/// Output buffer shared by all generations on one session.
+///
+/// The buffer starts at `DEFAULT_OUTPUT_BUFFER_LEN` bytes. When the host answers
+/// `MISSING_MEMORY` the buffer grows to the size the host asked for (or doubles
+/// if it did not say) and the call is retried, up to `limit` bytes. The grown
+/// allocation is kept for the next call.
+pub struct OutputBuffer {
+    data: RefCell<Vec<u8>>,
+    limit: usize,
+}
+
+impl OutputBuffer {
+    pub fn new(limit: usize) -> OutputBuffer {
+        OutputBuffer {
+            data: RefCell::new(Vec::new()),
+            limit,
+        }
+    }
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+    /// Current size of the allocation handed to the host.
+    pub fn capacity(&self) -> usize {
+        self.data.borrow().len()
+    }
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        let data = self.data.get_mut();
+        if data.len() > limit {
+            data.truncate(limit);
+            data.shrink_to_fit();
+        }
+    }
+
+    /// Runs `call` with the buffer and its `bytes_written` slot until the image
+    /// fits, then returns a copy of the written bytes.
+    fn fill<F>(&self, operation: Operation, mut call: F) -> Result<Vec<u8>, SdError>
+    where
+        F: FnMut(&mut [u8], &mut u32) -> Result<(), WasmedgeSdErrno>,
+    {
+        let mut data = self.data.borrow_mut();
+        if data.is_empty() {
+            data.resize(DEFAULT_OUTPUT_BUFFER_LEN.min(self.limit), 0);
+        }
+        loop {
+            let mut bytes_written = 0;
+            match call(&mut data, &mut bytes_written) {
+                Ok(()) => {
+                    let len = (bytes_written as usize).min(data.len());
+                    return Ok(data[..len].to_vec());
+                }
+                Err(WASMEDGE_SD_ERRNO_MISSING_MEMORY) if data.len() < self.limit => {
+                    let requested = bytes_written as usize;
+                    let wanted = if requested > data.len() {
+                        requested
+                    } else {
+                        data.len().saturating_mul(2).max(1)
+                    };
+                    if requested > self.limit {
+                        return Err(SdError::OutputTooLarge {
+                            operation,
+                            needed: Some(requested),
+                            limit: self.limit,
+                        });
+                    }
+                    data.resize(wanted.min(self.limit), 0);
+                }
+                Err(WASMEDGE_SD_ERRNO_MISSING_MEMORY) => {
+                    return Err(SdError::OutputTooLarge {
+                        operation,
+                        needed: (bytes_written as usize > data.len()).then_some(bytes_written as usize),
+                        limit: self.limit,
+                    });
+                }
+                Err(code) => return Err(SdError::host(operation, code)),
+            }
+        }
+    }
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        OutputBuffer::new(DEFAULT_OUTPUT_BUFFER_LIMIT)
+    }
+}
+
 pub trait BaseFunction<'a> {
     fn base(&mut self) -> &mut BaseContext<'a>;
 
+    /// Returns the generation parameters currently set on the context.
+    fn params(&self) -> &GenerationParams<'a>;
+
+    /// Replaces all generation parameters at once.
+    fn set_params(&mut self, params: GenerationParams<'a>) -> &mut Self {
+        self.base().params = params;
+        self
+    }
+
+    #[deprecated(note = "use `set_params` with a `GenerationParams` value")]
+    #[allow(clippy::too_many_arguments)]
     fn set_base_params(&mut self, 
         prompt: String,
         width: i32,
@@ -71,7 +606,7 @@ pub trait BaseFunction<'a> {
         cfg_scale: f32,
         sample_method: SampleMethodT,
         sample_steps: i32,
-        seed: i32,
+        seed: u64,
         batch_count: i32,
         control_strength: f32,
         style_ratio: f32,
@@ -82,31 +617,190 @@ pub trait BaseFunction<'a> {
         upscale_repeats: i32,
         output_path: String,
     ) -> &mut Self {
-        {
-            self.base().prompt = prompt;
-            self.base().width = width;
-            self.base().height = height;
-            self.base().control_image = control_image;
-            self.base().negative_prompt = negative_prompt;
-            self.base().clip_skip = clip_skip;
-            self.base().cfg_scale = cfg_scale;
-            self.base().sample_method = sample_method;
-            self.base().sample_steps = sample_steps;
-            self.base().seed = seed;
-            self.base().batch_count = batch_count;
-            self.base().control_strength = control_strength;
-            self.base().style_ratio = style_ratio;
-            self.base().normalize_input = normalize_input;
-            self.base().input_id_images_dir = input_id_images_dir;
-            self.base().canny_preprocess = canny_preprocess;
-            self.base().upscale_model = upscale_model;
-            self.base().upscale_repeats = upscale_repeats;
-            self.base().output_path = output_path;
-        }
+        self.set_params(GenerationParams {
+            prompt,
+            width,
+            height,
+            control_image,
+            negative_prompt,
+            clip_skip,
+            guidance: GuidanceParams {
+                cfg_scale,
+                ..self.params().guidance.clone()
+            },
+            sample_method,
+            sample_steps,
+            eta: self.params().eta,
+            seed,
+            batch_count,
+            control_strength,
+            style_ratio,
+            normalize_input,
+            input_id_images_dir,
+            canny_preprocess,
+            upscale_model,
+            upscale_repeats,
+            output_path,
+        })
+    }
+
+    fn set_prompt(&mut self, prompt: &str) -> &mut Self {
+        self.base().params.prompt = prompt.to_string();
+        self
+    }
+    fn set_width(&mut self, width: i32) -> &mut Self {
+        self.base().params.width = width;
+        self
+    }
+    fn set_height(&mut self, height: i32) -> &mut Self {
+        self.base().params.height = height;
+        self
+    }
+    fn set_control_image(&mut self, control_image: ImageType<'a>) -> &mut Self {
+        self.base().params.control_image = control_image;
+        self
+    }
+    fn set_negative_prompt(&mut self, negative_prompt: &str) -> &mut Self {
+        self.base().params.negative_prompt = negative_prompt.to_string();
+        self
+    }
+    fn set_clip_skip(&mut self, clip_skip: i32) -> &mut Self {
+        self.base().params.clip_skip = clip_skip;
+        self
+    }
+    fn set_guidance(&mut self, guidance: GuidanceParams) -> &mut Self {
+        self.base().params.guidance = guidance;
+        self
+    }
+    fn set_cfg_scale(&mut self, cfg_scale: f32) -> &mut Self {
+        self.base().params.guidance.cfg_scale = cfg_scale;
+        self
+    }
+    fn set_img_cfg_scale(&mut self, img_cfg_scale: Option<f32>) -> &mut Self {
+        self.base().params.guidance.img_cfg_scale = img_cfg_scale;
+        self
+    }
+    fn set_distilled_guidance(&mut self, distilled_guidance: f32) -> &mut Self {
+        self.base().params.guidance.distilled_guidance = distilled_guidance;
+        self
+    }
+    fn set_sample_method(&mut self, sample_method: SampleMethodT) -> &mut Self {
+        self.base().params.sample_method = sample_method;
+        self
+    }
+    fn set_eta(&mut self, eta: f32) -> &mut Self {
+        self.base().params.eta = eta;
+        self
+    }
+    fn set_sample_steps(&mut self, sample_steps: i32) -> &mut Self {
+        self.base().params.sample_steps = sample_steps;
+        self
+    }
+    fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.base().params.seed = seed;
+        self
+    }
+    fn set_batch_count(&mut self, batch_count: i32) -> &mut Self {
+        self.base().params.batch_count = batch_count;
+        self
+    }
+    fn set_control_strength(&mut self, control_strength: f32) -> &mut Self {
+        self.base().params.control_strength = control_strength;
+        self
+    }
+    fn set_style_ratio(&mut self, style_ratio: f32) -> &mut Self {
+        self.base().params.style_ratio = style_ratio;
+        self
+    }
+    fn set_normalize_input(&mut self, normalize_input: bool) -> &mut Self {
+        self.base().params.normalize_input = normalize_input;
+        self
+    }
+    fn set_input_id_images_dir(&mut self, input_id_images_dir: &str) -> &mut Self {
+        self.base().params.input_id_images_dir = input_id_images_dir.to_string();
+        self
+    }
+    fn set_canny_preprocess(&mut self, canny_preprocess: bool) -> &mut Self {
+        self.base().params.canny_preprocess = canny_preprocess;
+        self
+    }
+    fn set_upscale_model(&mut self, upscale_model: &str) -> &mut Self {
+        self.base().params.upscale_model = upscale_model.to_string();
+        self
+    }
+    fn set_upscale_repeats(&mut self, upscale_repeats: i32) -> &mut Self {
+        self.base().params.upscale_repeats = upscale_repeats;
+        self
+    }
+    fn set_output_path(&mut self, output_path: &str) -> &mut Self {
+        self.base().params.output_path = output_path.to_string();
+        self
+    }
+
+    /// Runs the generation and discards the encoded images. The results are
+    /// only written to `output_path`.
+    fn generate(&self) -> Result<(), SdError> {
+        self.generate_images().map(|_| ())
+    }
+
+    /// Runs the generation and returns the first encoded image. An empty
+    /// `output_path` keeps the result in memory only. The other images of a
+    /// batch are still written; use [`Self::generate_images`] to get them.
+    fn generate_image(&self) -> Result<ImageOutput, SdError> {
+        self.generate_images()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| SdError::format("host output", "no image returned"))
+    }
+
+    /// Runs the generation and returns every image of the batch in order.
+    /// Image `i` is generated with [`GenerationParams::seed_for`]`(i)` and
+    /// written to [`GenerationParams::output_path_for`]`(i)`.
+    fn generate_images(&self) -> Result<Vec<ImageOutput>, SdError>;
+
+    /// Checks the parameters without calling the host. `generate` runs the
+    /// same checks and refuses to start if any of them is an error.
+    fn validate(&self) -> Vec<Violation> {
+        self.params().validate()
+    }
+
+    /// Releases the host session now instead of on drop, returning any error
+    /// from the host.
+    fn close(mut self) -> Result<(), SdError>
+    where
+        Self: Sized,
+    {
+        self.base().close()
+    }
+
+    /// Turns the `parameters` chunk in generated PNGs on or off. While it is
+    /// on, the guest writes `output_path` itself and needs write access to it.
+    fn set_embed_metadata(&mut self, embed_metadata: bool) -> &mut Self {
+        self.base().embed_metadata = embed_metadata;
         self
     }
 
-    fn generate(&self) -> Result<(), WasmedgeSdErrno>;
+    /// Caps how large the output buffer may grow before generation fails with
+    /// [`SdError::OutputTooLarge`].
+    fn set_output_buffer_limit(&mut self, limit: usize) -> &mut Self {
+        self.base().output_buffer.set_limit(limit);
+        self
+    }
+}
+
+/// Encoded image returned by [`BaseFunction::generate_images`].
+#[derive(Clone, Debug)]
+pub struct ImageOutput {
+    /// The encoded image, with its `parameters` chunk if metadata is embedded.
+    pub data: Vec<u8>,
+    /// Number of bytes the host wrote for this image, before any metadata.
+    pub bytes_written: usize,
+    /// Whether the image was also written to `output_path`.
+    pub file_written: bool,
+    /// File the image was written to, empty if none.
+    pub output_path: String,
+    /// Seed the image was generated with.
+    pub seed: u64,
 }
 
 pub struct TextToImage<'a> {
@@ -117,6 +811,31 @@ pub struct ImageToImage<'a> {
     pub image: ImageType<'a>,
     pub strength: f32,
 }
+/// Runs only the upscaler over an existing image, `repeats` times.
+///
+/// Created by a `Task::Upscale` model, whose session holds the ESRGAN model
+/// and no diffusion model. Of the shared state only the session and the
+/// output buffer are used.
+pub struct Upscale<'a> {
+    pub common: BaseContext<'a>,
+    pub image: ImageType<'a>,
+    pub repeats: i32,
+    /// File the host writes the result to, if not empty.
+    pub output_path: String,
+}
+/// Regenerates the parts of `image` that are white in `mask`.
+pub struct Inpaint<'a> {
+    pub common: BaseContext<'a>,
+    pub image: ImageType<'a>,
+    /// Same size as `image`; white marks the pixels to regenerate.
+    pub mask: ImageType<'a>,
+    pub strength: f32,
+    /// Blur radius applied to the mask edges, in pixels.
+    pub mask_blur: i32,
+    /// Copies the pixels outside the mask from `image` unchanged, instead of
+    /// taking them from the decoded result.
+    pub keep_unmasked: bool,
+}
 
 impl Quantization {
     pub fn new(model_path: &str, output_path: &str, wtype: SdTypeT) -> Quantization {
@@ -124,22 +843,105 @@ impl Quantization {
             model_path: model_path.to_string(),
             vae_model_path: "".to_string(),
             output_path: output_path.to_string(),
-            wtype: wtype,
+            wtype,
+            precision_plan: PrecisionPlan::new(),
+            backend: None,
         }
     }
-    pub fn convert(&self) -> Result<(), WasmedgeSdErrno> {
-        unsafe {
-            stable_diffusion_interface::convert(
-                &self.model_path,
-                &self.vae_model_path,
-                &self.output_path,
-                self.wtype,
-            )
+    /// Runs the conversion on `backend` instead of the WasmEdge host.
+    pub fn with_backend(mut self, backend: Arc<dyn Backend>) -> Quantization {
+        self.backend = Some(backend);
+        self
+    }
+    /// Bakes the VAE at `vae_model_path` into the output, replacing the one
+    /// embedded in the model.
+    pub fn with_vae_model_path(mut self, vae_model_path: &str) -> Quantization {
+        self.vae_model_path = vae_model_path.to_string();
+        self
+    }
+    /// Gives the tensors matched by `plan` their own types instead of `wtype`.
+    pub fn with_precision_plan(mut self, plan: PrecisionPlan) -> Quantization {
+        self.precision_plan = plan;
+        self
+    }
+    /// Reads the tensor tables of the model and, if set, the VAE, and checks
+    /// that the host can convert them. No weights are loaded.
+    pub fn inspect(&self) -> Result<Vec<ModelFileInfo>, SdError> {
+        if self.model_path.is_empty() {
+            return Err(SdError::invalid("model_path", "is required"));
+        }
+        self.precision_plan
+            .check()
+            .map_err(|reason| SdError::invalid("precision_plan", reason))?;
+        let inputs = [
+            ("model_path", &self.model_path),
+            ("vae_model_path", &self.vae_model_path),
+        ];
+        inputs
+            .into_iter()
+            .filter(|(_, path)| !path.is_empty())
+            .map(|(field, path)| {
+                let info = ModelFileInfo::read(path).map_err(|err| SdError::invalid(field, err.to_string()))?;
+                info.check_convertible()
+                    .map_err(|reason| SdError::invalid(field, format!("{}: {}", path, reason)))?;
+                Ok(info)
+            })
+            .collect()
+    }
+    /// Estimated size of the converted tensor data, in bytes.
+    pub fn estimate_size(&self) -> Result<u64, SdError> {
+        self.estimate_size_as(self.wtype)
+    }
+    /// Estimated size of the tensor data converted to `wtype` instead of
+    /// [`wtype`](Self::wtype), in bytes.
+    pub fn estimate_size_as(&self, wtype: SdTypeT) -> Result<u64, SdError> {
+        Ok(self
+            .inspect()?
+            .iter()
+            .map(|info| info.planned_size(wtype, &self.precision_plan))
+            .fold(0, u64::saturating_add))
+    }
+    /// Converts the model, after [`inspect`](Self::inspect) has checked the
+    /// inputs.
+    pub fn convert(&self) -> Result<(), SdError> {
+        self.convert_all(&[(&self.output_path, self.wtype)])
+    }
+    /// Converts the model once per `(output_path, wtype)` pair, checking the
+    /// inputs only once. Stops at the first failure; the outputs written
+    /// before it are kept. The `output_path` and `wtype` fields are ignored.
+    pub fn convert_all(&self, outputs: &[(&str, SdTypeT)]) -> Result<(), SdError> {
+        let rules = self.precision_plan.host_rules();
+        // Per-tensor types need the `convert_with_rules` host function.
+        if !rules.is_empty() && self.backend.is_none() && !cfg!(feature = "host-v2") {
+            return Err(SdError::invalid("precision_plan", "needs the `host-v2` feature"));
+        }
+        self.inspect()?;
+        let backend: &dyn Backend = match &self.backend {
+            Some(backend) => backend.as_ref(),
+            None => &WasmEdgeBackend,
+        };
+        for &(output_path, wtype) in outputs {
+            if rules.is_empty() {
+                backend.convert(&self.model_path, &self.vae_model_path, output_path, wtype)
+            } else {
+                backend.convert_with_rules(
+                    &self.model_path,
+                    &self.vae_model_path,
+                    output_path,
+                    wtype,
+                    &rules,
+                )
+            }
+            .map_err(|errno| SdError::host(Operation::Convert, errno))?;
         }
+        Ok(())
     }
 }
 
 impl StableDiffusion {
+    /// Thin wrapper over [`StableDiffusionBuilder`] kept for existing callers.
+    /// Unlike [`StableDiffusionBuilder::build`] it does not validate the paths.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(task: Task, model_path: &str, 
         vae_path: &str,
         taesd_path: &str, 
@@ -154,85 +956,283 @@ impl StableDiffusion {
         control_net_cpu: bool,
         vae_on_cpu: bool
     ) -> StableDiffusion {
-        let vae_decode_only = match task {
-            Task::TextToImage => true,
-            Task::ImageToImage => false,
+        StableDiffusion::builder()
+            .task(task)
+            .model_path(model_path)
+            .vae_path(vae_path)
+            .taesd_path(taesd_path)
+            .control_net_path(control_net_path)
+            .lora_model_dir(lora_model_dir)
+            .embed_dir(embed_dir)
+            .id_embed_dir(id_embed_dir)
+            .vae_tiling(vae_tiling)
+            .n_threads(n_threads)
+            .wtype(wtype)
+            .rng_type(rng_type)
+            .schedule(schedule)
+            .clip_on_cpu(clip_on_cpu)
+            .control_net_cpu(control_net_cpu)
+            .vae_on_cpu(vae_on_cpu)
+            .finish()
+    }
+    pub fn builder() -> StableDiffusionBuilder {
+        StableDiffusionBuilder::new()
+    }
+    pub fn task(&self) -> Task {
+        self.task
+    }
+    pub fn config(&self) -> &ContextConfig {
+        &self.config
+    }
+    pub fn create_context(&self) -> Result<Context<'_>, SdError> {
+        let capabilities = self
+            .backend
+            .capabilities()
+            .map_err(|errno| SdError::host(Operation::CreateContext, errno))?;
+        let schedule = self.config.schedule;
+        if self.task != Task::Upscale && !capabilities.supports_schedule(schedule) {
+            return Err(SdError::invalid(
+                "schedule",
+                format!("{} is not supported by the host plugin", schedule),
+            ));
+        }
+        let (session_id, model_path) = match self.task {
+            Task::Upscale => (
+                self.backend.create_upscaler_context(&self.config),
+                self.config.upscale_model_path.as_str(),
+            ),
+            _ => (self.backend.create_context(&self.config), self.config.main_model_path()),
         };
-        StableDiffusion {
-            task: task,
-            model_path: model_path.to_string(),
-            vae_path: vae_path.to_string(),
-            taesd_path: taesd_path.to_string(),
-            control_net_path: control_net_path.to_string(),
-            lora_model_dir: lora_model_dir.to_string(),
-            embed_dir: embed_dir.to_string(),
-            id_embed_dir: id_embed_dir.to_string(),
-            vae_decode_only: vae_decode_only,
-            vae_tiling: vae_tiling,
-            n_threads: n_threads,
-            wtype: wtype,
-            rng_type: rng_type,
-            schedule: schedule,
-            clip_on_cpu: clip_on_cpu,
-            control_net_cpu: control_net_cpu,
-            vae_on_cpu: vae_on_cpu,
-        }
-    }
-    pub fn create_context(&self) -> Result<Context, WasmedgeSdErrno> {
-        let mut session_id = MaybeUninit::<u32>::uninit();
-        unsafe {
-            let result = stable_diffusion_interface::create_context(
-                &self.model_path,
-                &self.vae_path,
-                &self.taesd_path,
-                &self.control_net_path,
-                &self.lora_model_dir,
-                &self.embed_dir,
-                &self.id_embed_dir,
-                self.vae_decode_only,
-                self.vae_tiling,
-                self.n_threads,
-                self.wtype,
-                self.rng_type,
-                self.schedule,
-                self.clip_on_cpu,
-                self.control_net_cpu,
-                self.vae_on_cpu,
-                session_id.as_mut_ptr(),
-            );
-            if let Err(code) = result {
-                return Err(code);
+        let session_id = session_id.map_err(|errno| SdError::host(Operation::CreateContext, errno))?;
+        let common = BaseContext {
+            session_id,
+            params: GenerationParams::default(),
+            output_buffer: OutputBuffer::default(),
+            embed_metadata: true,
+            model: metadata::model_name(model_path),
+            schedule,
+            capabilities,
+            backend: self.backend.clone(),
+            closed: false,
+        };
+        match self.task {
+            Task::TextToImage => Ok(Context::TextToImage(TextToImage { common })),
+            Task::ImageToImage => Ok(Context::ImageToImage(ImageToImage {
+                common,
+                image: ImageType::Path(""),
+                strength: DEFAULT_IMG2IMG_STRENGTH,
+            })),
+            Task::Inpaint => Ok(Context::Inpaint(Inpaint {
+                common,
+                image: ImageType::Path(""),
+                mask: ImageType::Path(""),
+                strength: DEFAULT_IMG2IMG_STRENGTH,
+                mask_blur: DEFAULT_MASK_BLUR,
+                keep_unmasked: false,
+            })),
+            Task::Upscale => Ok(Context::Upscale(Upscale {
+                common,
+                image: ImageType::Path(""),
+                repeats: 1,
+                output_path: String::new(),
+            })),
+        }
+    }
+}
+/// Named-setter alternative to [`StableDiffusion::new`].
+///
+/// Only the model path is required, or the diffusion model path for models
+/// loaded from components, or the upscale model path for `Task::Upscale`.
+/// The task defaults to `Task::TextToImage` and every other option to the
+/// same value as the example CLI.
+#[derive(Clone, Default)]
+pub struct StableDiffusionBuilder {
+    task: Option<Task>,
+    config: ContextConfig,
+    backend: Option<Arc<dyn Backend>>,
+}
+
+impl StableDiffusionBuilder {
+    pub fn new() -> StableDiffusionBuilder {
+        StableDiffusionBuilder::default()
+    }
+    pub fn task(mut self, task: Task) -> Self {
+        self.task = Some(task);
+        self
+    }
+    /// Replaces every model option at once, such as a config loaded from a
+    /// preset. `vae_decode_only` is still derived from the task.
+    pub fn config(mut self, config: ContextConfig) -> Self {
+        self.config = config;
+        self
+    }
+    pub fn model_path(mut self, model_path: &str) -> Self {
+        self.config.model_path = model_path.to_string();
+        self
+    }
+    pub fn diffusion_model_path(mut self, diffusion_model_path: &str) -> Self {
+        self.config.diffusion_model_path = diffusion_model_path.to_string();
+        self
+    }
+    pub fn clip_l_path(mut self, clip_l_path: &str) -> Self {
+        self.config.clip_l_path = clip_l_path.to_string();
+        self
+    }
+    pub fn clip_g_path(mut self, clip_g_path: &str) -> Self {
+        self.config.clip_g_path = clip_g_path.to_string();
+        self
+    }
+    pub fn t5xxl_path(mut self, t5xxl_path: &str) -> Self {
+        self.config.t5xxl_path = t5xxl_path.to_string();
+        self
+    }
+    pub fn vae_path(mut self, vae_path: &str) -> Self {
+        self.config.vae_path = vae_path.to_string();
+        self
+    }
+    pub fn taesd_path(mut self, taesd_path: &str) -> Self {
+        self.config.taesd_path = taesd_path.to_string();
+        self
+    }
+    pub fn control_net_path(mut self, control_net_path: &str) -> Self {
+        self.config.control_net_path = control_net_path.to_string();
+        self
+    }
+    pub fn lora_model_dir(mut self, lora_model_dir: &str) -> Self {
+        self.config.lora_model_dir = lora_model_dir.to_string();
+        self
+    }
+    pub fn embed_dir(mut self, embed_dir: &str) -> Self {
+        self.config.embed_dir = embed_dir.to_string();
+        self
+    }
+    pub fn id_embed_dir(mut self, id_embed_dir: &str) -> Self {
+        self.config.id_embed_dir = id_embed_dir.to_string();
+        self
+    }
+    pub fn upscale_model_path(mut self, upscale_model_path: &str) -> Self {
+        self.config.upscale_model_path = upscale_model_path.to_string();
+        self
+    }
+    pub fn vae_tiling(mut self, vae_tiling: bool) -> Self {
+        self.config.vae_tiling = vae_tiling;
+        self
+    }
+    /// Number of threads; `<= 0` lets the host use all physical cores.
+    pub fn n_threads(mut self, n_threads: i32) -> Self {
+        self.config.n_threads = n_threads;
+        self
+    }
+    /// Weight type; `SdTypeT::SdTypeCount` keeps the type of the weight file.
+    pub fn wtype(mut self, wtype: SdTypeT) -> Self {
+        self.config.wtype = wtype;
+        self
+    }
+    pub fn rng_type(mut self, rng_type: RngTypeT) -> Self {
+        self.config.rng_type = rng_type;
+        self
+    }
+    pub fn schedule(mut self, schedule: ScheduleT) -> Self {
+        self.config.schedule = schedule;
+        self
+    }
+    pub fn clip_on_cpu(mut self, clip_on_cpu: bool) -> Self {
+        self.config.clip_on_cpu = clip_on_cpu;
+        self
+    }
+    pub fn control_net_cpu(mut self, control_net_cpu: bool) -> Self {
+        self.config.control_net_cpu = control_net_cpu;
+        self
+    }
+    pub fn vae_on_cpu(mut self, vae_on_cpu: bool) -> Self {
+        self.config.vae_on_cpu = vae_on_cpu;
+        self
+    }
+    /// Host to run on. Defaults to [`WasmEdgeBackend`].
+    pub fn backend(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Checks that the model path is set and that every configured file and
+    /// directory exists, then builds the [`StableDiffusion`].
+    pub fn build(self) -> Result<StableDiffusion, SdError> {
+        // The WasmEdge host inpaints and upscales only through the v2 imports.
+        if self.backend.is_none()
+            && !cfg!(feature = "host-v2")
+            && matches!(self.task, Some(Task::Inpaint | Task::Upscale))
+        {
+            return Err(SdError::invalid("task", "needs the `host-v2` feature"));
+        }
+        // The upscaler session loads nothing but the upscale model.
+        if self.task == Some(Task::Upscale) {
+            let path = &self.config.upscale_model_path;
+            if path.is_empty() {
+                return Err(SdError::invalid("upscale_model_path", "is required for upscaling"));
             }
-            let common = BaseContext {
-                prompt: "".to_string(),
-                session_id: session_id.assume_init(),
-                width: 512,
-                height: 512,
-                control_image: ImageType::Path(""),
-                negative_prompt: "".to_string(),
-                clip_skip: -1,
-                cfg_scale: 7.0,
-                sample_method: SampleMethodT::EULERA,
-                sample_steps: 20,
-                seed: 42,
-                batch_count: 1,
-                control_strength: 0.9,
-                style_ratio: 20.0,
-                normalize_input: false,
-                input_id_images_dir: "".to_string(),
-                canny_preprocess: false,
-                upscale_model: "".to_string(),
-                upscale_repeats: 1,
-                output_path: "".to_string(),
-            };
-            match self.task {
-                Task::TextToImage => Ok(Context::TextToImage(TextToImage { common: common })),
-                Task::ImageToImage => Ok(Context::ImageToImage(ImageToImage {
-                    common: common,
-                    image: ImageType::Path(""),
-                    strength: 0.75,
-                })),
+            if !Path::new(path).is_file() {
+                return Err(SdError::invalid(
+                    "upscale_model_path",
+                    format!("file not found: {}", path),
+                ));
             }
+            return Ok(self.finish());
+        }
+        if self.config.model_path.is_empty() && self.config.diffusion_model_path.is_empty() {
+            return Err(SdError::invalid(
+                "model_path",
+                "is required unless diffusion_model_path is set",
+            ));
+        }
+        let components = [
+            ("diffusion_model_path", &self.config.diffusion_model_path),
+            ("clip_l_path", &self.config.clip_l_path),
+            ("clip_g_path", &self.config.clip_g_path),
+            ("t5xxl_path", &self.config.t5xxl_path),
+        ];
+        // The WasmEdge host loads components only through the v2 imports.
+        if self.backend.is_none() && !cfg!(feature = "host-v2") {
+            if let Some((name, _)) = components.iter().find(|(_, path)| !path.is_empty()) {
+                return Err(SdError::invalid(name, "needs the `host-v2` feature"));
+            }
+        }
+        let files = [
+            ("model_path", &self.config.model_path),
+            ("vae_path", &self.config.vae_path),
+            ("taesd_path", &self.config.taesd_path),
+            ("control_net_path", &self.config.control_net_path),
+        ];
+        for (name, path) in files.into_iter().chain(components) {
+            if !path.is_empty() && !Path::new(path).is_file() {
+                return Err(SdError::invalid(name, format!("file not found: {}", path)));
+            }
+        }
+        let dirs = [
+            ("lora_model_dir", &self.config.lora_model_dir),
+            ("embed_dir", &self.config.embed_dir),
+            ("id_embed_dir", &self.config.id_embed_dir),
+        ];
+        for (name, path) in dirs {
+            if !path.is_empty() && !Path::new(path).is_dir() {
+                return Err(SdError::invalid(name, format!("directory not found: {}", path)));
+            }
+        }
+        Ok(self.finish())
+    }
+
+    fn finish(self) -> StableDiffusion {
+        let task = self.task.unwrap_or(Task::TextToImage);
+        let vae_decode_only = match task {
+            Task::TextToImage | Task::Upscale => true,
+            Task::ImageToImage | Task::Inpaint => false,
+        };
+        StableDiffusion {
+            task,
+            config: ContextConfig {
+                vae_decode_only,
+                ..self.config
+            },
+            backend: self.backend.unwrap_or_else(|| Arc::new(WasmEdgeBackend)),
         }
     }
 }
@@ -240,42 +1240,21 @@ impl<'a> BaseFunction<'a> for TextToImage<'a> {
     fn base(&mut self) -> &mut BaseContext<'a> {
         &mut self.common
     }
-    fn generate(&self) -> Result<(), WasmedgeSdErrno> {
-        if self.common.prompt.is_empty() {
-            return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
-        }
-        let mut data: Vec<u8> = Vec::new();
-        data.resize(BUF_LEN as usize, 0);
-        unsafe {
-            let result = stable_diffusion_interface::text_to_image(
-                &self.common.prompt,
-                self.common.session_id,
-                &self.common.control_image,
-                &self.common.negative_prompt,
-                self.common.width,
-                self.common.height,
-                self.common.clip_skip,
-                self.common.cfg_scale,
-                self.common.sample_method,
-                self.common.sample_steps,
-                self.common.seed,
-                self.common.batch_count,
-                self.common.control_strength,
-                self.common.style_ratio,
-                self.common.normalize_input,
-                &self.common.input_id_images_dir,
-                self.common.canny_preprocess,
-                &self.common.upscale_model,
-                self.common.upscale_repeats,
-                &self.common.output_path,
-                data.as_mut_ptr() as *mut u8,
-                BUF_LEN,
-            );
-            if let Err(code) = result {
-                return Err(code);
-            }
-            return Ok(());
+    fn params(&self) -> &GenerationParams<'a> {
+        &self.common.params
+    }
+    fn validate(&self) -> Vec<Violation> {
+        self.common.validate()
+    }
+    fn generate_images(&self) -> Result<Vec<ImageOutput>, SdError> {
+        if self.common.params.prompt.is_empty() {
+            return Err(SdError::invalid("prompt", "must not be empty"));
         }
+        reject_errors(self.validate())?;
+        let data = self.common.run(Operation::TextToImage, |params, buf, bytes_written| {
+            self.common.backend.text_to_image(self.common.session_id, params, buf, bytes_written)
+        })?;
+        self.common.outputs(&data, self.common.metadata())
     }
 }
 
@@ -283,51 +1262,178 @@ impl<'a> BaseFunction<'a> for ImageToImage<'a> {
     fn base(&mut self) -> &mut BaseContext<'a> {
         &mut self.common
     }
-    fn generate(&self) -> Result<(), WasmedgeSdErrno> {
-        if self.common.prompt.is_empty() {
-            return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+    fn params(&self) -> &GenerationParams<'a> {
+        &self.common.params
+    }
+    fn validate(&self) -> Vec<Violation> {
+        let mut violations = self.common.validate();
+        validation::check_range(&mut violations, "strength", self.strength, 0.0..=1.0);
+        violations
+    }
+    fn generate_images(&self) -> Result<Vec<ImageOutput>, SdError> {
+        if self.common.params.prompt.is_empty() {
+            return Err(SdError::invalid("prompt", "must not be empty"));
         }
-        match self.image {
-            ImageType::Path(path) => {
-                if path.is_empty() {
-                    return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
-                }
-            }
+        if self.image.is_empty() {
+            return Err(SdError::invalid("image", "an init image is required for img2img"));
         }
-        let mut data: Vec<u8> = Vec::new();
-        data.resize(BUF_LEN as usize, 0);
-        unsafe {
-            let result = stable_diffusion_interface::image_to_image(
-                &self.image,
+        reject_errors(self.validate())?;
+        let data = self.common.run(Operation::ImageToImage, |params, buf, bytes_written| {
+            self.common.backend.image_to_image(
                 self.common.session_id,
-                self.common.width,
-                self.common.height,
-                &self.common.control_image,
-                &self.common.prompt,
-                &self.common.negative_prompt,
-                self.common.clip_skip,
-                self.common.cfg_scale,
-                self.common.sample_method,
-                self.common.sample_steps,
+                &self.image,
                 self.strength,
-                self.common.seed,
-                self.common.batch_count,
-                self.common.control_strength,
-                self.common.style_ratio,
-                self.common.normalize_input,
-                &self.common.input_id_images_dir,
-                self.common.canny_preprocess,
-                &self.common.upscale_model,
-                self.common.upscale_repeats,
-                &self.common.output_path,
-                data.as_mut_ptr() as *mut u8,
-                BUF_LEN,
-            );
-            if let Err(code) = result {
-                return Err(code);
+                params,
+                buf,
+                bytes_written,
+            )
+        })?;
+        let metadata = ImageMetadata {
+            strength: Some(self.strength),
+            ..self.common.metadata()
+        };
+        self.common.outputs(&data, metadata)
+    }
+}
+impl<'a> BaseFunction<'a> for Inpaint<'a> {
+    fn base(&mut self) -> &mut BaseContext<'a> {
+        &mut self.common
+    }
+    fn params(&self) -> &GenerationParams<'a> {
+        &self.common.params
+    }
+    fn validate(&self) -> Vec<Violation> {
+        let mut violations = self.common.validate();
+        validation::check_range(&mut violations, "strength", self.strength, 0.0..=1.0);
+        if self.mask_blur < 0 {
+            violations.push(Violation::error(
+                "mask_blur",
+                format!("must not be negative, got {}", self.mask_blur),
+            ));
+        }
+        if let (Some(image), Some(mask)) = (self.image.dimensions(), self.mask.dimensions()) {
+            if image != mask {
+                violations.push(Violation::error(
+                    "mask",
+                    format!(
+                        "is {}x{} but the init image is {}x{}",
+                        mask.0, mask.1, image.0, image.1
+                    ),
+                ));
             }
-            return Ok(());
         }
+        violations
+    }
+    fn generate_images(&self) -> Result<Vec<ImageOutput>, SdError> {
+        if self.common.params.prompt.is_empty() {
+            return Err(SdError::invalid("prompt", "must not be empty"));
+        }
+        if self.image.is_empty() {
+            return Err(SdError::invalid("image", "an init image is required for inpainting"));
+        }
+        if self.mask.is_empty() {
+            return Err(SdError::invalid("mask", "a mask is required for inpainting"));
+        }
+        reject_errors(self.validate())?;
+        let data = self.common.run(Operation::Inpaint, |params, buf, bytes_written| {
+            self.common.backend.inpaint(
+                self.common.session_id,
+                &self.image,
+                &self.mask,
+                self.strength,
+                self.mask_blur,
+                self.keep_unmasked,
+                params,
+                buf,
+                bytes_written,
+            )
+        })?;
+        let metadata = ImageMetadata {
+            strength: Some(self.strength),
+            mask_blur: Some(self.mask_blur),
+            ..self.common.metadata()
+        };
+        self.common.outputs(&data, metadata)
+    }
+}
+impl<'a> Inpaint<'a> {
+    pub fn set_image(&mut self, image: ImageType<'a>) -> &mut Self {
+        self.image = image;
+        self
+    }
+    pub fn set_mask(&mut self, mask: ImageType<'a>) -> &mut Self {
+        self.mask = mask;
+        self
+    }
+    pub fn set_strength(&mut self, strength: f32) -> &mut Self {
+        self.strength = strength;
+        self
+    }
+    pub fn set_mask_blur(&mut self, mask_blur: i32) -> &mut Self {
+        self.mask_blur = mask_blur;
+        self
+    }
+    pub fn set_keep_unmasked(&mut self, keep_unmasked: bool) -> &mut Self {
+        self.keep_unmasked = keep_unmasked;
+        self
+    }
+    /// Sets the image and mask from an outpainting canvas, and the output
+    /// size to the canvas size. Call it after `set_params`, which would reset
+    /// the size.
+    pub fn set_canvas(&mut self, canvas: &'a OutpaintCanvas) -> &mut Self {
+        self.image = canvas.image();
+        self.mask = canvas.mask();
+        self.common.params.width = canvas.width as i32;
+        self.common.params.height = canvas.height as i32;
+        self
+    }
+}
+impl<'a> Upscale<'a> {
+    pub fn set_image(&mut self, image: ImageType<'a>) -> &mut Self {
+        self.image = image;
+        self
+    }
+    pub fn set_repeats(&mut self, repeats: i32) -> &mut Self {
+        self.repeats = repeats;
+        self
+    }
+    pub fn set_output_path(&mut self, output_path: &str) -> &mut Self {
+        self.output_path = output_path.to_string();
+        self
+    }
+    /// Upscales the image. The output's `seed` is 0, as nothing is sampled.
+    pub fn upscale(&self) -> Result<ImageOutput, SdError> {
+        if self.image.is_empty() {
+            return Err(SdError::invalid("image", "an image is required for upscaling"));
+        }
+        if self.repeats < 1 {
+            return Err(SdError::invalid(
+                "repeats",
+                format!("must be at least 1, got {}", self.repeats),
+            ));
+        }
+        let data = self.common.output_buffer.fill(Operation::Upscale, |buf, bytes_written| {
+            self.common.backend.upscale(
+                self.common.session_id,
+                &self.image,
+                self.repeats,
+                &self.output_path,
+                buf,
+                bytes_written,
+            )
+        })?;
+        Ok(ImageOutput {
+            bytes_written: data.len(),
+            data,
+            file_written: !self.output_path.is_empty(),
+            output_path: self.output_path.clone(),
+            seed: 0,
+        })
+    }
+    /// Frees the host session now instead of on drop, returning any error
+    /// from the host. Like [`BaseFunction::close`], it consumes the context.
+    pub fn close(mut self) -> Result<(), SdError> {
+        self.common.close()
     }
 }
 impl<'a> ImageToImage<'a> {
@@ -353,7 +1459,676 @@ impl std::str::FromStr for Task {
         match s {
             "txt2img" => Ok(Task::TextToImage),
             "img2img" => Ok(Task::ImageToImage),
+            "inpaint" => Ok(Task::Inpaint),
+            "upscale" => Ok(Task::Upscale),
             _ => Err(format!("Invalid mode: {}", s)),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::{MockBackend, MockCall};
+    use test_util::TempDir;
+
+    /// Creates a context of `sd`, which must be the `$variant` one.
+    macro_rules! context {
+        ($sd:expr, $variant:ident) => {
+            match $sd.create_context().unwrap() {
+                Context::$variant(context) => context,
+                _ => panic!(concat!("expected a ", stringify!($variant), " context")),
+            }
+        };
+    }
+
+    fn model(task: Task, backend: &Arc<MockBackend>) -> StableDiffusion {
+        StableDiffusion::builder()
+            .task(task)
+            .model_path("model.gguf")
+            .backend(backend.clone())
+            .finish()
+    }
+
+    fn session_id(context: &mut Context) -> u32 {
+        match context {
+            Context::TextToImage(text_to_image) => text_to_image.base().session_id,
+            Context::ImageToImage(image_to_image) => image_to_image.base().session_id,
+            Context::Inpaint(inpaint) => inpaint.base().session_id,
+            Context::Upscale(upscale) => upscale.common.session_id,
+        }
+    }
+
+    #[test]
+    fn every_session_is_freed() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::ImageToImage, &backend);
+        let mut dropped = sd.create_context().unwrap();
+        let mut closed = sd.create_context().unwrap();
+        let dropped_id = session_id(&mut dropped);
+        let closed_id = session_id(&mut closed);
+        assert_eq!(backend.live_sessions(), vec![dropped_id, closed_id]);
+
+        drop(dropped);
+        assert_eq!(backend.live_sessions(), vec![closed_id]);
+
+        let Context::ImageToImage(image_to_image) = closed else {
+            panic!("expected an img2img context");
+        };
+        image_to_image.close().unwrap();
+        assert!(backend.live_sessions().is_empty());
+    }
+
+    #[test]
+    fn close_reports_host_errors() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::ImageToImage, &backend);
+        let image_to_image = context!(sd, ImageToImage);
+        backend.fail_next(WASMEDGE_SD_ERRNO_BUSY);
+        assert_eq!(
+            image_to_image.close(),
+            Err(SdError::Host {
+                operation: Operation::FreeContext,
+                errno: HostErrno::Busy,
+            })
+        );
+    }
+
+    #[test]
+    fn close_reports_hosts_that_keep_sessions() {
+        let backend = Arc::new(MockBackend::new());
+        backend.keep_sessions();
+        let sd = model(Task::ImageToImage, &backend);
+        let image_to_image = context!(sd, ImageToImage);
+        assert_eq!(
+            image_to_image.close(),
+            Err(SdError::Unsupported {
+                operation: Operation::FreeContext,
+                reason: "needs the `host-v2` feature",
+            })
+        );
+        assert!(!backend.calls().iter().any(|call| matches!(call, MockCall::FreeContext { .. })));
+        assert_eq!(backend.live_sessions().len(), 1);
+    }
+
+    #[test]
+    fn create_context_passes_the_config() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = StableDiffusion::builder()
+            .task(Task::ImageToImage)
+            .model_path("model.gguf")
+            .clip_on_cpu(true)
+            .backend(backend.clone())
+            .finish();
+        let _context = sd.create_context().unwrap();
+        let MockCall::CreateContext { config, .. } = &backend.calls()[0] else {
+            panic!("expected create_context");
+        };
+        assert_eq!(config.model_path, "model.gguf");
+        assert!(config.clip_on_cpu);
+        assert!(!config.vae_on_cpu);
+        assert!(!config.vae_decode_only);
+    }
+
+    #[test]
+    fn flux_models_load_from_components() {
+        let dir = TempDir::new("flux");
+        let path = |name: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, b"").unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let (diffusion_model, clip_l, t5xxl) =
+            (path("flux1-dev.gguf"), path("clip_l.safetensors"), path("t5xxl.gguf"));
+        let builder = StableDiffusion::builder()
+            .diffusion_model_path(&diffusion_model)
+            .clip_l_path(&clip_l)
+            .t5xxl_path(&t5xxl);
+        let result = builder.clone().build();
+        if cfg!(feature = "host-v2") {
+            assert!(result.is_ok());
+        } else {
+            assert_eq!(
+                result.err(),
+                Some(SdError::invalid("diffusion_model_path", "needs the `host-v2` feature"))
+            );
+        }
+
+        let backend = Arc::new(MockBackend::new());
+        let builder = builder.backend(backend.clone());
+        assert!(matches!(
+            builder.clone().clip_g_path("missing.safetensors").build(),
+            Err(SdError::InvalidArgument { field: "clip_g_path", .. })
+        ));
+        let sd = builder.build().unwrap();
+        let mut text_to_image = context!(sd, TextToImage);
+        text_to_image
+            .set_prompt("a lovely cat")
+            .set_cfg_scale(1.0)
+            .set_distilled_guidance(2.5)
+            .generate()
+            .unwrap();
+        assert_eq!(text_to_image.common.metadata().model, "flux1-dev");
+
+        let calls = backend.calls();
+        let MockCall::CreateContext { config, .. } = &calls[0] else {
+            panic!("expected create_context");
+        };
+        assert!(config.model_path.is_empty() && config.has_components());
+        assert_eq!(config.t5xxl_path, t5xxl);
+        let MockCall::TextToImage { params, .. } = &calls[1] else {
+            panic!("expected text_to_image");
+        };
+        assert_eq!(params.guidance.distilled_guidance, 2.5);
+    }
+
+    #[test]
+    fn older_plugins_refuse_newer_samplers() {
+        let backend = Arc::new(MockBackend::new());
+        backend.set_capabilities(Capabilities::baseline());
+        let sd = StableDiffusion::builder()
+            .model_path("model.gguf")
+            .schedule(ScheduleT::GITS)
+            .backend(backend.clone())
+            .finish();
+        assert_eq!(
+            sd.create_context().err(),
+            Some(SdError::invalid("schedule", "gits is not supported by the host plugin"))
+        );
+        assert!(backend.calls().is_empty());
+
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        text_to_image.set_prompt("a lovely cat").set_sample_method(SampleMethodT::TCD);
+        assert_eq!(
+            text_to_image.generate(),
+            Err(SdError::Validation(vec![Violation::error(
+                "sample_method",
+                "tcd is not supported by the host plugin",
+            )]))
+        );
+        text_to_image.set_sample_method(SampleMethodT::LCM).set_cfg_scale(1.0).set_sample_steps(4);
+        text_to_image.generate().unwrap();
+
+        // Bits are stable-diffusion.cpp ids: lcm is 9, tcd 11 and ays 4.
+        let newer = Capabilities::from_masks(1 << 11 | 1 << 9 | 1, 1 << 4 | 1 << 5);
+        assert_eq!(
+            newer.sample_methods,
+            [SampleMethodT::EULERA, SampleMethodT::LCM, SampleMethodT::TCD]
+        );
+        assert_eq!(newer.schedules, [ScheduleT::AYS, ScheduleT::GITS]);
+        assert!(Capabilities::all().supports_schedule(ScheduleT::EXPONENTIAL));
+    }
+
+    #[test]
+    fn text_to_image_returns_the_encoded_image() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        let output = text_to_image
+            .set_prompt("a lovely cat")
+            .set_width(64)
+            .set_height(32)
+            .generate_image()
+            .unwrap();
+        assert_eq!(output.data[..8], png::SIGNATURE);
+        assert!(!output.file_written);
+        assert!(output.data.len() > output.bytes_written);
+        let parsed = ImageMetadata::read(&output.data).unwrap();
+        assert_eq!(parsed.metadata, text_to_image.common.metadata());
+
+        let MockCall::TextToImage { params, .. } = &backend.calls()[1] else {
+            panic!("expected text_to_image");
+        };
+        assert_eq!(params.prompt, "a lovely cat");
+        assert_eq!((params.width, params.height), (64, 32));
+    }
+
+    #[test]
+    fn image_to_image_sends_the_init_image() {
+        let init = png::encode(1, 1, 3, &[0, 0, 0]);
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::ImageToImage, &backend);
+        let mut image_to_image = context!(sd, ImageToImage);
+        image_to_image
+            .set_prompt("with blue eyes")
+            .set_control_image(ImageType::Path("edges.png"))
+            .set_image(ImageType::Bytes(&init))
+            .set_strength(0.5)
+            .generate()
+            .unwrap();
+        let MockCall::ImageToImage {
+            image,
+            control_image,
+            strength,
+            ..
+        } = &backend.calls()[1]
+        else {
+            panic!("expected image_to_image");
+        };
+        assert_eq!(image[..6], *b"bytes:");
+        assert_eq!(control_image, b"path:edges.png");
+        assert_eq!(*strength, 0.5);
+    }
+
+    #[test]
+    fn metadata_is_embedded_in_files_unless_disabled() {
+        let dir = TempDir::new("metadata");
+        let output_path = dir.join("cat.png");
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        text_to_image
+            .set_prompt("a lovely cat")
+            .set_width(8)
+            .set_height(8)
+            .set_output_path(output_path.to_str().unwrap());
+        let output = text_to_image.generate_image().unwrap();
+        assert!(output.file_written);
+        assert_eq!(std::fs::read(&output_path).unwrap(), output.data);
+        assert_eq!(&output.data[37..41], b"tEXt");
+        assert!(output.data[41..].starts_with(b"parameters\0a lovely cat\nSteps: 20,"));
+        let MockCall::TextToImage { params, .. } = &backend.calls()[1] else {
+            panic!("expected text_to_image");
+        };
+        assert_eq!(params.output_path, "");
+
+        std::fs::remove_file(&output_path).unwrap();
+        let output = text_to_image.set_embed_metadata(false).generate_image().unwrap();
+        assert!(output.file_written);
+        assert_eq!(output.bytes_written, output.data.len());
+        let MockCall::TextToImage { params, .. } = &backend.calls()[2] else {
+            panic!("expected text_to_image");
+        };
+        assert_eq!(params.output_path, output_path.to_str().unwrap());
+        // The host is trusted to write the file; the mock does not.
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn batches_return_every_image_with_its_seed() {
+        let dir = TempDir::new("batch");
+        let output_path = dir.join("cat.png");
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        text_to_image
+            .set_prompt("a lovely cat")
+            .set_width(8)
+            .set_height(8)
+            .set_seed(42)
+            .set_batch_count(3)
+            .set_embed_metadata(false)
+            .set_output_path(output_path.to_str().unwrap());
+        let outputs = text_to_image.generate_images().unwrap();
+        let seeds: Vec<u64> = outputs.iter().map(|output| output.seed).collect();
+        assert_eq!(seeds, vec![42, 43, 44]);
+        for (index, output) in outputs.iter().enumerate() {
+            let path = dir.join(format!("cat_{}.png", index));
+            assert_eq!(output.output_path, path.to_str().unwrap());
+            assert_eq!(std::fs::read(&path).unwrap(), output.data);
+        }
+        assert!(!output_path.exists());
+        let MockCall::TextToImage { params, .. } = &backend.calls()[1] else {
+            panic!("expected text_to_image");
+        };
+        assert_eq!(params.output_path, "");
+
+        // Each image records the seed that regenerates it on its own.
+        let outputs = text_to_image.set_embed_metadata(true).generate_images().unwrap();
+        let metadata = ImageMetadata::read(&outputs[2].data).unwrap().metadata;
+        assert_eq!((metadata.params.seed, metadata.params.batch_count), (44, 1));
+        assert_eq!(backend.calls().len(), 3);
+    }
+
+    #[test]
+    fn batches_past_the_host_seed_range_are_split() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        let first_seed = seed::HOST_SEED_MAX;
+        let outputs = text_to_image
+            .set_prompt("a lovely cat")
+            .set_width(8)
+            .set_height(8)
+            .set_seed(first_seed)
+            .set_batch_count(2)
+            .generate_images()
+            .unwrap();
+        let seeds: Vec<u64> = outputs.iter().map(|output| output.seed).collect();
+        assert_eq!(seeds, vec![first_seed, first_seed + 1]);
+        let sent: Vec<(u64, i32)> = backend.calls()[1..]
+            .iter()
+            .map(|call| match call {
+                MockCall::TextToImage { params, .. } => (params.seed, params.batch_count),
+                _ => panic!("expected text_to_image"),
+            })
+            .collect();
+        assert_eq!(sent, vec![(first_seed, 1), (first_seed + 1, 1)]);
+    }
+
+    #[test]
+    fn output_buffer_grows_to_fit() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::TextToImage, &backend);
+        let mut text_to_image = context!(sd, TextToImage);
+        text_to_image.set_prompt("a lovely cat").set_width(1024).set_height(1024);
+        let output = text_to_image.generate_image().unwrap();
+        assert!(output.data.len() > DEFAULT_OUTPUT_BUFFER_LEN);
+        assert!(text_to_image.common.output_buffer.capacity() >= output.bytes_written);
+        // The grown buffer is reused, so the second call fits first time.
+        text_to_image.generate_image().unwrap();
+        assert_eq!(backend.calls().len(), 4);
+
+        text_to_image.set_output_buffer_limit(1 << 10);
+        assert!(matches!(
+            text_to_image.generate_image(),
+            Err(SdError::OutputTooLarge {
+                operation: Operation::TextToImage,
+                needed: Some(_),
+                limit: 1024,
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_inputs_name_the_field() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::ImageToImage, &backend);
+        let mut image_to_image = context!(sd, ImageToImage);
+        assert_eq!(
+            image_to_image.generate(),
+            Err(SdError::invalid("prompt", "must not be empty"))
+        );
+        image_to_image.set_prompt("with blue eyes");
+        assert!(matches!(
+            image_to_image.generate(),
+            Err(SdError::InvalidArgument { field: "image", .. })
+        ));
+        assert!(matches!(
+            StableDiffusion::builder().build(),
+            Err(SdError::InvalidArgument { field: "model_path", .. })
+        ));
+    }
+
+    #[test]
+    fn generate_refuses_invalid_parameters() {
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::ImageToImage, &backend);
+        let mut image_to_image = context!(sd, ImageToImage);
+        image_to_image
+            .set_prompt("with blue eyes")
+            .set_image(ImageType::Path("input.png"))
+            .set_width(500)
+            .set_strength(1.5);
+        let violations = image_to_image.validate();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(image_to_image.generate(), Err(SdError::Validation(violations)));
+        // Nothing past create_context reached the host.
+        assert_eq!(backend.calls().len(), 1);
+
+        image_to_image
+            .set_width(512)
+            .set_strength(0.75)
+            .set_sample_method(SampleMethodT::LCM);
+        assert!(!image_to_image.validate().is_empty());
+        image_to_image.generate().unwrap();
+    }
+
+    #[test]
+    fn inpaint_sends_the_image_and_mask() {
+        let init = png::encode(16, 8, 3, &[0; 16 * 8 * 3]);
+        let mask = png::encode(16, 8, 1, &[255; 16 * 8]);
+        let small_mask = png::encode(8, 8, 1, &[255; 8 * 8]);
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::Inpaint, &backend);
+        let mut inpaint = context!(sd, Inpaint);
+        inpaint.set_prompt("a red hat").set_image(ImageType::Bytes(&init));
+        assert!(matches!(
+            inpaint.generate(),
+            Err(SdError::InvalidArgument { field: "mask", .. })
+        ));
+
+        inpaint.set_mask(ImageType::Bytes(&small_mask));
+        let violations = inpaint.validate();
+        assert_eq!(violations[0].to_string(), "error: mask: is 8x8 but the init image is 16x8");
+        assert_eq!(inpaint.generate(), Err(SdError::Validation(violations)));
+
+        inpaint
+            .set_mask(ImageType::Bytes(&mask))
+            .set_mask_blur(2)
+            .set_keep_unmasked(true)
+            .generate()
+            .unwrap();
+        let MockCall::Inpaint {
+            image,
+            mask: sent_mask,
+            mask_blur,
+            keep_unmasked,
+            ..
+        } = &backend.calls()[1]
+        else {
+            panic!("expected inpaint");
+        };
+        assert_eq!(image[6..], init[..]);
+        assert_eq!(sent_mask[6..], mask[..]);
+        assert_eq!((*mask_blur, *keep_unmasked), (2, true));
+        assert!(!sd.config().vae_decode_only);
+    }
+
+    #[test]
+    fn outpaint_runs_as_an_inpaint() {
+        let source = png::encode(64, 64, 3, &[128; 64 * 64 * 3]);
+        let canvas = Outpaint::to_aspect(64, 64, 16, 9)
+            .unwrap()
+            .prepare(&ImageType::Bytes(&source))
+            .unwrap();
+        let backend = Arc::new(MockBackend::new());
+        let sd = model(Task::Inpaint, &backend);
+        let mut inpaint = context!(sd, Inpaint);
+        inpaint.set_prompt("a wide landscape").set_canvas(&canvas).generate().unwrap();
+        let MockCall::Inpaint { params, image, mask, .. } = &backend.calls()[1] else {
+            panic!("expected inpaint");
+        };
+        assert_eq!((params.width, params.height), (120, 64));
+        assert_eq!(image[4..16], [120, 0, 0, 0, 64, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(mask[4..16], [120, 0, 0, 0, 64, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn upscale_loads_only_the_upscaler() {
+        let source = png::encode(2, 3, 3, &[0; 2 * 3 * 3]);
+        // Widths in the PNG header that overflow or pass the mock's limit.
+        let huge: Vec<_> = [40_000u32, 1 << 24]
+            .iter()
+            .map(|width| {
+                let mut huge = source.clone();
+                huge[16..20].copy_from_slice(&width.to_be_bytes());
+                huge
+            })
+            .collect();
+        let backend = Arc::new(MockBackend::new());
+        let sd = StableDiffusion::builder()
+            .task(Task::Upscale)
+            .upscale_model_path("models/RealESRGAN_x4plus.pth")
+            .backend(backend.clone())
+            .finish();
+        let mut upscale = context!(sd, Upscale);
+        assert!(matches!(
+            upscale.upscale(),
+            Err(SdError::InvalidArgument { field: "image", .. })
+        ));
+        let output = upscale
+            .set_image(ImageType::Bytes(&source))
+            .set_repeats(2)
+            .set_output_path("out/big.png")
+            .upscale()
+            .unwrap();
+        assert_eq!(ImageType::Bytes(&output.data).dimensions(), Some((32, 48)));
+        assert!(output.file_written);
+
+        let calls = backend.calls();
+        let MockCall::CreateUpscalerContext { config, .. } = &calls[0] else {
+            panic!("expected an upscaler context, got {:?}", calls[0]);
+        };
+        assert_eq!(config.upscale_model_path, "models/RealESRGAN_x4plus.pth");
+        let MockCall::Upscale { upscale_repeats, output_path, .. } = &calls[1] else {
+            panic!("expected upscale");
+        };
+        assert_eq!((*upscale_repeats, output_path.as_str()), (2, "out/big.png"));
+
+        for huge in &huge {
+            assert_eq!(
+                upscale.set_image(ImageType::Bytes(huge)).set_repeats(4).upscale().err(),
+                Some(SdError::Host {
+                    operation: Operation::Upscale,
+                    errno: HostErrno::InvalidArgument,
+                })
+            );
+        }
+
+        upscale.close().unwrap();
+        assert!(backend.live_sessions().is_empty());
+        let builder = StableDiffusion::builder().task(Task::Upscale);
+        assert!(matches!(
+            builder.clone().backend(backend.clone()).build(),
+            Err(SdError::InvalidArgument { field: "upscale_model_path", .. })
+        ));
+        if !cfg!(feature = "host-v2") {
+            assert_eq!(
+                builder.build().err(),
+                Some(SdError::invalid("task", "needs the `host-v2` feature"))
+            );
+        }
+    }
+
+    /// Writes a safetensors file holding one 2x64 f32 tensor per name.
+    fn safetensors_model(path: &Path, names: &[&str]) -> Vec<u8> {
+        let entries: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                format!(
+                    r#""{}":{{"dtype":"F32","shape":[2,64],"data_offsets":[{},{}]}}"#,
+                    name,
+                    i * 512,
+                    (i + 1) * 512
+                )
+            })
+            .collect();
+        let header = format!("{{{}}}", entries.join(","));
+        let mut model = (header.len() as u64).to_le_bytes().to_vec();
+        model.extend_from_slice(header.as_bytes());
+        model.resize(model.len() + names.len() * 512, 0);
+        std::fs::write(path, &model).unwrap();
+        model
+    }
+
+    #[test]
+    fn quantization_runs_on_the_backend() {
+        let dir = TempDir::new("convert");
+        let model_path = dir.join("sd-v1-4.safetensors");
+        let model = safetensors_model(&model_path, &["model.diffusion_model.proj.weight"]);
+        let model_path = model_path.to_str().unwrap();
+
+        let backend = Arc::new(MockBackend::new());
+        let quantization = Quantization::new(model_path, "sd-v1-4-Q8_0.gguf", SdTypeT::SdTypeQ8_0)
+            .with_backend(backend.clone());
+        assert_eq!(quantization.estimate_size().unwrap(), 2 * 2 * 34);
+        quantization.convert().unwrap();
+        assert_eq!(
+            backend.calls(),
+            vec![MockCall::Convert {
+                model_path: model_path.to_string(),
+                vae_model_path: "".to_string(),
+                output_path: "sd-v1-4-Q8_0.gguf".to_string(),
+                wtype: SdTypeT::SdTypeQ8_0,
+            }]
+        );
+
+        // Damaged or missing inputs never reach the host.
+        std::fs::write(dir.join("truncated.safetensors"), &model[..model.len() - 1]).unwrap();
+        let truncated = Quantization::new(
+            dir.join("truncated.safetensors").to_str().unwrap(),
+            "out.gguf",
+            SdTypeT::SdTypeQ8_0,
+        )
+        .with_backend(backend.clone());
+        let err = truncated.convert().unwrap_err();
+        assert!(matches!(err, SdError::InvalidArgument { field: "model_path", .. }));
+        assert!(err.to_string().ends_with("has data past the end of the file"), "{}", err);
+        let mut missing_vae = Quantization::new(model_path, "out.gguf", SdTypeT::SdTypeQ8_0)
+            .with_backend(backend.clone());
+        missing_vae.vae_model_path = dir.join("vae.ckpt").to_str().unwrap().to_string();
+        assert!(matches!(
+            missing_vae.convert(),
+            Err(SdError::InvalidArgument { field: "vae_model_path", .. })
+        ));
+        assert_eq!(backend.calls().len(), 1);
+    }
+
+    #[test]
+    fn quantization_converts_a_model_matrix() {
+        let dir = TempDir::new("matrix");
+        let model_path = dir.join("model.safetensors");
+        let vae_path = dir.join("vae.safetensors");
+        safetensors_model(
+            &model_path,
+            &["model.diffusion_model.out.2.weight", "model.diffusion_model.middle_block.weight"],
+        );
+        safetensors_model(&vae_path, &["first_stage_model.decoder.conv_in.weight"]);
+        let (model_path, vae_path) = (model_path.to_str().unwrap(), vae_path.to_str().unwrap());
+
+        let backend = Arc::new(MockBackend::new());
+        let quantization = Quantization::new(model_path, "unused.gguf", SdTypeT::SdTypeQ4K)
+            .with_backend(backend.clone())
+            .with_vae_model_path(vae_path)
+            .with_precision_plan(PrecisionPlan::keep_edges(SdTypeT::SdTypeF16));
+        // Rows of 64 elements are not whole q4_k blocks, so only q8_0
+        // quantizes the middle block.
+        assert_eq!(quantization.estimate_size().unwrap(), 3 * 512 - 2 * 256);
+        assert_eq!(
+            quantization.estimate_size_as(SdTypeT::SdTypeQ8_0).unwrap(),
+            256 + 2 * 2 * 34 + 256
+        );
+        quantization
+            .convert_all(&[("model-q8_0.gguf", SdTypeT::SdTypeQ8_0), ("model-q4_k.gguf", SdTypeT::SdTypeQ4K)])
+            .unwrap();
+        let rules = r"^first_stage_model\.=f16,^model\.diffusion_model\.input_blocks\.0\.=f16,^model\.diffusion_model\.out\.=f16";
+        assert_eq!(
+            backend.calls(),
+            [("model-q8_0.gguf", SdTypeT::SdTypeQ8_0), ("model-q4_k.gguf", SdTypeT::SdTypeQ4K)]
+                .map(|(output_path, wtype)| MockCall::ConvertWithRules {
+                    model_path: model_path.to_string(),
+                    vae_model_path: vae_path.to_string(),
+                    output_path: output_path.to_string(),
+                    wtype,
+                    tensor_type_rules: rules.to_string(),
+                })
+        );
+
+        let on_host = Quantization::new(model_path, "out.gguf", SdTypeT::SdTypeQ4K)
+            .with_precision_plan(PrecisionPlan::keep_edges(SdTypeT::SdTypeF16));
+        if cfg!(feature = "host-v2") {
+            assert!(matches!(on_host.convert(), Err(SdError::Host { .. })));
+        } else {
+            assert_eq!(
+                on_host.convert(),
+                Err(SdError::invalid("precision_plan", "needs the `host-v2` feature"))
+            );
+        }
+
+        let bad_plan = Quantization::new(model_path, "out.gguf", SdTypeT::SdTypeQ4K)
+            .with_backend(backend.clone())
+            .with_precision_plan(PrecisionPlan::new().rule("vae.", SdTypeT::SdTypeCount));
+        assert!(matches!(
+            bad_plan.convert(),
+            Err(SdError::InvalidArgument { field: "precision_plan", .. })
+        ));
+        backend.fail_next(WASMEDGE_SD_ERRNO_RUNTIME_ERROR);
+        let err = quantization
+            .convert_all(&[("a.gguf", SdTypeT::SdTypeQ8_0), ("b.gguf", SdTypeT::SdTypeF16)])
+            .unwrap_err();
+        assert_eq!(err.errno(), Some(HostErrno::RuntimeError));
+        assert_eq!(backend.calls().len(), 3);
+    }
+}
@@ -0,0 +1,208 @@
+//! Conversion of guest arguments into the `(pointer, length)` pairs taken by
+//! the `wasmedge_stablediffusion` host functions.
+//!
+//! Every [`HostSlice`] borrows the data it points to, so the borrow checker
+//! keeps the data alive for as long as the pointer can be handed to the host.
+//! Build the slices as locals of the wrapper that makes the host call.
+use crate::stable_diffusion_interface::{
+    ImageType, WasmedgeSdErrno, WASMEDGE_SD_ERRNO_INVALID_ARGUMENT,
+    WASMEDGE_SD_ERRNO_INVALID_ENCODING,
+};
+use core::marker::PhantomData;
+
+/// Converts a guest length to the host's `i32`, rejecting lengths that do not
+/// fit.
+pub(crate) fn host_len(len: usize) -> Result<i32, WasmedgeSdErrno> {
+    i32::try_from(len).map_err(|_| WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
+}
+
+/// Read-only buffer passed to the host.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct HostSlice<'a> {
+    ptr: *const u8,
+    len: i32,
+    _data: PhantomData<&'a [u8]>,
+}
+
+impl<'a> HostSlice<'a> {
+    /// Arbitrary bytes. An empty slice is passed as a null pointer.
+    pub(crate) fn bytes(data: &'a [u8]) -> Result<HostSlice<'a>, WasmedgeSdErrno> {
+        let len = host_len(data.len())?;
+        let ptr = if data.is_empty() {
+            core::ptr::null()
+        } else {
+            data.as_ptr()
+        };
+        Ok(HostSlice {
+            ptr,
+            len,
+            _data: PhantomData,
+        })
+    }
+
+    /// A string the host turns into a C string, such as a path or a prompt.
+    /// Interior NULs would silently truncate it on the host, so they are
+    /// rejected with `INVALID_ENCODING`.
+    pub(crate) fn c_str(text: &'a str) -> Result<HostSlice<'a>, WasmedgeSdErrno> {
+        if text.as_bytes().contains(&0) {
+            return Err(WASMEDGE_SD_ERRNO_INVALID_ENCODING);
+        }
+        HostSlice::bytes(text.as_bytes())
+    }
+
+    pub(crate) fn raw(&self) -> (*const u8, i32) {
+        (self.ptr, self.len)
+    }
+}
+
+/// Writable buffer the host fills, such as the output image buffer.
+#[derive(Debug)]
+pub(crate) struct HostSliceMut<'a> {
+    ptr: *mut u8,
+    len: i32,
+    _data: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> HostSliceMut<'a> {
+    pub(crate) fn new(data: &'a mut [u8]) -> Result<HostSliceMut<'a>, WasmedgeSdErrno> {
+        Ok(HostSliceMut {
+            len: host_len(data.len())?,
+            ptr: data.as_mut_ptr(),
+            _data: PhantomData,
+        })
+    }
+
+    pub(crate) fn raw(&self) -> (*mut u8, i32) {
+        (self.ptr, self.len)
+    }
+}
+
+/// Encodes `image` with its prefix. An empty image encodes to an empty buffer.
+///
+/// See [`ImageType`] for the layout of each variant.
+pub(crate) fn encode_image(image: &ImageType) -> Result<Vec<u8>, WasmedgeSdErrno> {
+    if image.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut encoded = Vec::new();
+    match *image {
+        ImageType::Path(path) => {
+            if path.as_bytes().contains(&0) {
+                return Err(WASMEDGE_SD_ERRNO_INVALID_ENCODING);
+            }
+            encoded.extend_from_slice(b"path:");
+            encoded.extend_from_slice(path.as_bytes());
+        }
+        ImageType::Bytes(data) => {
+            encoded.extend_from_slice(b"bytes:");
+            encoded.extend_from_slice(data);
+        }
+        ImageType::Rgb {
+            width,
+            height,
+            channels,
+            data,
+        } => {
+            let expected = (width as u64) * (height as u64) * (channels as u64);
+            if !(1..=4).contains(&channels) || data.len() as u64 != expected {
+                return Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT);
+            }
+            encoded.reserve(16 + data.len());
+            encoded.extend_from_slice(b"rgb:");
+            encoded.extend_from_slice(&width.to_le_bytes());
+            encoded.extend_from_slice(&height.to_le_bytes());
+            encoded.extend_from_slice(&channels.to_le_bytes());
+            encoded.extend_from_slice(data);
+        }
+    }
+    Ok(encoded)
+}
+
+/// Encodes `values` as consecutive little-endian `i32`s.
+#[cfg(any(test, feature = "host-v2"))]
+pub(crate) fn encode_i32s(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_must_fit_in_i32() {
+        assert_eq!(host_len(0), Ok(0));
+        assert_eq!(host_len(i32::MAX as usize), Ok(i32::MAX));
+        assert_eq!(
+            host_len(i32::MAX as usize + 1),
+            Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
+        );
+    }
+
+    #[test]
+    fn slices_point_at_the_borrowed_data() {
+        let prompt = String::from("a lovely cat");
+        let (ptr, len) = HostSlice::c_str(&prompt).unwrap().raw();
+        assert_eq!(ptr, prompt.as_ptr());
+        assert_eq!(len, 12);
+
+        let (ptr, len) = HostSlice::bytes(&[]).unwrap().raw();
+        assert!(ptr.is_null());
+        assert_eq!(len, 0);
+
+        let mut out = vec![0u8; 16];
+        let out_ptr = out.as_mut_ptr();
+        let (ptr, len) = HostSliceMut::new(&mut out).unwrap().raw();
+        assert_eq!((ptr, len), (out_ptr, 16));
+    }
+
+    #[test]
+    fn c_strings_reject_interior_nul() {
+        assert_eq!(
+            HostSlice::c_str("model\0.gguf").unwrap_err(),
+            WASMEDGE_SD_ERRNO_INVALID_ENCODING
+        );
+        assert_eq!(
+            encode_image(&ImageType::Path("in\0put.png")),
+            Err(WASMEDGE_SD_ERRNO_INVALID_ENCODING)
+        );
+        // Encoded image data may contain NULs.
+        assert!(HostSlice::bytes(b"\x89PNG\0\0").is_ok());
+    }
+
+    #[test]
+    fn images_are_prefixed() {
+        assert_eq!(encode_image(&ImageType::Path("")).unwrap(), b"");
+        assert_eq!(encode_image(&ImageType::Path("a.png")).unwrap(), b"path:a.png");
+        assert_eq!(encode_image(&ImageType::Bytes(b"\x89PNG")).unwrap(), b"bytes:\x89PNG");
+
+        let pixels = [1u8, 2, 3, 4, 5, 6];
+        let rgb = ImageType::Rgb {
+            width: 2,
+            height: 1,
+            channels: 3,
+            data: &pixels,
+        };
+        let mut expected = b"rgb:".to_vec();
+        expected.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0]);
+        expected.extend_from_slice(&pixels);
+        assert_eq!(encode_image(&rgb).unwrap(), expected);
+    }
+
+    #[test]
+    fn integers_are_little_endian() {
+        assert_eq!(encode_i32s(&[]), b"");
+        assert_eq!(encode_i32s(&[7, -1]), [7, 0, 0, 0, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn rgb_size_must_match_its_pixels() {
+        let pixels = [0u8; 5];
+        let rgb = ImageType::Rgb {
+            width: 2,
+            height: 1,
+            channels: 3,
+            data: &pixels,
+        };
+        assert_eq!(encode_image(&rgb), Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT));
+    }
+}
@@ -0,0 +1,597 @@
+//! Generation settings recorded in output images.
+//!
+//! Images get a `parameters` text chunk in the format written by the
+//! AUTOMATIC1111 web UI, which most image viewers and diffusion tools read:
+//!
+//! ```text
+//! a lovely cat
+//! Negative prompt: blurry
+//! Steps: 20, Sampler: Euler a, CFG scale: 7, Seed: 42, Size: 512x512, Model: sd-v1-4
+//! ```
+//!
+//! That format only covers the common settings. With the `serde` feature the
+//! complete [`ImageMetadata`] is also stored as JSON under [`JSON_KEYWORD`],
+//! and [`ImageMetadata::read`] prefers it when present.
+use crate::png;
+use crate::stable_diffusion_interface::{SampleMethodT, ScheduleT};
+use crate::{
+    BaseFunction, Context, GenerationParams, SdError, StableDiffusion, StableDiffusionBuilder,
+    Task, DEFAULT_GUIDANCE,
+};
+use core::fmt;
+use std::path::Path;
+
+/// Keyword of the A1111 text chunk.
+pub const PARAMETERS_KEYWORD: &str = "parameters";
+/// Keyword of the JSON text chunk written with the `serde` feature.
+pub const JSON_KEYWORD: &str = "wasmedge-sd";
+
+/// Settings that produced an image.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageMetadata {
+    pub params: GenerationParams<'static>,
+    /// Model name, the file stem of the model path.
+    pub model: String,
+    pub schedule: ScheduleT,
+    /// img2img strength; `None` for txt2img.
+    pub strength: Option<f32>,
+    /// Inpainting mask blur; `None` for the other tasks.
+    pub mask_blur: Option<i32>,
+}
+
+impl ImageMetadata {
+    /// The A1111 `parameters` text.
+    pub fn to_parameters(&self) -> String {
+        let params = &self.params;
+        let mut text = params.prompt.clone();
+        if !params.negative_prompt.is_empty() {
+            text.push_str("\nNegative prompt: ");
+            text.push_str(&params.negative_prompt);
+        }
+        let mut fields = vec![
+            ("Steps", params.sample_steps.to_string()),
+            ("Sampler", sampler_label(params.sample_method).to_string()),
+        ];
+        if let Some(label) = schedule_label(self.schedule) {
+            fields.push(("Schedule type", label.to_string()));
+        }
+        let guidance = &params.guidance;
+        fields.push(("CFG scale", guidance.cfg_scale.to_string()));
+        if let Some(img_cfg_scale) = guidance.img_cfg_scale {
+            fields.push(("Image CFG scale", img_cfg_scale.to_string()));
+        }
+        if guidance.distilled_guidance != DEFAULT_GUIDANCE {
+            fields.push(("Distilled CFG Scale", guidance.distilled_guidance.to_string()));
+        }
+        if params.eta != 0.0 {
+            fields.push(("Eta", params.eta.to_string()));
+        }
+        fields.push(("Seed", params.seed.to_string()));
+        fields.push(("Size", format!("{}x{}", params.width, params.height)));
+        if !self.model.is_empty() {
+            fields.push(("Model", self.model.clone()));
+        }
+        if let Some(strength) = self.strength {
+            fields.push(("Denoising strength", strength.to_string()));
+        }
+        if let Some(mask_blur) = self.mask_blur {
+            fields.push(("Mask blur", mask_blur.to_string()));
+        }
+        if params.clip_skip > 0 {
+            fields.push(("Clip skip", params.clip_skip.to_string()));
+        }
+        if params.batch_count > 1 {
+            fields.push(("Batch size", params.batch_count.to_string()));
+        }
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|(key, value)| format!("{}: {}", key, quote(&value)))
+            .collect();
+        text.push('\n');
+        text.push_str(&fields.join(", "));
+        text
+    }
+
+    /// Returns `image` with a `parameters` chunk, or `None` if it is not a PNG.
+    pub fn embed(&self, image: &[u8]) -> Option<Vec<u8>> {
+        #[cfg(feature = "serde")]
+        let image = &png::insert_text(image, JSON_KEYWORD, &self.to_json().ok()?)?;
+        png::insert_text(image, PARAMETERS_KEYWORD, &self.to_parameters())
+    }
+
+    /// Reads the settings embedded in a PNG, from the JSON chunk if there is
+    /// one and the `serde` feature is on, otherwise from the A1111 chunk.
+    pub fn read(image: &[u8]) -> Result<ParsedMetadata, SdError> {
+        if png::chunks(image).is_none() {
+            return Err(SdError::format("PNG", "missing PNG signature"));
+        }
+        let texts = png::text_chunks(image);
+        let find = |keyword: &str| {
+            texts
+                .iter()
+                .find(|(key, _)| key == keyword)
+                .map(|(_, text)| text.as_str())
+        };
+        #[cfg(feature = "serde")]
+        if let Some(json) = find(JSON_KEYWORD) {
+            return Ok(ParsedMetadata {
+                metadata: ImageMetadata::from_json(json)?,
+                unmapped: Vec::new(),
+            });
+        }
+        match find(PARAMETERS_KEYWORD) {
+            Some(text) => Ok(ImageMetadata::from_parameters(text)),
+            None => Err(SdError::format("PNG", "no generation parameters embedded")),
+        }
+    }
+
+    /// Parses A1111 `parameters` text. Settings without an equivalent here are
+    /// listed in [`ParsedMetadata::unmapped`]; the ones not in the text keep
+    /// their defaults.
+    pub fn from_parameters(text: &str) -> ParsedMetadata {
+        let mut metadata = ImageMetadata {
+            params: GenerationParams::default(),
+            model: String::new(),
+            schedule: ScheduleT::DEFAULT,
+            strength: None,
+            mask_blur: None,
+        };
+        let mut unmapped = Vec::new();
+        let mut lines: Vec<&str> = text.lines().collect();
+        let fields = match lines.last().map(|line| parse_fields(line)) {
+            Some(fields) if fields.iter().any(|(key, _)| key == "Steps") => {
+                lines.pop();
+                fields
+            }
+            _ => Vec::new(),
+        };
+        let negative = lines
+            .iter()
+            .position(|line| line.starts_with("Negative prompt:"))
+            .unwrap_or(lines.len());
+        metadata.params.prompt = lines[..negative].join("\n");
+        if let Some((first, rest)) = lines[negative..].split_first() {
+            let first = first["Negative prompt:".len()..].trim_start();
+            metadata.params.negative_prompt =
+                core::iter::once(first).chain(rest.iter().copied()).collect::<Vec<_>>().join("\n");
+        }
+
+        let mut schedule_from_sampler = None;
+        for (key, value) in fields {
+            let params = &mut metadata.params;
+            let mapped = match key.as_str() {
+                "Steps" => parse_into(&value, &mut params.sample_steps),
+                "CFG scale" => parse_into(&value, &mut params.guidance.cfg_scale),
+                "Image CFG scale" => value
+                    .parse()
+                    .map(|scale| params.guidance.img_cfg_scale = Some(scale))
+                    .map_err(|_| "invalid value"),
+                "Distilled CFG Scale" => parse_into(&value, &mut params.guidance.distilled_guidance),
+                "Eta" => parse_into(&value, &mut params.eta),
+                "Seed" => parse_into(&value, &mut params.seed),
+                "Clip skip" => parse_into(&value, &mut params.clip_skip),
+                "Batch size" => parse_into(&value, &mut params.batch_count),
+                "Size" => match value.split_once('x') {
+                    Some((width, height)) => match (width.parse(), height.parse()) {
+                        (Ok(width), Ok(height)) => {
+                            (params.width, params.height) = (width, height);
+                            Ok(())
+                        }
+                        _ => Err("invalid value"),
+                    },
+                    None => Err("invalid value"),
+                },
+                "Sampler" => match parse_sampler(&value) {
+                    Some((method, schedule)) => {
+                        params.sample_method = method;
+                        schedule_from_sampler = schedule;
+                        Ok(())
+                    }
+                    None => Err("unknown sampler"),
+                },
+                "Schedule type" => match parse_schedule(&value) {
+                    Some(schedule) => {
+                        metadata.schedule = schedule;
+                        Ok(())
+                    }
+                    None => Err("unknown schedule"),
+                },
+                "Model" => {
+                    metadata.model = value.clone();
+                    Ok(())
+                }
+                "Denoising strength" => {
+                    let mut strength = 0.0;
+                    let parsed = parse_into(&value, &mut strength);
+                    metadata.strength = parsed.is_ok().then_some(strength);
+                    parsed
+                }
+                "Mask blur" => {
+                    let mut mask_blur = 0;
+                    let parsed = parse_into(&value, &mut mask_blur);
+                    metadata.mask_blur = parsed.is_ok().then_some(mask_blur);
+                    parsed
+                }
+                _ => Err("not supported"),
+            };
+            if let Err(reason) = mapped {
+                unmapped.push(UnmappedField {
+                    key,
+                    value,
+                    reason: reason.to_string(),
+                });
+            }
+        }
+        // Older A1111 versions fold the schedule into the sampler name.
+        if metadata.schedule == ScheduleT::DEFAULT {
+            if let Some(schedule) = schedule_from_sampler {
+                metadata.schedule = schedule;
+            }
+        }
+        ParsedMetadata { metadata, unmapped }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(text: &str) -> Result<ImageMetadata, SdError> {
+        serde_json::from_str(text).map_err(|err| SdError::format("image metadata JSON", err))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, SdError> {
+        serde_json::to_string(self).map_err(|err| SdError::format("image metadata JSON", err))
+    }
+
+    /// `Inpaint` if the image records a mask blur, `ImageToImage` if it
+    /// records a denoising strength.
+    pub fn task(&self) -> Task {
+        match (self.mask_blur, self.strength) {
+            (Some(_), _) => Task::Inpaint,
+            (None, Some(_)) => Task::ImageToImage,
+            (None, None) => Task::TextToImage,
+        }
+    }
+
+    /// Builder reproducing the model setup. Only the model name is recorded,
+    /// so the caller supplies the path.
+    pub fn builder(&self, model_path: &str) -> StableDiffusionBuilder {
+        StableDiffusion::builder()
+            .task(self.task())
+            .model_path(model_path)
+            .schedule(self.schedule)
+    }
+
+    /// Replaces the generation settings of `context` with the recorded ones.
+    /// The init image (and mask) still have to be set for img2img and
+    /// inpainting.
+    pub fn apply(&self, context: &mut Context) {
+        match context {
+            Context::TextToImage(text_to_image) => {
+                text_to_image.set_params(self.params.clone());
+            }
+            Context::ImageToImage(image_to_image) => {
+                image_to_image.set_params(self.params.clone());
+                if let Some(strength) = self.strength {
+                    image_to_image.set_strength(strength);
+                }
+            }
+            Context::Inpaint(inpaint) => {
+                inpaint.set_params(self.params.clone());
+                if let Some(strength) = self.strength {
+                    inpaint.set_strength(strength);
+                }
+                if let Some(mask_blur) = self.mask_blur {
+                    inpaint.set_mask_blur(mask_blur);
+                }
+            }
+            // Upscaling takes no generation settings.
+            Context::Upscale(_) => {}
+        }
+    }
+}
+
+/// Settings read from an image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedMetadata {
+    pub metadata: ImageMetadata,
+    /// Settings found in the image that could not be mapped.
+    pub unmapped: Vec<UnmappedField>,
+}
+
+/// A setting from an image's metadata that was not applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnmappedField {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for UnmappedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.key, self.value, self.reason)
+    }
+}
+
+fn parse_into<T: core::str::FromStr>(value: &str, slot: &mut T) -> Result<(), &'static str> {
+    *slot = value.parse().map_err(|_| "invalid value")?;
+    Ok(())
+}
+
+/// Splits an A1111 settings line into `(key, value)` pairs, unquoting quoted
+/// values. Stops at the first piece without a `key: `.
+fn parse_fields(line: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start_matches([',', ' ']);
+        let Some((key, after)) = rest.split_once(':') else {
+            break;
+        };
+        let after = after.trim_start();
+        let value;
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(i + 1);
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => unquoted.push('\n'),
+                        Some((_, c)) => unquoted.push(c),
+                        None => {}
+                    },
+                    c => unquoted.push(c),
+                }
+            }
+            let Some(end) = end else {
+                break;
+            };
+            value = unquoted;
+            rest = &quoted[end..];
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            value = after[..end].trim().to_string();
+            rest = &after[end..];
+        }
+        fields.push((key.trim().to_string(), value));
+    }
+    fields
+}
+
+/// Sampler from an A1111 label or one of our names, along with the schedule
+/// of labels such as `DPM++ 2M Karras`.
+fn parse_sampler(label: &str) -> Option<(SampleMethodT, Option<ScheduleT>)> {
+    if let Some(method) = SampleMethodT::from_name(label) {
+        return Some((method, None));
+    }
+    let (label, schedule) = match label.strip_suffix(" Karras") {
+        Some(label) => (label, Some(ScheduleT::KARRAS)),
+        None => (label, None),
+    };
+    SampleMethodT::all()
+        .find(|&method| sampler_label(method) == label)
+        .map(|method| (method, schedule))
+}
+
+fn parse_schedule(label: &str) -> Option<ScheduleT> {
+    if label == "Automatic" {
+        return Some(ScheduleT::DEFAULT);
+    }
+    ScheduleT::from_name(label).or_else(|| {
+        ScheduleT::all().find(|&schedule| schedule_label(schedule) == Some(label))
+    })
+}
+
+/// Quotes values that would otherwise split the field list, the way A1111 does.
+fn quote(value: &str) -> String {
+    if !value.contains([',', ':', '\n', '"']) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A1111 name of a sampler.
+pub(crate) fn sampler_label(method: SampleMethodT) -> &'static str {
+    match method {
+        SampleMethodT::EULERA => "Euler a",
+        SampleMethodT::EULER => "Euler",
+        SampleMethodT::HEUN => "Heun",
+        SampleMethodT::DPM2 => "DPM2",
+        SampleMethodT::DPMPP2SA => "DPM++ 2S a",
+        SampleMethodT::DPMPP2M => "DPM++ 2M",
+        SampleMethodT::DPMPP2Mv2 => "DPM++ 2M v2",
+        SampleMethodT::LCM => "LCM",
+        SampleMethodT::IPNDM => "iPNDM",
+        SampleMethodT::IPNDMV => "iPNDM v",
+        SampleMethodT::DDIMTRAILING => "DDIM Trailing",
+        SampleMethodT::TCD => "TCD",
+        SampleMethodT::NSAMPLEMETHODS => "Unknown",
+    }
+}
+
+/// A1111 name of a schedule; the model's default schedule is left out.
+pub(crate) fn schedule_label(schedule: ScheduleT) -> Option<&'static str> {
+    match schedule {
+        ScheduleT::DISCRETE => Some("Discrete"),
+        ScheduleT::KARRAS => Some("Karras"),
+        ScheduleT::AYS => Some("Align Your Steps"),
+        ScheduleT::EXPONENTIAL => Some("Exponential"),
+        ScheduleT::GITS => Some("GITS"),
+        ScheduleT::DEFAULT | ScheduleT::NSCHEDULES => None,
+    }
+}
+
+/// Name recorded for the model at `model_path`.
+pub(crate) fn model_name(model_path: &str) -> String {
+    Path::new(model_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GuidanceParams;
+
+    #[test]
+    fn writes_the_a1111_format() {
+        let metadata = ImageMetadata {
+            params: GenerationParams {
+                prompt: "a lovely cat".to_string(),
+                negative_prompt: "blurry".to_string(),
+                ..GenerationParams::default()
+            },
+            model: model_name("models/sd-v1-4.gguf"),
+            schedule: ScheduleT::DEFAULT,
+            strength: None,
+            mask_blur: None,
+        };
+        assert_eq!(
+            metadata.to_parameters(),
+            "a lovely cat\nNegative prompt: blurry\n\
+             Steps: 20, Sampler: Euler a, CFG scale: 7, Seed: 42, Size: 512x512, Model: sd-v1-4"
+        );
+
+        let metadata = ImageMetadata {
+            params: GenerationParams {
+                prompt: "a lovely cat".to_string(),
+                sample_method: SampleMethodT::DPMPP2M,
+                guidance: GuidanceParams {
+                    cfg_scale: 4.5,
+                    img_cfg_scale: Some(1.5),
+                    distilled_guidance: 2.5,
+                    ..GuidanceParams::default()
+                },
+                clip_skip: 2,
+                ..GenerationParams::default()
+            },
+            model: "v1, pruned".to_string(),
+            schedule: ScheduleT::KARRAS,
+            strength: Some(0.75),
+            mask_blur: None,
+        };
+        assert_eq!(
+            metadata.to_parameters(),
+            "a lovely cat\n\
+             Steps: 20, Sampler: DPM++ 2M, Schedule type: Karras, CFG scale: 4.5, \
+             Image CFG scale: 1.5, Distilled CFG Scale: 2.5, Seed: 42, Size: 512x512, Model: \"v1, pruned\", Denoising strength: 0.75, Clip skip: 2"
+        );
+        assert_eq!(ImageMetadata::from_parameters(&metadata.to_parameters()).metadata, metadata);
+    }
+
+    #[test]
+    fn parses_a1111_text() {
+        let parsed = ImageMetadata::from_parameters(
+            "masterpiece, a cat\non a sofa\nNegative prompt: lowres,\nbad hands\n\
+             Steps: 30, Sampler: DPM++ 2M Karras, CFG scale: 5.5, Seed: 1234, Size: 640x448, \
+             Model hash: 6ce0161689, Model: v1-5-pruned, Lora hashes: \"cat: 0a1b, sofa: 2c3d\", \
+             Sampler: DPM++ SDE, Seed: -1, Version: v1.9.4",
+        );
+        let metadata = parsed.metadata;
+        assert_eq!(metadata.params.prompt, "masterpiece, a cat\non a sofa");
+        assert_eq!(metadata.params.negative_prompt, "lowres,\nbad hands");
+        assert_eq!(metadata.params.sample_steps, 30);
+        assert_eq!(metadata.params.sample_method, SampleMethodT::DPMPP2M);
+        assert_eq!(metadata.schedule, ScheduleT::KARRAS);
+        assert_eq!(metadata.params.guidance.cfg_scale, 5.5);
+        assert_eq!(metadata.params.seed, 1234);
+        assert_eq!((metadata.params.width, metadata.params.height), (640, 448));
+        assert_eq!(metadata.model, "v1-5-pruned");
+        assert_eq!(metadata.task(), Task::TextToImage);
+
+        let unmapped: Vec<String> = parsed.unmapped.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            unmapped,
+            vec![
+                "Model hash: 6ce0161689 (not supported)",
+                "Lora hashes: cat: 0a1b, sofa: 2c3d (not supported)",
+                "Sampler: DPM++ SDE (unknown sampler)",
+                "Seed: -1 (invalid value)",
+                "Version: v1.9.4 (not supported)",
+            ]
+        );
+    }
+
+    #[test]
+    fn newer_samplers_round_trip() {
+        let metadata = ImageMetadata {
+            params: GenerationParams {
+                prompt: "a lovely cat".to_string(),
+                sample_method: SampleMethodT::TCD,
+                sample_steps: 4,
+                eta: 0.3,
+                ..GenerationParams::default()
+            },
+            model: "sd3.5_medium".to_string(),
+            schedule: ScheduleT::GITS,
+            strength: None,
+            mask_blur: None,
+        };
+        let text = metadata.to_parameters();
+        assert!(text.contains("Sampler: TCD, Schedule type: GITS, CFG scale: 7, Eta: 0.3,"), "{}", text);
+        assert_eq!(ImageMetadata::from_parameters(&text).metadata, metadata);
+        let parsed = ImageMetadata::from_parameters("a cat\nSteps: 20, Sampler: iPNDM v, Schedule type: Exponential");
+        assert_eq!(parsed.metadata.params.sample_method, SampleMethodT::IPNDMV);
+        assert_eq!(parsed.metadata.schedule, ScheduleT::EXPONENTIAL);
+    }
+
+    #[test]
+    fn keeps_64_bit_seeds() {
+        let parsed = ImageMetadata::from_parameters("a cat\nSteps: 4, Seed: 18446744073709551615");
+        assert_eq!(parsed.metadata.params.seed, u64::MAX);
+        assert!(parsed.unmapped.is_empty());
+    }
+
+    #[test]
+    fn text_without_settings_is_a_prompt() {
+        let parsed = ImageMetadata::from_parameters("just a prompt: nothing else");
+        assert_eq!(parsed.metadata.params.prompt, "just a prompt: nothing else");
+        assert_eq!(parsed.metadata.params.sample_steps, 20);
+        assert!(parsed.unmapped.is_empty());
+    }
+
+    #[test]
+    fn reads_embedded_metadata() {
+        let metadata = ImageMetadata {
+            params: GenerationParams {
+                prompt: "a lovely cat".to_string(),
+                style_ratio: 35.0,
+                ..GenerationParams::default()
+            },
+            model: "sd-v1-4".to_string(),
+            schedule: ScheduleT::AYS,
+            strength: Some(0.4),
+            mask_blur: Some(6),
+        };
+        let image = metadata.embed(&png::encode(1, 1, 1, &[0])).unwrap();
+        let parsed = ImageMetadata::read(&image).unwrap();
+        assert!(parsed.unmapped.is_empty());
+        assert_eq!(parsed.metadata.task(), Task::Inpaint);
+        assert_eq!(parsed.metadata.mask_blur, Some(6));
+        // Only the JSON chunk keeps settings A1111 has no field for.
+        let style_ratio = if cfg!(feature = "serde") { 35.0 } else { 20.0 };
+        assert_eq!(parsed.metadata.params.style_ratio, style_ratio);
+
+        assert!(matches!(
+            ImageMetadata::read(&png::encode(1, 1, 1, &[0])),
+            Err(SdError::Format { .. })
+        ));
+        assert!(ImageMetadata::read(b"not a png").is_err());
+    }
+}
@@ -0,0 +1,593 @@
+//! Lists the tensors of a model file in any format the host converts from:
+//! `.gguf`, `.safetensors` and PyTorch `.ckpt`/`.pt` archives.
+//!
+//! Only headers are read, so a file can be checked before handing it to
+//! [`Quantization::convert`](crate::Quantization::convert), which would
+//! otherwise fail late inside the host.
+use crate::gguf::GgufInfo;
+use crate::stable_diffusion_interface::SdTypeT;
+use crate::{ckpt, safetensors, SdError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::str::FromStr;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Container format of a model file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ModelFormat {
+    Gguf,
+    Safetensors,
+    /// A PyTorch zip archive, as written by `torch.save`.
+    Ckpt,
+}
+
+impl ModelFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelFormat::Gguf => "GGUF",
+            ModelFormat::Safetensors => "safetensors",
+            ModelFormat::Ckpt => "ckpt",
+        }
+    }
+}
+
+impl fmt::Display for ModelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Model family, detected from tensor names the way stable-diffusion.cpp does.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ModelVersion {
+    Sd1,
+    Sd2,
+    Sdxl,
+    Sd3,
+    Flux,
+    Unknown,
+}
+
+impl ModelVersion {
+    /// Detects the family from the tensor names of a checkpoint.
+    pub fn detect<'a>(names: impl IntoIterator<Item = &'a str>) -> ModelVersion {
+        let mut version = ModelVersion::Unknown;
+        for name in names {
+            if name.contains("double_blocks.") {
+                return ModelVersion::Flux;
+            }
+            if name.contains("joint_blocks.") {
+                return ModelVersion::Sd3;
+            }
+            if name.contains("conditioner.embedders.1") || name.contains("diffusion_model.label_emb") {
+                version = ModelVersion::Sdxl;
+            } else if version == ModelVersion::Unknown || version == ModelVersion::Sd1 {
+                if name.starts_with("cond_stage_model.model.") {
+                    version = ModelVersion::Sd2;
+                } else if name.starts_with("cond_stage_model.transformer.")
+                    || name.starts_with("model.diffusion_model.")
+                {
+                    version = ModelVersion::Sd1;
+                }
+            }
+        }
+        version
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelVersion::Sd1 => "SD1.x",
+            ModelVersion::Sd2 => "SD2.x",
+            ModelVersion::Sdxl => "SDXL",
+            ModelVersion::Sd3 => "SD3",
+            ModelVersion::Flux => "Flux",
+            ModelVersion::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for ModelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One tensor of a model file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TensorEntry {
+    pub name: String,
+    /// The type as the file names it, e.g. `F16` or `float16`.
+    pub dtype: String,
+    /// The type the host loads the tensor as, or `None` if it cannot load it.
+    pub sd_type: Option<SdTypeT>,
+    /// Dimensions, outermost first.
+    pub shape: Vec<u64>,
+}
+
+impl TensorEntry {
+    /// Number of elements, or `None` if it does not fit in a `u64`.
+    pub fn elements(&self) -> Option<u64> {
+        self.shape.iter().try_fold(1u64, |n, &dim| n.checked_mul(dim))
+    }
+
+    /// Whether converting to `wtype` changes this tensor's type. Mirrors the
+    /// host: biases, scales, vectors and integer tensors keep their type, and
+    /// so do rows that are not a whole number of quantization blocks.
+    pub fn converts_to(&self, wtype: SdTypeT) -> bool {
+        let Some(sd_type) = self.sd_type else {
+            return false;
+        };
+        let is_float = matches!(
+            sd_type,
+            SdTypeT::SdTypeF32 | SdTypeT::SdTypeF16 | SdTypeT::SdTypeBf16 | SdTypeT::SdTypeF64
+        );
+        let (block_elements, _) = block_layout(wtype);
+        let row = self.shape.last().copied().unwrap_or(0);
+        wtype != SdTypeT::SdTypeCount
+            && is_float
+            && self.shape.len() >= 2
+            && row.is_multiple_of(block_elements)
+            && !self.name.ends_with(".bias")
+            && !self.name.ends_with(".scale")
+    }
+
+    /// Size in bytes once converted to `wtype`, or `None` if the tensor
+    /// cannot be loaded or its size does not fit in a `u64`.
+    pub fn size_as(&self, wtype: SdTypeT) -> Option<u64> {
+        let sd_type = if self.converts_to(wtype) { wtype } else { self.sd_type? };
+        let (block_elements, block_bytes) = block_layout(sd_type);
+        self.elements()?.div_ceil(block_elements).checked_mul(block_bytes)
+    }
+}
+
+/// The tensor table of a model file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelFileInfo {
+    pub format: ModelFormat,
+    /// String metadata: GGUF string entries or the safetensors
+    /// `__metadata__` table. Empty for ckpt files.
+    pub metadata: Vec<(String, String)>,
+    pub tensors: Vec<TensorEntry>,
+}
+
+impl ModelFileInfo {
+    /// Reads the tensor table of the file at `path`, telling the format
+    /// apart by its first bytes.
+    pub fn read(path: impl AsRef<Path>) -> Result<ModelFileInfo, SdError> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(|err| SdError::io(path, err))?;
+        let mut magic = [0; 4];
+        let read = file.read(&mut magic).map_err(|err| SdError::io(path, err))?;
+        if read == 4 && magic == crate::gguf::MAGIC {
+            return GgufInfo::read(path).map(ModelFileInfo::from);
+        }
+        let (format, result) = if read == 4 && magic == *b"PK\x03\x04" {
+            (ModelFormat::Ckpt, ckpt::read(BufReader::new(file)))
+        } else if read >= 1 && magic[0] == 0x80 {
+            // A bare pickle: the format torch.save used before 1.6.
+            return Err(SdError::format(
+                "ckpt",
+                "legacy (non-zip) PyTorch checkpoints are not supported; re-save it with a recent torch",
+            ));
+        } else {
+            let len = file.metadata().map_err(|err| SdError::io(path, err))?.len();
+            file.seek(SeekFrom::Start(0)).map_err(|err| SdError::io(path, err))?;
+            (ModelFormat::Safetensors, safetensors::read(BufReader::new(file), len))
+        };
+        result.map_err(|err| match err {
+            ReadError::Io(err) => SdError::io(path, err),
+            ReadError::Format(message) => SdError::format(format.name(), message),
+        })
+    }
+
+    /// The string metadata value stored under `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.metadata.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn model_version(&self) -> ModelVersion {
+        ModelVersion::detect(self.tensors.iter().map(|tensor| tensor.name.as_str()))
+    }
+
+    /// Whether the file carries its own VAE weights.
+    pub fn has_vae(&self) -> bool {
+        self.tensors.iter().any(|tensor| {
+            tensor.name.starts_with("first_stage_model.") || tensor.name.starts_with("vae.")
+        })
+    }
+
+    /// Tensors the host cannot load.
+    pub fn unsupported(&self) -> impl Iterator<Item = &TensorEntry> {
+        self.tensors.iter().filter(|tensor| tensor.sd_type.is_none())
+    }
+
+    /// Number of tensors of each file type.
+    pub fn dtype_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for tensor in &self.tensors {
+            *counts.entry(tensor.dtype.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Estimated tensor data size of the file converted to `wtype`, in
+    /// bytes. [`SdTypeT::SdTypeCount`] keeps every tensor's type.
+    pub fn estimated_size(&self, wtype: SdTypeT) -> u64 {
+        self.planned_size(wtype, &PrecisionPlan::default())
+    }
+
+    /// Like [`estimated_size`](Self::estimated_size), with the tensors
+    /// matched by `plan` converted to the plan's types instead.
+    pub fn planned_size(&self, wtype: SdTypeT, plan: &PrecisionPlan) -> u64 {
+        self.tensors
+            .iter()
+            .filter_map(|tensor| tensor.size_as(plan.type_for(&tensor.name).unwrap_or(wtype)))
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Checks that the host can convert this file: it has tensors and all of
+    /// them have a type the host loads.
+    pub fn check_convertible(&self) -> Result<(), String> {
+        if self.tensors.is_empty() {
+            return Err(format!("the {} file has no tensors", self.format));
+        }
+        let unsupported: Vec<_> = self.unsupported().collect();
+        if let Some(first) = unsupported.first() {
+            return Err(format!(
+                "{} tensor(s) have a type the host cannot load, e.g. {} ({})",
+                unsupported.len(),
+                first.name,
+                first.dtype
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<GgufInfo> for ModelFileInfo {
+    fn from(info: GgufInfo) -> ModelFileInfo {
+        let metadata = info
+            .metadata
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+            .collect();
+        let tensors = info
+            .tensors
+            .into_iter()
+            .map(|tensor| {
+                let sd_type = tensor.sd_type();
+                TensorEntry {
+                    dtype: sd_type.map_or_else(|| format!("type {}", tensor.ggml_type), |t| t.name().to_string()),
+                    sd_type,
+                    shape: tensor.dims.into_iter().rev().collect(),
+                    name: tensor.name,
+                }
+            })
+            .collect();
+        ModelFileInfo {
+            format: ModelFormat::Gguf,
+            metadata,
+            tensors,
+        }
+    }
+}
+
+/// Per-tensor output types for a conversion, e.g. to keep the VAE at f16
+/// while the UNet is quantized.
+///
+/// The first rule whose prefix starts a tensor's name gives its type; other
+/// tensors get the conversion's `wtype`. Tensors the host never converts,
+/// such as biases, keep their type either way. The text form lists
+/// `prefix=type` rules separated by commas:
+/// `first_stage_model.=f16,model.diffusion_model.out.=f16`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrecisionPlan {
+    /// Tensor name prefixes and their types, in priority order.
+    pub rules: Vec<(String, SdTypeT)>,
+}
+
+impl PrecisionPlan {
+    pub fn new() -> PrecisionPlan {
+        PrecisionPlan::default()
+    }
+
+    /// Keeps the VAE and the first and last UNet layers at `keep`, the
+    /// layers that lose the most quality when quantized.
+    pub fn keep_edges(keep: SdTypeT) -> PrecisionPlan {
+        PrecisionPlan::new()
+            .rule("first_stage_model.", keep)
+            .rule("model.diffusion_model.input_blocks.0.", keep)
+            .rule("model.diffusion_model.out.", keep)
+    }
+
+    /// Adds a rule after the existing ones. `wtype` must be a concrete type,
+    /// not [`SdTypeT::SdTypeCount`].
+    pub fn rule(mut self, prefix: &str, wtype: SdTypeT) -> PrecisionPlan {
+        self.rules.push((prefix.to_string(), wtype));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The type the plan gives the tensor `name`, if a rule matches it.
+    pub fn type_for(&self, name: &str) -> Option<SdTypeT> {
+        self.rules
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .map(|(_, wtype)| *wtype)
+    }
+
+    /// Checks that every rule has a prefix and a concrete type.
+    pub fn check(&self) -> Result<(), String> {
+        for (prefix, wtype) in &self.rules {
+            if prefix.is_empty() {
+                return Err("a rule has an empty prefix".to_string());
+            }
+            if *wtype == SdTypeT::SdTypeCount {
+                return Err(format!("the rule for {} needs a concrete type", prefix));
+            }
+        }
+        Ok(())
+    }
+
+    /// The plan in the host's `tensor_type_rules` form: anchored regular
+    /// expressions and ggml type names.
+    pub(crate) fn host_rules(&self) -> String {
+        let rules: Vec<_> = self
+            .rules
+            .iter()
+            .map(|(prefix, wtype)| {
+                let mut pattern = String::from("^");
+                for c in prefix.chars() {
+                    if "\\.^$|?*+()[]{}".contains(c) {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                // ggml spells the k-quants with a capital K.
+                let name = wtype.name();
+                match name.strip_suffix("_k") {
+                    Some(stem) => format!("{}={}_K", pattern, stem),
+                    None => format!("{}={}", pattern, name),
+                }
+            })
+            .collect();
+        rules.join(",")
+    }
+}
+
+impl fmt::Display for PrecisionPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (prefix, wtype)) in self.rules.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", prefix, wtype.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PrecisionPlan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut plan = PrecisionPlan::new();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (prefix, name) = rule
+                .rsplit_once('=')
+                .ok_or_else(|| format!("Invalid rule: {} (expected prefix=type)", rule))?;
+            let wtype: SdTypeT = name.trim().parse()?;
+            plan = plan.rule(prefix.trim(), wtype);
+        }
+        plan.check()?;
+        Ok(plan)
+    }
+}
+
+/// Elements per block and bytes per block of a ggml type.
+pub(crate) fn block_layout(sd_type: SdTypeT) -> (u64, u64) {
+    use SdTypeT::*;
+    match sd_type {
+        SdTypeF32 | SdTypeI32 => (1, 4),
+        SdTypeF16 | SdTypeBf16 | SdTypeI16 => (1, 2),
+        SdTypeF64 | SdTypeI64 => (1, 8),
+        SdTypeI8 => (1, 1),
+        SdTypeQ4_0 | SdTypeIq4Nl => (32, 18),
+        SdTypeQ4_1 => (32, 20),
+        SdTypeQ5_0 => (32, 22),
+        SdTypeQ5_1 => (32, 24),
+        SdTypeQ8_0 => (32, 34),
+        SdTypeQ8_1 => (32, 36),
+        SdTypeQ2K => (256, 84),
+        SdTypeQ3K | SdTypeIq3S => (256, 110),
+        SdTypeQ4K => (256, 144),
+        SdTypeQ5K => (256, 176),
+        SdTypeQ6K => (256, 210),
+        SdTypeQ8K => (256, 292),
+        SdTypeIq2Xxs => (256, 66),
+        SdTypeIq2Xs => (256, 74),
+        SdTypeIq3Xxs => (256, 98),
+        SdTypeIq1S => (256, 50),
+        SdTypeIq2S => (256, 82),
+        SdTypeIq4Xs => (256, 136),
+        SdTypeIq1M => (256, 56),
+        SdTypeCount => (1, 0),
+    }
+}
+
+/// Failure reading a safetensors or ckpt header.
+#[derive(Debug)]
+pub(crate) enum ReadError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl ReadError {
+    pub(crate) fn format(message: impl Into<String>) -> ReadError {
+        ReadError::Format(message.into())
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            ReadError::format("truncated file")
+        } else {
+            ReadError::Io(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn tensor(name: &str, dtype: SdTypeT, shape: &[u64]) -> TensorEntry {
+        TensorEntry {
+            name: name.to_string(),
+            dtype: dtype.name().to_string(),
+            sd_type: Some(dtype),
+            shape: shape.to_vec(),
+        }
+    }
+
+    #[test]
+    fn detects_the_model_family() {
+        let detect = |names: &[&str]| ModelVersion::detect(names.iter().copied());
+        assert_eq!(detect(&["model.diffusion_model.double_blocks.0.img_attn.qkv.weight"]), ModelVersion::Flux);
+        assert_eq!(detect(&["model.diffusion_model.joint_blocks.0.x_block.attn.qkv.weight"]), ModelVersion::Sd3);
+        assert_eq!(
+            detect(&["model.diffusion_model.input_blocks.0.0.weight", "conditioner.embedders.1.model.ln_final.weight"]),
+            ModelVersion::Sdxl
+        );
+        assert_eq!(
+            detect(&["model.diffusion_model.input_blocks.0.0.weight", "cond_stage_model.model.ln_final.weight"]),
+            ModelVersion::Sd2
+        );
+        assert_eq!(detect(&["lora_unet_down_blocks_0.alpha"]), ModelVersion::Unknown);
+    }
+
+    #[test]
+    fn estimates_the_converted_size() {
+        let info = ModelFileInfo {
+            format: ModelFormat::Safetensors,
+            metadata: Vec::new(),
+            tensors: vec![
+                tensor("model.diffusion_model.out.2.weight", SdTypeT::SdTypeF32, &[4, 320, 3, 3]),
+                tensor("model.diffusion_model.out.2.bias", SdTypeT::SdTypeF32, &[4]),
+                tensor("model.diffusion_model.proj.weight", SdTypeT::SdTypeF16, &[640, 320]),
+                tensor("model.diffusion_model.odd.weight", SdTypeT::SdTypeF16, &[8, 40]),
+                tensor("position_ids", SdTypeT::SdTypeI64, &[1, 77]),
+            ],
+        };
+        let kept = 4 * 320 * 9 * 4 + 4 * 4 + 640 * 320 * 2 + 8 * 40 * 2 + 77 * 8;
+        assert_eq!(info.estimated_size(SdTypeT::SdTypeCount), kept);
+        // Rows of 3 and 40 elements are not whole q8_0 blocks.
+        assert_eq!(
+            info.estimated_size(SdTypeT::SdTypeQ8_0),
+            4 * 320 * 9 * 4 + 4 * 4 + 640 * 320 / 32 * 34 + 8 * 40 * 2 + 77 * 8
+        );
+        assert_eq!(
+            info.estimated_size(SdTypeT::SdTypeF16),
+            4 * 320 * 9 * 2 + 4 * 4 + 640 * 320 * 2 + 8 * 40 * 2 + 77 * 8
+        );
+        assert!(info.check_convertible().is_ok());
+        assert_eq!(info.model_version(), ModelVersion::Sd1);
+    }
+
+    #[test]
+    fn overflowing_shapes_have_no_size() {
+        let huge = tensor("model.diffusion_model.huge.weight", SdTypeT::SdTypeF32, &[u64::MAX, 2]);
+        assert_eq!(huge.elements(), None);
+        assert_eq!(huge.size_as(SdTypeT::SdTypeCount), None);
+        let wide = tensor("model.diffusion_model.wide.weight", SdTypeT::SdTypeF32, &[1 << 62, 1]);
+        assert_eq!(wide.elements(), Some(1 << 62));
+        assert_eq!(wide.size_as(SdTypeT::SdTypeCount), None);
+        let info = ModelFileInfo {
+            format: ModelFormat::Ckpt,
+            metadata: Vec::new(),
+            tensors: vec![huge, wide, tensor("position_ids", SdTypeT::SdTypeI64, &[1, 77])],
+        };
+        assert_eq!(info.estimated_size(SdTypeT::SdTypeQ8_0), 77 * 8);
+    }
+
+    #[test]
+    fn reports_tensors_the_host_cannot_load() {
+        let mut info = ModelFileInfo {
+            format: ModelFormat::Ckpt,
+            metadata: Vec::new(),
+            tensors: Vec::new(),
+        };
+        assert_eq!(info.check_convertible().unwrap_err(), "the ckpt file has no tensors");
+        info.tensors.push(tensor("a.weight", SdTypeT::SdTypeF16, &[2, 2]));
+        info.tensors.push(TensorEntry {
+            name: "mask".to_string(),
+            dtype: "bool".to_string(),
+            sd_type: None,
+            shape: vec![2],
+        });
+        assert_eq!(
+            info.check_convertible().unwrap_err(),
+            "1 tensor(s) have a type the host cannot load, e.g. mask (bool)"
+        );
+        assert_eq!(info.estimated_size(SdTypeT::SdTypeCount), 8);
+        assert_eq!(
+            info.dtype_counts().into_iter().collect::<Vec<_>>(),
+            [("bool", 1), ("f16", 1)]
+        );
+    }
+
+    #[test]
+    fn plans_mix_precisions() {
+        let plan = PrecisionPlan::keep_edges(SdTypeT::SdTypeF16);
+        let info = ModelFileInfo {
+            format: ModelFormat::Safetensors,
+            metadata: Vec::new(),
+            tensors: vec![
+                tensor("model.diffusion_model.input_blocks.0.0.weight", SdTypeT::SdTypeF32, &[320, 256]),
+                tensor("model.diffusion_model.middle_block.1.proj.weight", SdTypeT::SdTypeF32, &[320, 256]),
+                tensor("first_stage_model.decoder.conv_in.weight", SdTypeT::SdTypeF32, &[512, 256]),
+            ],
+        };
+        assert_eq!(
+            info.planned_size(SdTypeT::SdTypeQ4K, &plan),
+            320 * 256 * 2 + 320 * 144 + 512 * 256 * 2
+        );
+        assert_eq!(info.planned_size(SdTypeT::SdTypeQ4K, &PrecisionPlan::new()), (320 + 320 + 512) * 144);
+
+        let text = "first_stage_model.=f16,model.diffusion_model.input_blocks.0.=f16,model.diffusion_model.out.=f16";
+        assert_eq!(plan.to_string(), text);
+        assert_eq!(text.parse::<PrecisionPlan>().unwrap(), plan);
+        assert_eq!(
+            PrecisionPlan::new().rule("vae.", SdTypeT::SdTypeQ4K).rule("a+b", SdTypeT::SdTypeQ8_0).host_rules(),
+            r"^vae\.=q4_K,^a\+b=q8_0"
+        );
+        assert_eq!("".parse::<PrecisionPlan>().unwrap(), PrecisionPlan::new());
+        assert!("vae.".parse::<PrecisionPlan>().unwrap_err().contains("expected prefix=type"));
+        assert!("vae.=q9".parse::<PrecisionPlan>().unwrap_err().starts_with("Invalid weight type: q9 (expected one of f32, f16,"));
+        assert!("vae.=default".parse::<PrecisionPlan>().unwrap_err().contains("concrete type"));
+        assert!("=f16".parse::<PrecisionPlan>().unwrap_err().contains("empty prefix"));
+    }
+
+    #[test]
+    fn reads_files_by_their_magic() {
+        let dir = TempDir::new("model-file");
+        let path = dir.join("legacy.ckpt");
+        std::fs::write(&path, b"\x80\x02\x8a\x0a").unwrap();
+        let err = ModelFileInfo::read(&path).unwrap_err();
+        assert!(err.to_string().starts_with("invalid ckpt: legacy (non-zip)"), "{}", err);
+        std::fs::write(&path, b"not a model").unwrap();
+        assert!(matches!(
+            ModelFileInfo::read(&path),
+            Err(SdError::Format { format: "safetensors", .. })
+        ));
+        drop(dir);
+        assert!(matches!(ModelFileInfo::read(&path), Err(SdError::Io { .. })));
+    }
+}
@@ -0,0 +1,272 @@
+//! Stand-in for the `wasmedge_stablediffusion` import module on native targets.
+//!
+//! It mirrors the host signatures so the crate links outside WasmEdge. Every
+//! call fails with `RUNTIME_ERROR`; use `backend::MockBackend` to test against a
+//! working host.
+#![allow(clippy::too_many_arguments)]
+
+/// `WASMEDGE_SD_ERRNO_RUNTIME_ERROR`
+const NO_HOST: i32 = 5;
+
+pub unsafe fn create_context(
+    _model_path_ptr: *const u8,
+    _model_path_len: i32,
+    _vae_path_ptr: *const u8,
+    _vae_path_len: i32,
+    _taesd_path_ptr: *const u8,
+    _taesd_path_len: i32,
+    _control_net_path_ptr: *const u8,
+    _control_net_path_len: i32,
+    _lora_model_dir_ptr: *const u8,
+    _lora_model_dir_len: i32,
+    _embed_dir_ptr: *const u8,
+    _embed_dir_len: i32,
+    _id_embed_dir_ptr: *const u8,
+    _id_embed_dir_len: i32,
+    _vae_decode_only: i32,
+    _vae_tiling: i32,
+    _n_threads: i32,
+    _wtype: i32,
+    _rng_type: i32,
+    _schedule: i32,
+    _clip_on_cpu: i32,
+    _control_net_cpu: i32,
+    _vae_on_cpu: i32,
+    _session_id_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+pub unsafe fn image_to_image(
+    _image_ptr: *const u8,
+    _image_len: i32,
+    _session_id: i32,
+    _width: i32,
+    _height: i32,
+    _control_image_ptr: *const u8,
+    _control_image_len: i32,
+    _prompt_ptr: *const u8,
+    _prompt_len: i32,
+    _negative_prompt_ptr: *const u8,
+    _negative_prompt_len: i32,
+    _clip_skip: i32,
+    _cfg_scale: f32,
+    _sample_method: i32,
+    _sample_steps: i32,
+    _strength: f32,
+    _seed: i32,
+    _batch_count: i32,
+    _control_strength: f32,
+    _style_ratio: f32,
+    _normalize_input: i32,
+    _input_id_images_dir_ptr: *const u8,
+    _input_id_images_dir_len: i32,
+    _canny_preprocess: i32,
+    _upscale_model_path_ptr: *const u8,
+    _upscale_model_path_len: i32,
+    _upscale_repeats: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _out_buffer_ptr: *mut u8,
+    _out_buffer_max_size: i32,
+    _bytes_written_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn inpaint(
+    _image_ptr: *const u8,
+    _image_len: i32,
+    _mask_ptr: *const u8,
+    _mask_len: i32,
+    _session_id: i32,
+    _width: i32,
+    _height: i32,
+    _control_image_ptr: *const u8,
+    _control_image_len: i32,
+    _prompt_ptr: *const u8,
+    _prompt_len: i32,
+    _negative_prompt_ptr: *const u8,
+    _negative_prompt_len: i32,
+    _clip_skip: i32,
+    _cfg_scale: f32,
+    _sample_method: i32,
+    _sample_steps: i32,
+    _strength: f32,
+    _mask_blur: i32,
+    _keep_unmasked: i32,
+    _seed: i32,
+    _batch_count: i32,
+    _control_strength: f32,
+    _style_ratio: f32,
+    _normalize_input: i32,
+    _input_id_images_dir_ptr: *const u8,
+    _input_id_images_dir_len: i32,
+    _canny_preprocess: i32,
+    _upscale_model_path_ptr: *const u8,
+    _upscale_model_path_len: i32,
+    _upscale_repeats: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _out_buffer_ptr: *mut u8,
+    _out_buffer_max_size: i32,
+    _bytes_written_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+pub unsafe fn text_to_image(
+    _prompt_ptr: *const u8,
+    _prompt_len: i32,
+    _session_id: i32,
+    _control_image_ptr: *const u8,
+    _control_image_len: i32,
+    _negative_prompt_ptr: *const u8,
+    _negative_prompt_len: i32,
+    _width: i32,
+    _height: i32,
+    _clip_skip: i32,
+    _cfg_scale: f32,
+    _sample_method: i32,
+    _sample_steps: i32,
+    _seed: i32,
+    _batch_count: i32,
+    _control_strength: f32,
+    _style_ratio: f32,
+    _normalize_input: i32,
+    _input_id_images_dir_ptr: *const u8,
+    _input_id_images_dir_len: i32,
+    _canny_preprocess: i32,
+    _upscale_model_path_ptr: *const u8,
+    _upscale_model_path_len: i32,
+    _upscale_repeats: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _out_buffer_ptr: *mut u8,
+    _out_buffer_max_size: i32,
+    _bytes_written_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn create_upscaler_context(
+    _upscale_model_path_ptr: *const u8,
+    _upscale_model_path_len: i32,
+    _n_threads: i32,
+    _wtype: i32,
+    _session_id_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn upscale(
+    _session_id: i32,
+    _image_ptr: *const u8,
+    _image_len: i32,
+    _upscale_repeats: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _out_buffer_ptr: *mut u8,
+    _out_buffer_max_size: i32,
+    _bytes_written_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn free_context(_session_id: i32) -> i32 {
+    NO_HOST
+}
+
+pub unsafe fn convert(
+    _model_path_ptr: *const u8,
+    _model_path_len: i32,
+    _vae_model_path_ptr: *const u8,
+    _vae_model_path_len: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _wtype: i32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn convert_with_rules(
+    _model_path_ptr: *const u8,
+    _model_path_len: i32,
+    _vae_model_path_ptr: *const u8,
+    _vae_model_path_len: i32,
+    _output_path_ptr: *const u8,
+    _output_path_len: i32,
+    _wtype: i32,
+    _tensor_type_rules_ptr: *const u8,
+    _tensor_type_rules_len: i32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn create_context_v2(
+    _model_path_ptr: *const u8,
+    _model_path_len: i32,
+    _clip_l_path_ptr: *const u8,
+    _clip_l_path_len: i32,
+    _clip_g_path_ptr: *const u8,
+    _clip_g_path_len: i32,
+    _t5xxl_path_ptr: *const u8,
+    _t5xxl_path_len: i32,
+    _diffusion_model_path_ptr: *const u8,
+    _diffusion_model_path_len: i32,
+    _vae_path_ptr: *const u8,
+    _vae_path_len: i32,
+    _taesd_path_ptr: *const u8,
+    _taesd_path_len: i32,
+    _control_net_path_ptr: *const u8,
+    _control_net_path_len: i32,
+    _lora_model_dir_ptr: *const u8,
+    _lora_model_dir_len: i32,
+    _embed_dir_ptr: *const u8,
+    _embed_dir_len: i32,
+    _id_embed_dir_ptr: *const u8,
+    _id_embed_dir_len: i32,
+    _vae_decode_only: i32,
+    _vae_tiling: i32,
+    _n_threads: i32,
+    _wtype: i32,
+    _rng_type: i32,
+    _schedule: i32,
+    _clip_on_cpu: i32,
+    _control_net_cpu: i32,
+    _vae_on_cpu: i32,
+    _session_id_ptr: *mut u32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn set_guidance_params(
+    _session_id: i32,
+    _cfg_scale: f32,
+    _img_cfg_scale: f32,
+    _distilled_guidance: f32,
+    _slg_layers_ptr: *const u8,
+    _slg_layers_len: i32,
+    _slg_start: f32,
+    _slg_end: f32,
+    _slg_scale: f32,
+) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn set_eta(_session_id: i32, _eta: f32) -> i32 {
+    NO_HOST
+}
+
+#[cfg(feature = "host-v2")]
+pub unsafe fn capabilities(_sample_methods_ptr: *mut u32, _schedules_ptr: *mut u32) -> i32 {
+    NO_HOST
+}
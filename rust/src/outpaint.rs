@@ -0,0 +1,375 @@
+//! Outpainting: extending an image past its borders with the inpainting task.
+//!
+//! [`Outpaint::prepare`] pads the source image on the requested sides and
+//! builds the matching mask: white over the new area, fading to black over
+//! `feather` pixels inside the source so the seam is regenerated as well.
+//! [`Inpaint::set_canvas`](crate::Inpaint::set_canvas) then hands both to an
+//! inpainting context.
+//!
+//! Sources are decoded on the guest, so they must be 8-bit PNGs or raw
+//! [`ImageType::Rgb`] pixels. JPEGs are refused with a [`SdError::Format`]
+//! and need converting to PNG first.
+use crate::png;
+use crate::stable_diffusion_interface::ImageType;
+use crate::SdError;
+
+/// Width of the mask fade into the source of a new [`Outpaint`], in pixels.
+pub const DEFAULT_OUTPAINT_FEATHER: u32 = 32;
+
+/// What the padded area holds before generation.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutpaintFill {
+    /// Repeats the nearest edge pixel of the source outwards.
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "stretch"))]
+    Stretch,
+    /// Random pixels, from [`Outpaint::seed`].
+    #[cfg_attr(feature = "serde", serde(rename = "noise"))]
+    Noise,
+}
+
+impl std::str::FromStr for OutpaintFill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stretch" => Ok(OutpaintFill::Stretch),
+            "noise" => Ok(OutpaintFill::Noise),
+            _ => Err(format!("Invalid outpaint fill: {}", s)),
+        }
+    }
+}
+
+/// How far to extend an image on each side.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Outpaint {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    /// Width of the mask fade into the source, in pixels.
+    pub feather: u32,
+    pub fill: OutpaintFill,
+    /// Seed of the [`OutpaintFill::Noise`] fill.
+    pub seed: u64,
+}
+
+impl Default for Outpaint {
+    fn default() -> Self {
+        Outpaint::new(0, 0, 0, 0)
+    }
+}
+
+impl Outpaint {
+    pub fn new(left: u32, top: u32, right: u32, bottom: u32) -> Outpaint {
+        Outpaint {
+            left,
+            top,
+            right,
+            bottom,
+            feather: DEFAULT_OUTPAINT_FEATHER,
+            fill: OutpaintFill::Stretch,
+            seed: 0,
+        }
+    }
+
+    /// Padding that widens or heightens a `width`x`height` image to the
+    /// aspect ratio `ratio_width:ratio_height`, split evenly between the two
+    /// sides. Both sides of the result are rounded up to a multiple of 8, as
+    /// generation requires.
+    pub fn to_aspect(
+        width: u32,
+        height: u32,
+        ratio_width: u32,
+        ratio_height: u32,
+    ) -> Result<Outpaint, SdError> {
+        if ratio_width == 0 || ratio_height == 0 {
+            return Err(SdError::invalid("aspect", "both ratio terms must be positive"));
+        }
+        let (w, h, rw, rh) = (width as u64, height as u64, ratio_width as u64, ratio_height as u64);
+        let (target_w, target_h) = if w * rh < h * rw {
+            ((h * rw).div_ceil(rh), h)
+        } else {
+            (w, (w * rh).div_ceil(rw))
+        };
+        let round = |value: u64| u32::try_from(value.div_ceil(8) * 8);
+        let (Ok(target_w), Ok(target_h)) = (round(target_w), round(target_h)) else {
+            return Err(SdError::invalid("aspect", "the padded image is too large"));
+        };
+        let (pad_w, pad_h) = (target_w - width, target_h - height);
+        Ok(Outpaint::new(pad_w / 2, pad_h / 2, pad_w - pad_w / 2, pad_h - pad_h / 2))
+    }
+
+    pub fn feather(mut self, feather: u32) -> Self {
+        self.feather = feather;
+        self
+    }
+
+    pub fn fill(mut self, fill: OutpaintFill) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Size of a `width`x`height` source after padding.
+    pub fn size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let width = width.checked_add(self.left)?.checked_add(self.right)?;
+        let height = height.checked_add(self.top)?.checked_add(self.bottom)?;
+        Some((width, height))
+    }
+
+    /// Pads `image` and builds its mask.
+    ///
+    /// `image` must be an 8-bit non-interlaced PNG or raw pixels. JPEG and
+    /// other formats fail with [`SdError::Format`], as there is no decoder for
+    /// them on the guest.
+    pub fn prepare(&self, image: &ImageType) -> Result<OutpaintCanvas, SdError> {
+        if self.left == 0 && self.top == 0 && self.right == 0 && self.bottom == 0 {
+            return Err(SdError::invalid("outpaint", "no side to extend"));
+        }
+        let source = decode(image)?;
+        let (src_w, src_h) = (source.width as usize, source.height as usize);
+        let (width, height) = self
+            .size(source.width, source.height)
+            .filter(|&(w, h)| (w as u64) * (h as u64) * 3 <= isize::MAX as u64)
+            .ok_or_else(|| SdError::invalid("outpaint", "the padded image is too large"))?;
+        let (left, top) = (self.left as usize, self.top as usize);
+
+        let mut rng = self.seed;
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+        let mut mask = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let inside = (left..left + src_w).contains(&x) && (top..top + src_h).contains(&y);
+                if !inside && self.fill == OutpaintFill::Noise {
+                    let noise = splitmix64(&mut rng).to_le_bytes();
+                    pixels.extend_from_slice(&noise[..3]);
+                } else {
+                    let sx = x.saturating_sub(left).min(src_w - 1);
+                    let sy = y.saturating_sub(top).min(src_h - 1);
+                    pixels.extend_from_slice(&source.rgb(sx, sy));
+                }
+                mask.push(if inside {
+                    self.seam(x - left, y - top, src_w, src_h)
+                } else {
+                    255
+                });
+            }
+        }
+        Ok(OutpaintCanvas {
+            width,
+            height,
+            pixels,
+            mask,
+        })
+    }
+
+    /// Mask value of source pixel (`x`, `y`), fading out with the distance
+    /// to the nearest extended side.
+    fn seam(&self, x: usize, y: usize, width: usize, height: usize) -> u8 {
+        let distance = [
+            (self.left, x),
+            (self.top, y),
+            (self.right, width - 1 - x),
+            (self.bottom, height - 1 - y),
+        ]
+        .into_iter()
+        .filter(|&(pad, _)| pad > 0)
+        .map(|(_, distance)| distance)
+        .min()
+        .unwrap_or(usize::MAX);
+        let feather = self.feather as usize;
+        if distance >= feather {
+            return 0;
+        }
+        (255 * (feather - distance) / (feather + 1)) as u8
+    }
+}
+
+/// A padded image and its mask, ready for [`Inpaint::set_canvas`](crate::Inpaint::set_canvas).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutpaintCanvas {
+    pub width: u32,
+    pub height: u32,
+    /// RGB, row-major.
+    pub pixels: Vec<u8>,
+    /// Gray, row-major; white marks the pixels to generate.
+    pub mask: Vec<u8>,
+}
+
+impl OutpaintCanvas {
+    pub fn image(&self) -> ImageType<'_> {
+        ImageType::Rgb {
+            width: self.width,
+            height: self.height,
+            channels: 3,
+            data: &self.pixels,
+        }
+    }
+
+    pub fn mask(&self) -> ImageType<'_> {
+        ImageType::Rgb {
+            width: self.width,
+            height: self.height,
+            channels: 1,
+            data: &self.mask,
+        }
+    }
+}
+
+fn decode(image: &ImageType) -> Result<png::Pixels, SdError> {
+    let pixels = match *image {
+        ImageType::Path(path) => {
+            let data = std::fs::read(path).map_err(|err| SdError::io(path, err))?;
+            decode_encoded(&data)?
+        }
+        ImageType::Bytes(data) => decode_encoded(data)?,
+        ImageType::Rgb {
+            width,
+            height,
+            channels,
+            data,
+        } => {
+            let expected = (width as u64) * (height as u64) * (channels as u64);
+            if !(1..=4).contains(&channels) || data.len() as u64 != expected {
+                return Err(SdError::invalid("image", "pixel data does not match its size"));
+            }
+            Some(png::Pixels {
+                width,
+                height,
+                channels: channels as u8,
+                data: data.to_vec(),
+            })
+        }
+    };
+    match pixels {
+        Some(pixels) if pixels.width > 0 && pixels.height > 0 => Ok(pixels),
+        Some(_) => Err(SdError::invalid("image", "the source image is empty")),
+        None => Err(SdError::format(
+            "PNG",
+            "outpainting reads 8-bit non-interlaced PNGs of up to 256 MiB of pixels, or raw pixels",
+        )),
+    }
+}
+
+fn decode_encoded(data: &[u8]) -> Result<Option<png::Pixels>, SdError> {
+    if data.starts_with(&[0xff, 0xd8]) {
+        return Err(SdError::format(
+            "JPEG",
+            "outpainting cannot decode JPEG sources; convert the image to PNG",
+        ));
+    }
+    Ok(png::decode(data))
+}
+
+impl png::Pixels {
+    /// RGB value of pixel (`x`, `y`); gray is replicated and alpha dropped.
+    fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let channels = self.channels as usize;
+        let at = (y * self.width as usize + x) * channels;
+        let pixel = &self.data[at..at + channels];
+        match channels {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aspect_padding_is_split_evenly() {
+        let square = Outpaint::to_aspect(512, 512, 16, 9).unwrap();
+        assert_eq!((square.left, square.top, square.right, square.bottom), (200, 0, 200, 0));
+        assert_eq!(square.size(512, 512), Some((912, 512)));
+
+        let tall = Outpaint::to_aspect(512, 512, 2, 3).unwrap();
+        assert_eq!((tall.left, tall.top, tall.right, tall.bottom), (0, 128, 0, 128));
+        assert_eq!(Outpaint::to_aspect(512, 512, 1, 1).unwrap().size(512, 512), Some((512, 512)));
+        assert!(Outpaint::to_aspect(512, 512, 0, 9).is_err());
+    }
+
+    #[test]
+    fn stretch_pads_with_edge_pixels() {
+        // 2x1 gray source: black, white.
+        let source = png::encode(2, 1, 1, &[0, 255]);
+        let canvas = Outpaint::new(1, 0, 2, 1)
+            .feather(2)
+            .prepare(&ImageType::Bytes(&source))
+            .unwrap();
+        assert_eq!((canvas.width, canvas.height), (5, 2));
+        let gray: Vec<u8> = canvas.pixels.chunks(3).map(|rgb| rgb[0]).collect();
+        assert_eq!(gray, [0, 0, 255, 255, 255, 0, 0, 255, 255, 255]);
+        assert_eq!(canvas.mask, [255, 170, 170, 255, 255, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn the_seam_fades_into_the_source() {
+        let source = ImageType::Rgb {
+            width: 8,
+            height: 1,
+            channels: 3,
+            data: &[9; 8 * 3],
+        };
+        let canvas = Outpaint::new(2, 0, 0, 0).feather(3).prepare(&source).unwrap();
+        assert_eq!(canvas.mask, [255, 255, 191, 127, 63, 0, 0, 0, 0, 0]);
+        assert_eq!(canvas.image().dimensions(), Some((10, 1)));
+        assert_eq!(canvas.mask().dimensions(), Some((10, 1)));
+
+        let hard = Outpaint::new(2, 0, 0, 0).feather(0).prepare(&source).unwrap();
+        assert_eq!(hard.mask, [255, 255, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn noise_depends_on_the_seed() {
+        let source = png::encode(1, 1, 3, &[1, 2, 3]);
+        let source = ImageType::Bytes(&source);
+        let noise = |seed| {
+            Outpaint::new(4, 4, 4, 4)
+                .fill(OutpaintFill::Noise)
+                .seed(seed)
+                .prepare(&source)
+                .unwrap()
+        };
+        assert_eq!(noise(7), noise(7));
+        assert_ne!(noise(7).pixels, noise(8).pixels);
+        // The source pixel is kept.
+        assert_eq!(noise(7).pixels[(4 * 9 + 4) * 3..][..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn unusable_sources_are_rejected() {
+        let source = png::encode(1, 1, 1, &[0]);
+        assert!(matches!(
+            Outpaint::default().prepare(&ImageType::Bytes(&source)),
+            Err(SdError::InvalidArgument { field: "outpaint", .. })
+        ));
+        assert!(matches!(
+            Outpaint::new(8, 0, 0, 0).prepare(&ImageType::Bytes(&[0xff, 0xd8, 0xff])),
+            Err(SdError::Format { format: "JPEG", .. })
+        ));
+        assert!(matches!(
+            Outpaint::new(8, 0, 0, 0).prepare(&ImageType::Bytes(b"GIF89a")),
+            Err(SdError::Format { format: "PNG", .. })
+        ));
+        assert!(matches!(
+            Outpaint::new(8, 0, 0, 0).prepare(&ImageType::Path("/nonexistent/source.png")),
+            Err(SdError::Io { .. })
+        ));
+    }
+}
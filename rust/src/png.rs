@@ -0,0 +1,436 @@
+//! Minimal PNG helpers: chunk framing, an uncompressed encoder and an 8-bit
+//! decoder.
+//!
+//! The encoder only writes stored deflate blocks. It is meant for synthetic
+//! test images, not for shipping output; real images come from the host.
+
+/// The eight bytes every PNG starts with.
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Most pixel bytes [`decode`] produces, those of an 8192x8192 RGBA image.
+/// The header alone sets the size, so larger ones are refused before
+/// inflating anything.
+const MAX_DECODED_LEN: usize = 256 << 20;
+
+/// CRC-32 (ISO 3309) as used by PNG chunks.
+pub fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for data in chunks {
+        for &byte in *data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Appends one chunk with its length and CRC to `out`.
+pub fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32(&[kind, data]).to_be_bytes());
+}
+
+/// Builds the data of a text chunk under `keyword`: `tEXt` when `text` is
+/// Latin-1, otherwise an uncompressed UTF-8 `iTXt`.
+pub fn text_chunk(keyword: &str, text: &str) -> ([u8; 4], Vec<u8>) {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    if text.chars().all(|c| (c as u32) <= 0xff) {
+        data.extend(text.chars().map(|c| c as u8));
+        (*b"tEXt", data)
+    } else {
+        // Compression flag and method, then empty language tag and translated
+        // keyword.
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        (*b"iTXt", data)
+    }
+}
+
+/// Returns `png` with a text chunk inserted right after `IHDR`, or `None` if
+/// `png` does not start with a PNG header.
+pub fn insert_text(png: &[u8], keyword: &str, text: &str) -> Option<Vec<u8>> {
+    if png.len() < 33 || png[..8] != SIGNATURE || &png[12..16] != b"IHDR" {
+        return None;
+    }
+    let ihdr_end = 8 + 12 + u32::from_be_bytes(png[8..12].try_into().unwrap()) as usize;
+    if ihdr_end > png.len() {
+        return None;
+    }
+    let (kind, data) = text_chunk(keyword, text);
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend_from_slice(&png[..ihdr_end]);
+    write_chunk(&mut out, &kind, &data);
+    out.extend_from_slice(&png[ihdr_end..]);
+    Some(out)
+}
+
+/// Iterates over the `(kind, data)` of each chunk, or returns `None` if `png`
+/// does not start with the PNG signature. Iteration stops at a truncated
+/// chunk; CRCs are not checked.
+pub fn chunks(png: &[u8]) -> Option<impl Iterator<Item = ([u8; 4], &[u8])>> {
+    let mut rest = png.strip_prefix(&SIGNATURE[..])?;
+    Some(core::iter::from_fn(move || {
+        if rest.len() < 12 {
+            return None;
+        }
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+        let data = rest.get(8..8usize.checked_add(len)?)?;
+        rest = rest.get(12 + len..)?;
+        Some((kind, data))
+    }))
+}
+
+/// The `(keyword, text)` of every uncompressed `tEXt` and `iTXt` chunk.
+/// Compressed text (`zTXt`, compressed `iTXt`) is skipped.
+pub fn text_chunks(png: &[u8]) -> Vec<(String, String)> {
+    let Some(chunks) = chunks(png) else {
+        return Vec::new();
+    };
+    let mut texts = Vec::new();
+    for (kind, data) in chunks {
+        let Some(nul) = data.iter().position(|&byte| byte == 0) else {
+            continue;
+        };
+        let keyword: String = data[..nul].iter().map(|&byte| byte as char).collect();
+        let rest = &data[nul + 1..];
+        match &kind {
+            b"tEXt" => texts.push((keyword, rest.iter().map(|&byte| byte as char).collect())),
+            b"iTXt" if rest.len() >= 2 && rest[0] == 0 => {
+                // Skip the compression method, language tag and translated
+                // keyword.
+                let mut fields = rest[2..].splitn(3, |&byte| byte == 0);
+                let (Some(_), Some(_), Some(text)) = (fields.next(), fields.next(), fields.next())
+                else {
+                    continue;
+                };
+                if let Ok(text) = core::str::from_utf8(text) {
+                    texts.push((keyword, text.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+    texts
+}
+
+/// Splits back-to-back PNG files, such as the images of a batch, at the end
+/// of each `IEND` chunk. Anything that does not start with a PNG, including
+/// trailing bytes, is kept as one last piece.
+pub fn split(data: &[u8]) -> Vec<&[u8]> {
+    let mut images = Vec::new();
+    let mut rest = data;
+    while let Some(len) = png_len(rest) {
+        let (image, tail) = rest.split_at(len);
+        images.push(image);
+        rest = tail;
+    }
+    if !rest.is_empty() || images.is_empty() {
+        images.push(rest);
+    }
+    images
+}
+
+/// Length of the PNG at the start of `data`, up to and including `IEND`.
+fn png_len(data: &[u8]) -> Option<usize> {
+    let mut len = SIGNATURE.len();
+    for (kind, chunk) in chunks(data)? {
+        len += 12 + chunk.len();
+        if &kind == b"IEND" {
+            return Some(len);
+        }
+    }
+    None
+}
+
+/// Encodes 8-bit pixels as a PNG. `channels` is 1 (gray), 2 (gray + alpha),
+/// 3 (RGB) or 4 (RGBA).
+pub fn encode(width: u32, height: u32, channels: u8, pixels: &[u8]) -> Vec<u8> {
+    let color_type = match channels {
+        1 => 0,
+        2 => 4,
+        3 => 2,
+        4 => 6,
+        _ => panic!("unsupported channel count {}", channels),
+    };
+    let stride = width as usize * channels as usize;
+    assert_eq!(pixels.len(), stride * height as usize, "pixel buffer size");
+
+    // Every scanline is prefixed with filter type 0 (none).
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &zlib);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Pixels decoded by [`decode`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    /// 1 (gray), 2 (gray + alpha), 3 (RGB) or 4 (RGBA).
+    pub channels: u8,
+    /// Row-major, `channels` bytes per pixel.
+    pub data: Vec<u8>,
+}
+
+/// Decodes a non-interlaced PNG with 8-bit samples. Palette images are
+/// expanded to RGB. `None` for other formats, malformed data or images over
+/// 256 MiB of pixels.
+pub fn decode(png: &[u8]) -> Option<Pixels> {
+    let mut chunks = chunks(png)?;
+    let (kind, ihdr) = chunks.next()?;
+    if &kind != b"IHDR" || ihdr.len() != 13 {
+        return None;
+    }
+    let width = u32::from_be_bytes(ihdr[..4].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr[4..8].try_into().unwrap());
+    let (depth, color_type, interlace) = (ihdr[8], ihdr[9], ihdr[12]);
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return None,
+    };
+    if depth != 8 || interlace != 0 {
+        return None;
+    }
+    let stride = (width as usize).checked_mul(channels)?;
+    if stride.checked_mul(height as usize)? > MAX_DECODED_LEN {
+        return None;
+    }
+    let mut palette = &[][..];
+    let mut zlib = Vec::new();
+    for (kind, data) in chunks {
+        match &kind {
+            b"PLTE" => palette = data,
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let raw_len = stride.checked_add(1)?.checked_mul(height as usize)?;
+    let raw = crate::inflate::zlib_decompress(&zlib, raw_len)?;
+    if raw.len() != raw_len {
+        return None;
+    }
+    let mut data = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = data.split_at_mut(y * stride);
+        let prev = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        unfilter(filter, line, prev, &mut rest[..stride], channels)?;
+    }
+    if color_type == 3 {
+        let mut rgb = Vec::with_capacity(data.len() * 3);
+        for &index in &data {
+            let entry = index as usize * 3;
+            rgb.extend_from_slice(palette.get(entry..entry + 3)?);
+        }
+        return Some(Pixels {
+            width,
+            height,
+            channels: 3,
+            data: rgb,
+        });
+    }
+    Some(Pixels {
+        width,
+        height,
+        channels: channels as u8,
+        data,
+    })
+}
+
+/// Reverses the filter of one scanline into `out`.
+fn unfilter(filter: u8, line: &[u8], prev: Option<&[u8]>, out: &mut [u8], bpp: usize) -> Option<()> {
+    for i in 0..line.len() {
+        let left = if i >= bpp { out[i - bpp] } else { 0 };
+        let up = prev.map_or(0, |prev| prev[i]);
+        let up_left = match prev {
+            Some(prev) if i >= bpp => prev[i - bpp],
+            _ => 0,
+        };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return None,
+        };
+        out[i] = line[i].wrapping_add(predicted);
+    }
+    Some(())
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_matches_reference() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b"IEND"]), 0xae42_6082);
+    }
+
+    #[test]
+    fn encodes_a_valid_header() {
+        let png = encode(2, 1, 3, &[255, 0, 0, 0, 255, 0]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 2);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 1);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn text_goes_after_the_header() {
+        let png = encode(1, 1, 1, &[0]);
+        let tagged = insert_text(&png, "parameters", "a cat").unwrap();
+        assert_eq!(tagged[..33], png[..33]);
+        assert_eq!(&tagged[37..41], b"tEXt");
+        assert_eq!(&tagged[41..57], b"parameters\0a cat");
+        assert_eq!(tagged[61..], png[33..]);
+        assert_eq!(insert_text(b"GIF89a", "parameters", "a cat"), None);
+
+        let (kind, data) = text_chunk("parameters", "猫");
+        assert_eq!(&kind, b"iTXt");
+        assert_eq!(data, "parameters\0\0\0\0\0猫".as_bytes());
+    }
+
+    #[test]
+    fn reads_text_back() {
+        let png = encode(1, 1, 1, &[0]);
+        let png = insert_text(&png, "parameters", "café").unwrap();
+        let png = insert_text(&png, "comment", "猫").unwrap();
+        assert_eq!(
+            text_chunks(&png),
+            vec![
+                ("comment".to_string(), "猫".to_string()),
+                ("parameters".to_string(), "café".to_string()),
+            ]
+        );
+        let kinds: Vec<[u8; 4]> = chunks(&png).unwrap().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [*b"IHDR", *b"iTXt", *b"tEXt", *b"IDAT", *b"IEND"]);
+        assert!(chunks(b"GIF89a").is_none());
+        assert!(text_chunks(&png[..40]).is_empty());
+    }
+
+    #[test]
+    fn splits_concatenated_images() {
+        let first = encode(1, 1, 1, &[0]);
+        let second = encode(2, 1, 1, &[0, 0]);
+        let data = [&first[..], &second[..]].concat();
+        assert_eq!(split(&data), vec![&first[..], &second[..]]);
+        assert_eq!(split(&first), vec![&first[..]]);
+        let trailing = [&first[..], b"junk"].concat();
+        assert_eq!(split(&trailing), vec![&first[..], &b"junk"[..]]);
+        assert_eq!(split(b""), vec![&b""[..]]);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let pixels: Vec<u8> = (0..5 * 3 * 4).map(|i| (i * 37) as u8).collect();
+        let decoded = decode(&encode(5, 3, 4, &pixels)).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.channels), (5, 3, 4));
+        assert_eq!(decoded.data, pixels);
+    }
+
+    #[test]
+    fn decodes_every_filter() {
+        // 2x5 RGB, row `y` filtered with filter type `y`, compressed by zlib.
+        let png: Vec<u8> = "89504e470d0a1a0a0000000d4948445200000002000000050802000000e0d1aa\
+             cb000000274944415478da63e0ce3caedc789ff1aef54c090909a64b60c0ac7593e36be957\
+             1620f3d5ab570011da124c300ce0be0000000049454e44ae426082"
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect();
+        let decoded = decode(&png).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.channels), (2, 5, 3));
+        assert_eq!(
+            decoded.data,
+            [
+                11, 105, 199, 35, 129, 223, 221, 59, 153, 245, 83, 177, 175, 13, 107, 199, 37,
+                131, 129, 223, 61, 153, 247, 85, 83, 177, 15, 107, 201, 39
+            ]
+        );
+        assert_eq!(decode(&png[..png.len() - 20]), None);
+        assert_eq!(decode(b"not a png"), None);
+    }
+
+    #[test]
+    fn refuses_huge_images() {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&8193u32.to_be_bytes());
+        ihdr.extend_from_slice(&8192u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &[0x78, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        write_chunk(&mut png, b"IEND", &[]);
+        assert_eq!(decode(&png), None);
+    }
+}
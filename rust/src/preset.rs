@@ -0,0 +1,245 @@
+//! Model and generation settings stored together as a JSON or TOML file.
+//!
+//! Enums are written by their stable names (`"dpm++2m"`, `"karras"`, `"q8_0"`),
+//! and fields left out of a file take their default value.
+use crate::{
+    BaseFunction, Context, ContextConfig, GenerationParams, SdError, StableDiffusion,
+    StableDiffusionBuilder, Task, DEFAULT_IMG2IMG_STRENGTH,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A complete model + generation setup.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub task: Task,
+    /// img2img and inpainting strength; ignored by txt2img.
+    pub strength: f32,
+    pub model: ContextConfig,
+    pub params: GenerationParams<'static>,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            task: Task::TextToImage,
+            strength: DEFAULT_IMG2IMG_STRENGTH,
+            model: ContextConfig::default(),
+            params: GenerationParams::default(),
+        }
+    }
+}
+
+impl Preset {
+    /// Captures the model of `sd` and the current settings of `context`.
+    /// The control image, init image and mask are not part of a preset.
+    pub fn capture(sd: &StableDiffusion, context: &Context) -> Preset {
+        let (params, strength) = match context {
+            Context::TextToImage(text_to_image) => {
+                (text_to_image.params(), DEFAULT_IMG2IMG_STRENGTH)
+            }
+            Context::ImageToImage(image_to_image) => {
+                (image_to_image.params(), image_to_image.strength)
+            }
+            Context::Inpaint(inpaint) => (inpaint.params(), inpaint.strength),
+            Context::Upscale(upscale) => (&upscale.common.params, DEFAULT_IMG2IMG_STRENGTH),
+        };
+        Preset {
+            task: sd.task(),
+            strength,
+            model: sd.config().clone(),
+            params: params.without_control_image(),
+        }
+    }
+
+    /// Builder for the model described by this preset.
+    pub fn builder(&self) -> StableDiffusionBuilder {
+        StableDiffusion::builder()
+            .task(self.task)
+            .config(self.model.clone())
+    }
+
+    /// Replaces the generation settings of `context` with this preset's.
+    pub fn apply(&self, context: &mut Context) {
+        match context {
+            Context::TextToImage(text_to_image) => {
+                text_to_image.set_params(self.params.clone());
+            }
+            Context::ImageToImage(image_to_image) => {
+                image_to_image.set_params(self.params.clone());
+                image_to_image.set_strength(self.strength);
+            }
+            Context::Inpaint(inpaint) => {
+                inpaint.set_params(self.params.clone());
+                inpaint.set_strength(self.strength);
+            }
+            // Upscaling takes no generation settings.
+            Context::Upscale(_) => {}
+        }
+    }
+
+    pub fn from_json(text: &str) -> Result<Preset, SdError> {
+        serde_json::from_str(text).map_err(|err| SdError::format("JSON preset", err))
+    }
+
+    pub fn to_json(&self) -> Result<String, SdError> {
+        serde_json::to_string_pretty(self).map_err(|err| SdError::format("JSON preset", err))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Preset, SdError> {
+        toml::from_str(text).map_err(|err| SdError::format("TOML preset", err))
+    }
+
+    pub fn to_toml(&self) -> Result<String, SdError> {
+        toml::to_string_pretty(self).map_err(|err| SdError::format("TOML preset", err))
+    }
+
+    /// Reads a preset, choosing JSON or TOML from the file extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Preset, SdError> {
+        let path = path.as_ref();
+        let format = PresetFormat::of(path)?;
+        let text = std::fs::read_to_string(path).map_err(|err| SdError::io(path, err))?;
+        match format {
+            PresetFormat::Json => Preset::from_json(&text),
+            PresetFormat::Toml => Preset::from_toml(&text),
+        }
+    }
+
+    /// Writes the preset, choosing JSON or TOML from the file extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SdError> {
+        let path = path.as_ref();
+        let text = match PresetFormat::of(path)? {
+            PresetFormat::Json => self.to_json()?,
+            PresetFormat::Toml => self.to_toml()?,
+        };
+        std::fs::write(path, text).map_err(|err| SdError::io(path, err))
+    }
+}
+
+enum PresetFormat {
+    Json,
+    Toml,
+}
+
+impl PresetFormat {
+    fn of(path: &Path) -> Result<PresetFormat, SdError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Ok(PresetFormat::Json),
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => Ok(PresetFormat::Toml),
+            _ => Err(SdError::invalid(
+                "path",
+                format!("expected a .json or .toml preset, got {}", path.display()),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::test_util::TempDir;
+    use crate::{GuidanceParams, DEFAULT_SLG_LAYERS};
+    use crate::stable_diffusion_interface::*;
+    use std::sync::Arc;
+
+    fn preset() -> Preset {
+        Preset {
+            task: Task::ImageToImage,
+            strength: 0.55,
+            model: ContextConfig {
+                model_path: "models/sd-v1-4.gguf".to_string(),
+                lora_model_dir: "loras".to_string(),
+                vae_decode_only: false,
+                n_threads: 8,
+                wtype: SdTypeT::SdTypeQ8_0,
+                rng_type: RngTypeT::CUDARng,
+                schedule: ScheduleT::KARRAS,
+                ..ContextConfig::default()
+            },
+            params: GenerationParams {
+                prompt: "a lovely cat, \"oil painting\"".to_string(),
+                negative_prompt: "blurry".to_string(),
+                width: 768,
+                height: 512,
+                guidance: GuidanceParams {
+                    cfg_scale: 6.1,
+                    slg_scale: 2.5,
+                    ..GuidanceParams::default()
+                },
+                sample_method: SampleMethodT::DPMPP2M,
+                sample_steps: 28,
+                seed: 0xdead_beef,
+                control_strength: 0.3,
+                ..GenerationParams::default()
+            },
+        }
+    }
+
+    #[test]
+    fn enums_use_stable_names() {
+        let json = preset().to_json().unwrap();
+        assert!(json.contains("\"task\": \"img2img\""));
+        assert!(json.contains("\"wtype\": \"q8_0\""));
+        assert!(json.contains("\"schedule\": \"karras\""));
+        assert!(json.contains("\"sample_method\": \"dpm++2m\""));
+        assert!(json.contains("\"cfg_scale\": 6.1"));
+        assert!(!json.contains("control_image"));
+    }
+
+    #[test]
+    fn json_and_toml_round_trip() {
+        let preset = preset();
+        assert_eq!(Preset::from_json(&preset.to_json().unwrap()).unwrap(), preset);
+        assert_eq!(Preset::from_toml(&preset.to_toml().unwrap()).unwrap(), preset);
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let preset =
+            Preset::from_toml("[params]\nprompt = \"a cat\"\nsample_method = \"lcm\"\ncfg_scale = 1\n").unwrap();
+        assert_eq!(preset.task, Task::TextToImage);
+        assert_eq!(preset.model, ContextConfig::default());
+        assert_eq!(preset.params.prompt, "a cat");
+        assert_eq!(preset.params.sample_method, SampleMethodT::LCM);
+        assert_eq!(preset.params.sample_steps, GenerationParams::default().sample_steps);
+        assert_eq!(preset.params.guidance.cfg_scale, 1.0);
+        assert_eq!(preset.params.guidance.slg_layers, DEFAULT_SLG_LAYERS);
+    }
+
+    #[test]
+    fn unknown_names_and_sentinels_are_rejected() {
+        let err = Preset::from_json(r#"{"params": {"sample_method": "dpmpp"}}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `dpmpp`"), "{}", err);
+
+        let mut preset = Preset::default();
+        preset.params.sample_method = SampleMethodT::NSAMPLEMETHODS;
+        assert!(matches!(preset.to_json(), Err(SdError::Format { .. })));
+    }
+
+    #[test]
+    fn files_pick_the_format_from_the_extension() {
+        let dir = TempDir::new("preset");
+        let preset = preset();
+        for name in ["preset.json", "preset.toml"] {
+            let path = dir.join(name);
+            preset.save(&path).unwrap();
+            assert_eq!(Preset::load(&path).unwrap(), preset);
+        }
+        assert!(matches!(
+            preset.save(dir.join("preset.yaml")),
+            Err(SdError::InvalidArgument { field: "path", .. })
+        ));
+    }
+
+    #[test]
+    fn capture_and_apply_a_context() {
+        let backend = Arc::new(MockBackend::new());
+        let preset = preset();
+        let sd = preset.builder().backend(backend.clone()).finish();
+        let mut context = sd.create_context().unwrap();
+        preset.apply(&mut context);
+        assert_eq!(Preset::capture(&sd, &context), preset);
+    }
+}
@@ -0,0 +1,176 @@
+//! Reads the JSON header of a `.safetensors` file.
+//!
+//! The file is an 8-byte little-endian header length, the header, then the
+//! tensor data. See <https://github.com/huggingface/safetensors> for the
+//! format.
+use crate::json::{self, Json};
+use crate::model_file::{ModelFileInfo, ModelFormat, ReadError, TensorEntry};
+use crate::stable_diffusion_interface::SdTypeT;
+use std::io::Read;
+
+/// Largest header accepted, as in the reference implementation.
+const MAX_HEADER_LEN: u64 = 100 << 20;
+
+/// Reads the header from the start of a file `file_len` bytes long, checking
+/// that every tensor lies inside the file.
+pub(crate) fn read(mut reader: impl Read, file_len: u64) -> Result<ModelFileInfo, ReadError> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let header_len = u64::from_le_bytes(len);
+    if header_len > MAX_HEADER_LEN || header_len > file_len.saturating_sub(8) {
+        return Err(ReadError::format(format!(
+            "header length {} does not fit the file; is it a safetensors file?",
+            header_len
+        )));
+    }
+    let mut header = vec![0; header_len as usize];
+    reader.read_exact(&mut header)?;
+    let header = std::str::from_utf8(&header)
+        .map_err(|_| ReadError::format("header is not UTF-8"))?;
+    parse(header, file_len - 8 - header_len)
+}
+
+/// Parses a header followed by `data_len` bytes of tensor data.
+fn parse(header: &str, data_len: u64) -> Result<ModelFileInfo, ReadError> {
+    let Json::Object(entries) = json::parse(header.trim_end()).map_err(ReadError::Format)? else {
+        return Err(ReadError::format("header is not a JSON object"));
+    };
+    let mut metadata = Vec::new();
+    let mut tensors = Vec::new();
+    for (name, entry) in &entries {
+        if name == "__metadata__" {
+            let Json::Object(pairs) = entry else {
+                return Err(ReadError::format("__metadata__ is not an object"));
+            };
+            for (key, value) in pairs {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| ReadError::format(format!("metadata {} is not a string", key)))?;
+                metadata.push((key.clone(), value.to_string()));
+            }
+            continue;
+        }
+        let invalid = |what: &str| ReadError::format(format!("tensor {} has {}", name, what));
+        let dtype = entry
+            .get("dtype")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid("no dtype"))?;
+        let shape = entry
+            .get("shape")
+            .and_then(Json::as_array)
+            .and_then(|dims| dims.iter().map(Json::as_u64).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| invalid("an invalid shape"))?;
+        let (begin, end) = match entry.get("data_offsets").and_then(Json::as_array) {
+            Some([begin, end]) => (begin.as_u64(), end.as_u64()),
+            _ => (None, None),
+        };
+        let (Some(begin), Some(end)) = (begin, end) else {
+            return Err(invalid("invalid data_offsets"));
+        };
+        if begin > end || end > data_len {
+            return Err(invalid("data past the end of the file"));
+        }
+        if let Some(bits) = dtype_bits(dtype) {
+            let elements = shape.iter().try_fold(1u64, |n, &dim| n.checked_mul(dim));
+            if elements.and_then(|n| n.checked_mul(bits)) != Some((end - begin) * 8) {
+                return Err(invalid("a data size that does not match its shape"));
+            }
+        }
+        tensors.push(TensorEntry {
+            name: name.clone(),
+            dtype: dtype.to_string(),
+            sd_type: sd_type(dtype),
+            shape,
+        });
+    }
+    Ok(ModelFileInfo {
+        format: ModelFormat::Safetensors,
+        metadata,
+        tensors,
+    })
+}
+
+/// The type the host loads a safetensors dtype as.
+fn sd_type(dtype: &str) -> Option<SdTypeT> {
+    Some(match dtype {
+        "F64" => SdTypeT::SdTypeF64,
+        "F32" => SdTypeT::SdTypeF32,
+        // 8-bit floats are widened on load.
+        "F16" | "F8_E4M3" | "F8_E5M2" => SdTypeT::SdTypeF16,
+        "BF16" => SdTypeT::SdTypeBf16,
+        "I64" => SdTypeT::SdTypeI64,
+        "I32" => SdTypeT::SdTypeI32,
+        "I16" => SdTypeT::SdTypeI16,
+        "I8" => SdTypeT::SdTypeI8,
+        _ => return None,
+    })
+}
+
+fn dtype_bits(dtype: &str) -> Option<u64> {
+    Some(match dtype {
+        "F64" | "I64" | "U64" => 64,
+        "F32" | "I32" | "U32" => 32,
+        "F16" | "BF16" | "I16" | "U16" => 16,
+        "F8_E4M3" | "F8_E5M2" | "I8" | "U8" | "BOOL" => 8,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(header: &str, data_len: usize) -> Vec<u8> {
+        let mut out = (header.len() as u64).to_le_bytes().to_vec();
+        out.extend_from_slice(header.as_bytes());
+        out.resize(out.len() + data_len, 0);
+        out
+    }
+
+    fn read_bytes(data: &[u8]) -> Result<ModelFileInfo, String> {
+        read(data, data.len() as u64).map_err(|err| match err {
+            ReadError::Format(message) => message,
+            ReadError::Io(err) => err.to_string(),
+        })
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let data = file(
+            r#"{"__metadata__":{"format":"pt"},
+                "first_stage_model.decoder.conv_in.weight":{"dtype":"F16","shape":[512,4,3,3],"data_offsets":[0,36864]},
+                "model.diffusion_model.double_blocks.0.img_attn.qkv.weight":{"dtype":"F8_E4M3","shape":[2,3],"data_offsets":[36864,36870]},
+                "flags":{"dtype":"BOOL","shape":[2],"data_offsets":[36870,36872]}}  "#,
+            36872,
+        );
+        let info = read_bytes(&data).unwrap();
+        assert_eq!(info.format, ModelFormat::Safetensors);
+        assert_eq!(info.get("format"), Some("pt"));
+        assert_eq!(info.tensors.len(), 3);
+        assert_eq!(info.tensors[0].shape, [512, 4, 3, 3]);
+        assert_eq!(info.tensors[0].sd_type, Some(SdTypeT::SdTypeF16));
+        assert_eq!(info.tensors[1].dtype, "F8_E4M3");
+        assert_eq!(info.tensors[1].sd_type, Some(SdTypeT::SdTypeF16));
+        assert_eq!(info.tensors[2].sd_type, None);
+        assert_eq!(info.model_version(), crate::model_file::ModelVersion::Flux);
+        assert!(info.has_vae());
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let header = r#"{"w":{"dtype":"F32","shape":[2,2],"data_offsets":[0,16]}}"#;
+        let truncated = file(header, 8);
+        assert_eq!(read_bytes(&truncated).unwrap_err(), "tensor w has data past the end of the file");
+        let wrong_size = file(r#"{"w":{"dtype":"F32","shape":[3],"data_offsets":[0,16]}}"#, 16);
+        assert_eq!(
+            read_bytes(&wrong_size).unwrap_err(),
+            "tensor w has a data size that does not match its shape"
+        );
+        assert!(read_bytes(&file(header, 16)[..20]).unwrap_err().contains("does not fit the file"));
+        assert!(read_bytes(b"PK\x03\x04 not safetensors")
+            .unwrap_err()
+            .ends_with("is it a safetensors file?"));
+        assert!(read_bytes(&file("[1, 2]", 0)).unwrap_err().contains("not a JSON object"));
+        assert!(read_bytes(&file(r#"{"w":{"dtype":"F32"}}"#, 0)).unwrap_err().contains("invalid shape"));
+    }
+}
@@ -0,0 +1,66 @@
+//! How seeds are derived and handed to the host.
+//!
+//! The API takes `u64` seeds so seeds from other tools are kept whole. The
+//! host takes an `i32` and treats negative values as "pick a random seed", so
+//! [`host_seed`] maps every `u64` to `0..=i32::MAX`: seeds up to `i32::MAX`
+//! pass through unchanged and larger ones are folded by XOR-ing their 31-bit
+//! limbs.
+//!
+//! Image `i` of a batch uses seed [`derive`]`(seed, i)`, which is
+//! `seed + i` wrapping at `u64::MAX`. Generating with that seed and a batch
+//! count of 1 reproduces the image on its own. The host derives batch seeds
+//! the same way from its `i32` seed, so a batch is sent in one call only when
+//! all its seeds pass through [`host_seed`] unchanged; otherwise the images are
+//! requested one at a time.
+
+/// Largest seed the host receives unchanged.
+pub const HOST_SEED_MAX: u64 = i32::MAX as u64;
+
+/// Seed passed to the host for `seed`.
+pub fn host_seed(seed: u64) -> i32 {
+    let folded = (seed & HOST_SEED_MAX) ^ ((seed >> 31) & HOST_SEED_MAX) ^ (seed >> 62);
+    folded as i32
+}
+
+/// Seed of image `index` of a batch or sweep starting at `seed`.
+pub fn derive(seed: u64, index: usize) -> u64 {
+    seed.wrapping_add(index as u64)
+}
+
+/// Whether the host's own per-image seeds match [`derive`] for the whole
+/// batch.
+pub(crate) fn host_derives_batch(seed: u64, batch_count: i32) -> bool {
+    let last = (batch_count.max(1) - 1) as u64;
+    seed.checked_add(last).is_some_and(|last_seed| last_seed <= HOST_SEED_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_seeds_pass_through() {
+        assert_eq!(host_seed(0), 0);
+        assert_eq!(host_seed(42), 42);
+        assert_eq!(host_seed(HOST_SEED_MAX), i32::MAX);
+    }
+
+    #[test]
+    fn large_seeds_fold_into_the_host_range() {
+        assert_eq!(host_seed(1 << 31), 1);
+        assert_eq!(host_seed(u32::MAX as u64), (u32::MAX >> 1) as i32 ^ 1);
+        for seed in [u64::MAX, 1 << 62, 0xdead_beef_cafe_f00d] {
+            assert!(host_seed(seed) >= 0);
+        }
+        assert_ne!(host_seed(0xdead_beef_cafe_f00d), host_seed(0xdead_beef_cafe_f00e));
+    }
+
+    #[test]
+    fn batches_cross_into_folded_seeds() {
+        assert_eq!(derive(u64::MAX, 1), 0);
+        assert!(host_derives_batch(42, 4));
+        assert!(host_derives_batch(HOST_SEED_MAX, 1));
+        assert!(!host_derives_batch(HOST_SEED_MAX, 2));
+        assert!(!host_derives_batch(u64::MAX, 1));
+    }
+}
@@ -1,6 +1,10 @@
 use core::fmt;
 use std::error::Error;
-use core::mem::MaybeUninit;
+use std::str::FromStr;
+use crate::image_size;
+#[cfg(feature = "host-v2")]
+use crate::marshal::encode_i32s;
+use crate::marshal::{encode_image, HostSlice, HostSliceMut};
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct WasmedgeSdErrno(u32);
@@ -15,6 +19,11 @@ impl WasmedgeSdErrno {
         self.0
     }
 
+    /// Wraps a code returned by the host.
+    pub const fn from_raw(code: u32) -> WasmedgeSdErrno {
+        WasmedgeSdErrno(code)
+    }
+
     pub fn name(&self) -> &'static str {
         match self.0 {
             0 => "SUCCESS",
@@ -23,18 +32,18 @@ impl WasmedgeSdErrno {
             3 => "MISSING_MEMORY",
             4 => "BUSY",
             5 => "RUNTIME_ERROR",
-            _ => unsafe { core::hint::unreachable_unchecked() },
+            _ => "UNKNOWN",
         }
     }
     pub fn message(&self) -> &'static str {
         match self.0 {
-            0 => "",
-            1 => "",
-            2 => "",
-            3 => "",
-            4 => "",
-            5 => "",
-            _ => unsafe { core::hint::unreachable_unchecked() },
+            0 => "no error",
+            1 => "the host rejected an argument",
+            2 => "an argument is not valid UTF-8 or contains a NUL byte",
+            3 => "the output buffer is too small or the host ran out of memory",
+            4 => "the host is busy with another request",
+            5 => "the stable diffusion runtime failed",
+            _ => "error code not known to this version of the crate",
         }
     }
 }
@@ -55,12 +64,9 @@ impl fmt::Display for WasmedgeSdErrno {
 
 impl Error for WasmedgeSdErrno {}
 
-#[cfg(feature = "std")]
-extern crate std;
-#[cfg(feature = "std")]
-impl std::error::Error for WasmedgeSdErrno {}
-
-#[derive(Copy, Clone, Debug)]
+/// Weight type, by ggml id. `SdTypeCount` asks the host to keep the type
+/// stored in the weight file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SdTypeT {
     SdTypeF32 = 0,
     SdTypeF16 = 1,
@@ -95,12 +101,16 @@ pub enum SdTypeT {
     SdTypeBf16 = 30,
     SdTypeCount = 31,
 }
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RngTypeT {
     StdDefaultRng = 0,
     CUDARng = 1,
 }
-#[derive(Copy, Clone, Debug)]
+/// Sampling method, by stable-diffusion.cpp id.
+///
+/// Plugins with the `host-v2` functions take these ids as they are. Older
+/// plugins predate iPNDM and number `LCM` 7; see [`SampleMethodT::legacy_id`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SampleMethodT {
     EULERA = 0,
     EULER = 1,
@@ -109,112 +119,305 @@ pub enum SampleMethodT {
     DPMPP2SA = 4,
     DPMPP2M = 5,
     DPMPP2Mv2 = 6,
-    LCM = 7,
-    NSAMPLEMETHODS = 8,
+    IPNDM = 7,
+    IPNDMV = 8,
+    LCM = 9,
+    DDIMTRAILING = 10,
+    TCD = 11,
+    NSAMPLEMETHODS = 12,
 }
-#[derive(Copy, Clone, Debug)]
+/// Sigma schedule, by stable-diffusion.cpp id. As with [`SampleMethodT`],
+/// older plugins use other ids; see [`ScheduleT::legacy_id`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ScheduleT {
     DEFAULT = 0,
     DISCRETE = 1,
     KARRAS = 2,
-    AYS = 3,
-    NSCHEDULES = 4,
-}
-pub enum ImageType<'a> {
-    Path(&'a str),
+    EXPONENTIAL = 3,
+    AYS = 4,
+    GITS = 5,
+    NSCHEDULES = 6,
 }
-fn parse_image(image: &ImageType) -> (i32, i32) {
-    return match image {
-        ImageType::Path(path) => {
-            if path.is_empty() {
-                return (0, 0);
-            }
-            let path = "path:".to_string() + path;
-            (path.as_ptr() as i32, path.len() as i32)
+
+impl SampleMethodT {
+    /// Id of the method in plugins without the `host-v2` functions, or `None`
+    /// for the methods they lack. They stop at `LCM`, which they number 7.
+    pub fn legacy_id(self) -> Option<i32> {
+        match self {
+            SampleMethodT::LCM => Some(7),
+            SampleMethodT::IPNDM
+            | SampleMethodT::IPNDMV
+            | SampleMethodT::DDIMTRAILING
+            | SampleMethodT::TCD
+            | SampleMethodT::NSAMPLEMETHODS => None,
+            method => Some(method as i32),
         }
-    };
+    }
 }
 
-//as for wtype
-impl SdTypeT{
-    pub fn from_index(index: usize) -> Result<SdTypeT, String> {
-        match index {
-            0 => Ok(SdTypeT::SdTypeF32),
-            1 => Ok(SdTypeT::SdTypeF16),
-            2 => Ok(SdTypeT::SdTypeQ4_0),
-            3 => Ok(SdTypeT::SdTypeQ4_1),
-            //4
-            //5
-            6 => Ok(SdTypeT::SdTypeQ5_0),
-            7 => Ok(SdTypeT::SdTypeQ5_1),
-            8 => Ok(SdTypeT::SdTypeQ8_0),
-            9 => Ok(SdTypeT::SdTypeQ8_1),
-            10 => Ok(SdTypeT::SdTypeQ2K),
-            11 => Ok(SdTypeT::SdTypeQ3K),
-            12 => Ok(SdTypeT::SdTypeQ4K),
-            13 => Ok(SdTypeT::SdTypeQ5K),
-            14 => Ok(SdTypeT::SdTypeQ6K),
-            15 => Ok(SdTypeT::SdTypeQ8K),
-            16 => Ok(SdTypeT::SdTypeIq2Xxs),
-            17 => Ok(SdTypeT::SdTypeIq2Xs),
-            18 => Ok(SdTypeT::SdTypeIq3Xxs),
-            19 => Ok(SdTypeT::SdTypeIq1S), 
-            20 => Ok(SdTypeT::SdTypeIq4Nl),
-            21 => Ok(SdTypeT::SdTypeIq3S),
-            22 => Ok(SdTypeT::SdTypeIq2S),
-            23 => Ok(SdTypeT::SdTypeIq4Xs),
-            24 => Ok(SdTypeT::SdTypeI8),
-            25 => Ok(SdTypeT::SdTypeI16),
-            26 => Ok(SdTypeT::SdTypeI32),
-            27 => Ok(SdTypeT::SdTypeI64),
-            28 => Ok(SdTypeT::SdTypeF64),
-            29 => Ok(SdTypeT::SdTypeIq1M), 
-            30 => Ok(SdTypeT::SdTypeBf16),
-            _ => Ok(SdTypeT::SdTypeCount),
+impl ScheduleT {
+    /// Id of the schedule in plugins without the `host-v2` functions, or
+    /// `None` for the schedules they lack. They stop at `AYS`, which they
+    /// number 3.
+    pub fn legacy_id(self) -> Option<i32> {
+        match self {
+            ScheduleT::AYS => Some(3),
+            ScheduleT::EXPONENTIAL | ScheduleT::GITS | ScheduleT::NSCHEDULES => None,
+            schedule => Some(schedule as i32),
         }
     }
 }
 
-impl SampleMethodT {
-    pub fn from_index(index: usize) -> Result<SampleMethodT, String> {
-        match index {
-            0 => Ok(SampleMethodT::EULERA),
-            1 => Ok(SampleMethodT::EULER),
-            2 => Ok(SampleMethodT::HEUN),
-            3 => Ok(SampleMethodT::DPM2),
-            4 => Ok(SampleMethodT::DPMPP2SA),
-            5 => Ok(SampleMethodT::DPMPP2M),
-            6 => Ok(SampleMethodT::DPMPP2Mv2),
-            7 => Ok(SampleMethodT::LCM),
-            _ => Ok(SampleMethodT::NSAMPLEMETHODS),
-        }
+/// The id a plugin built for this crate's features expects for `method`.
+fn host_sample_method(method: SampleMethodT) -> Result<i32, WasmedgeSdErrno> {
+    if cfg!(feature = "host-v2") {
+        Ok(method as i32)
+    } else {
+        method.legacy_id().ok_or(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
     }
 }
 
-impl ScheduleT {
-    pub fn from_index(index: usize) -> Result<ScheduleT, String> {
-        match index {
-            0 => Ok(ScheduleT::DEFAULT),
-            1 => Ok(ScheduleT::DISCRETE),
-            2 => Ok(ScheduleT::KARRAS),
-            3 => Ok(ScheduleT::AYS),
-            _ => Ok(ScheduleT::NSCHEDULES),
+/// The id a plugin built for this crate's features expects for `schedule`.
+fn host_schedule(schedule: ScheduleT) -> Result<i32, WasmedgeSdErrno> {
+    if cfg!(feature = "host-v2") {
+        Ok(schedule as i32)
+    } else {
+        schedule.legacy_id().ok_or(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT)
+    }
+}
+/// Gives an enum the stable string names used by presets, image metadata and
+/// the command line, conversions from names and host ids, and with the
+/// `serde` feature (de)serialization by name.
+///
+/// The optional `sentinel` variant only marks the end of the C enum: it has a
+/// name for display, but is neither parsed, converted nor serialized. A
+/// count the host takes as a value, like `SdTypeCount`, is listed as an
+/// ordinary variant instead.
+///
+/// Names after `|` are older spellings: `from_name` accepts them, but they
+/// are never produced.
+macro_rules! named_enum {
+    ($ty:ident $label:literal { $($variant:ident => $name:literal $(| $alias:literal)*,)* } $(sentinel $sentinel:ident => $sentinel_name:literal)?) => {
+        impl $ty {
+            /// Names accepted by [`Self::from_name`], in declaration order.
+            pub const NAMES: &'static [&'static str] = &[$($name),*];
+            /// Every variant except the sentinel, in declaration order.
+            pub const ALL: &'static [$ty] = &[$($ty::$variant),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($ty::$variant => $name,)*
+                    $($ty::$sentinel => $sentinel_name,)?
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$ty> {
+                match name {
+                    $($name $(| $alias)* => Some($ty::$variant),)*
+                    _ => None,
+                }
+            }
+
+            /// Older spellings [`Self::from_name`] also accepts.
+            pub fn aliases(&self) -> &'static [&'static str] {
+                match self {
+                    $($ty::$variant => &[$($alias),*],)*
+                    $($ty::$sentinel => &[],)?
+                }
+            }
+
+            /// Iterates over [`Self::ALL`].
+            pub fn all() -> impl Iterator<Item = $ty> {
+                Self::ALL.iter().copied()
+            }
+
+            /// The variant with host id `index`. Ids that are out of range,
+            /// retired or the sentinel are errors.
+            pub fn from_index(index: usize) -> Result<$ty, String> {
+                i32::try_from(index)
+                    .map_err(|_| format!(concat!("Invalid ", $label, " id: {}"), index))
+                    .and_then($ty::try_from)
+            }
+        }
+
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $ty::from_name(s).ok_or_else(|| {
+                    format!(
+                        concat!("Invalid ", $label, ": {} (expected one of {})"),
+                        s,
+                        $ty::NAMES.join(", ")
+                    )
+                })
+            }
+        }
+
+        impl TryFrom<i32> for $ty {
+            type Error = String;
+
+            fn try_from(id: i32) -> Result<Self, Self::Error> {
+                $ty::all()
+                    .find(|variant| *variant as i32 == id)
+                    .ok_or_else(|| format!(concat!("Invalid ", $label, " id: {}"), id))
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $(if let $ty::$sentinel = self {
+                    return Err(serde::ser::Error::custom(concat!(
+                        "`", $sentinel_name, "` is not a valid ", stringify!($ty)
+                    )));
+                })?
+                serializer.serialize_str(self.name())
+            }
         }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<$ty, D::Error> {
+                let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+                $ty::from_name(&name)
+                    .ok_or_else(|| serde::de::Error::unknown_variant(&name, $ty::NAMES))
+            }
+        }
+    };
+}
+
+named_enum!(SdTypeT "weight type" {
+    SdTypeF32 => "f32",
+    SdTypeF16 => "f16",
+    SdTypeQ4_0 => "q4_0",
+    SdTypeQ4_1 => "q4_1",
+    SdTypeQ5_0 => "q5_0",
+    SdTypeQ5_1 => "q5_1",
+    SdTypeQ8_0 => "q8_0",
+    SdTypeQ8_1 => "q8_1",
+    SdTypeQ2K => "q2_k" | "q2k",
+    SdTypeQ3K => "q3_k" | "q3k",
+    SdTypeQ4K => "q4_k" | "q4k",
+    SdTypeQ5K => "q5_k" | "q5k",
+    SdTypeQ6K => "q6_k" | "q6k",
+    SdTypeQ8K => "q8_k" | "q8k",
+    SdTypeIq2Xxs => "iq2_xxs" | "iq2Xxs",
+    SdTypeIq2Xs => "iq2_xs" | "iq2Xs",
+    SdTypeIq3Xxs => "iq3_xxs" | "iq3Xxs",
+    SdTypeIq1S => "iq1_s" | "iq1S",
+    SdTypeIq4Nl => "iq4_nl" | "iq4N1",
+    SdTypeIq3S => "iq3_s" | "iq3S",
+    SdTypeIq2S => "iq2_s" | "iq2S",
+    SdTypeIq4Xs => "iq4_xs" | "iq4Xs",
+    SdTypeI8 => "i8",
+    SdTypeI16 => "i16",
+    SdTypeI32 => "i32",
+    SdTypeI64 => "i64",
+    SdTypeF64 => "f64",
+    SdTypeIq1M => "iq1_m" | "iq1M",
+    SdTypeBf16 => "bf16",
+    // The host reads the C enum's count as "keep the type stored in the
+    // weight file", so it is a value here rather than a sentinel.
+    SdTypeCount => "default" | "count",
+});
+
+named_enum!(RngTypeT "rng type" {
+    StdDefaultRng => "std_default",
+    CUDARng => "cuda",
+});
+
+named_enum!(SampleMethodT "sampling method" {
+    EULERA => "euler_a",
+    EULER => "euler",
+    HEUN => "heun",
+    DPM2 => "dpm2",
+    DPMPP2SA => "dpm++2s_a",
+    DPMPP2M => "dpm++2m",
+    DPMPP2Mv2 => "dpm++2mv2",
+    IPNDM => "ipndm",
+    IPNDMV => "ipndm_v",
+    LCM => "lcm",
+    DDIMTRAILING => "ddim_trailing",
+    TCD => "tcd",
+} sentinel NSAMPLEMETHODS => "n_sample_methods");
+
+named_enum!(ScheduleT "schedule" {
+    DEFAULT => "default",
+    DISCRETE => "discrete",
+    KARRAS => "karras",
+    EXPONENTIAL => "exponential",
+    AYS => "ays",
+    GITS => "gits",
+} sentinel NSCHEDULES => "n_schedules");
+
+/// Image argument passed to the host.
+///
+/// Each variant is sent with its own prefix: `path:` followed by the path,
+/// `bytes:` followed by an encoded PNG/JPEG, or `rgb:` followed by width,
+/// height and channel count as little-endian `u32` and then the raw pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageType<'a> {
+    /// Path of an image file readable by the host.
+    Path(&'a str),
+    /// Encoded PNG or JPEG data.
+    Bytes(&'a [u8]),
+    /// Raw 8-bit pixels, row-major, `channels` bytes per pixel.
+    Rgb {
+        width: u32,
+        height: u32,
+        channels: u32,
+        data: &'a [u8],
+    },
+}
+impl Default for ImageType<'_> {
+    fn default() -> Self {
+        ImageType::Path("")
     }
 }
+impl ImageType<'_> {
+    /// Whether no image was given.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ImageType::Path(path) => path.is_empty(),
+            ImageType::Bytes(data) => data.is_empty(),
+            ImageType::Rgb { data, .. } => data.is_empty(),
+        }
+    }
 
+    /// Width and height of the image, read from the PNG or JPEG header for
+    /// `Path` and `Bytes`. `None` if the size cannot be determined, such as
+    /// for an unreadable file.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match *self {
+            ImageType::Path(path) => image_size::dimensions(&std::fs::read(path).ok()?),
+            ImageType::Bytes(data) => image_size::dimensions(data),
+            ImageType::Rgb { width, height, .. } => Some((width, height)),
+        }
+    }
+}
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
 pub unsafe fn convert(
     model_path: &str,
     vae_model_path: &str,
     output_path: &str,
     wtype: SdTypeT,
 ) -> Result<(), WasmedgeSdErrno> {
-    let model_path_ptr = model_path.as_ptr() as i32;
-    let model_path_len = model_path.len() as i32;
-    let vae_model_path_ptr = vae_model_path.as_ptr() as i32;
-    let vae_model_path_len = vae_model_path.len() as i32;
-    let output_path_ptr = output_path.as_ptr() as i32;
-    let output_path_len = output_path.len() as i32;
+    let model_path = HostSlice::c_str(model_path)?;
+    let (model_path_ptr, model_path_len) = model_path.raw();
+    let vae_model_path = HostSlice::c_str(vae_model_path)?;
+    let (vae_model_path_ptr, vae_model_path_len) = vae_model_path.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
     let result = wasmedge_stablediffusion::convert(
         model_path_ptr,
         model_path_len,
@@ -230,6 +433,51 @@ pub unsafe fn convert(
         Ok(())
     }
 }
+/// Like [`convert`], with `tensor_type_rules` giving some tensors their own
+/// type: comma-separated `regex=type` pairs matched against tensor names.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+#[cfg(feature = "host-v2")]
+pub unsafe fn convert_with_rules(
+    model_path: &str,
+    vae_model_path: &str,
+    output_path: &str,
+    wtype: SdTypeT,
+    tensor_type_rules: &str,
+) -> Result<(), WasmedgeSdErrno> {
+    let model_path = HostSlice::c_str(model_path)?;
+    let (model_path_ptr, model_path_len) = model_path.raw();
+    let vae_model_path = HostSlice::c_str(vae_model_path)?;
+    let (vae_model_path_ptr, vae_model_path_len) = vae_model_path.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
+    let tensor_type_rules = HostSlice::c_str(tensor_type_rules)?;
+    let (tensor_type_rules_ptr, tensor_type_rules_len) = tensor_type_rules.raw();
+    let result = wasmedge_stablediffusion::convert_with_rules(
+        model_path_ptr,
+        model_path_len,
+        vae_model_path_ptr,
+        vae_model_path_len,
+        output_path_ptr,
+        output_path_len,
+        wtype as i32,
+        tensor_type_rules_ptr,
+        tensor_type_rules_len,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. `session_id` must be
+/// valid for writes.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn create_context(
     model_path: &str,
     vae_path: &str,
@@ -249,30 +497,29 @@ pub unsafe fn create_context(
     vae_on_cpu: bool,
     session_id: *mut u32,
 ) -> Result<(), WasmedgeSdErrno> {
-    let model_path_ptr = model_path.as_ptr() as i32;
-    let model_path_len = model_path.len() as i32;
-    let vae_path_ptr = vae_path.as_ptr() as i32;
-    let vae_path_len = vae_path.len() as i32;
-    let taesd_path_ptr = taesd_path.as_ptr() as i32;
-    let taesd_path_len = taesd_path.len() as i32;
-    let control_net_path_ptr = control_net_path.as_ptr() as i32;
-    let control_net_path_len = control_net_path.len() as i32;
-    let lora_model_dir_ptr = lora_model_dir.as_ptr() as i32;
-    let lora_model_dir_len = lora_model_dir.len() as i32;
-    let embed_dir_ptr = embed_dir.as_ptr() as i32;
-    let embed_dir_len = embed_dir.len() as i32;
-    let id_embed_dir_ptr = id_embed_dir.as_ptr() as i32;
-    let id_embed_dir_len = id_embed_dir.len() as i32;
+    let model_path = HostSlice::c_str(model_path)?;
+    let (model_path_ptr, model_path_len) = model_path.raw();
+    let vae_path = HostSlice::c_str(vae_path)?;
+    let (vae_path_ptr, vae_path_len) = vae_path.raw();
+    let taesd_path = HostSlice::c_str(taesd_path)?;
+    let (taesd_path_ptr, taesd_path_len) = taesd_path.raw();
+    let control_net_path = HostSlice::c_str(control_net_path)?;
+    let (control_net_path_ptr, control_net_path_len) = control_net_path.raw();
+    let lora_model_dir = HostSlice::c_str(lora_model_dir)?;
+    let (lora_model_dir_ptr, lora_model_dir_len) = lora_model_dir.raw();
+    let embed_dir = HostSlice::c_str(embed_dir)?;
+    let (embed_dir_ptr, embed_dir_len) = embed_dir.raw();
+    let id_embed_dir = HostSlice::c_str(id_embed_dir)?;
+    let (id_embed_dir_ptr, id_embed_dir_len) = id_embed_dir.raw();
     let vae_decode_only = vae_decode_only as i32;
     let vae_tiling = vae_tiling as i32;
-    let n_threads = n_threads as i32;
     let wtype = wtype as i32;
     let rng_type = rng_type as i32;
-    let schedule = schedule as i32;
+    let schedule = host_schedule(schedule)?;
     let clip_on_cpu = clip_on_cpu as i32;
     let control_net_cpu = control_net_cpu as i32;
     let vae_on_cpu = vae_on_cpu as i32;
-    let session_id_ptr = session_id as i32;
+    let session_id_ptr = session_id;
     let result = wasmedge_stablediffusion::create_context(
         model_path_ptr,
         model_path_len,
@@ -306,6 +553,185 @@ pub unsafe fn create_context(
     }
 }
 
+/// Like [`create_context`], with the separate components of SD3 and Flux
+/// models: a standalone diffusion model and the CLIP-L, CLIP-G and T5-XXL
+/// text encoders. Empty paths are not loaded.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. `session_id` must be
+/// valid for writes.
+#[cfg(feature = "host-v2")]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn create_context_v2(
+    model_path: &str,
+    clip_l_path: &str,
+    clip_g_path: &str,
+    t5xxl_path: &str,
+    diffusion_model_path: &str,
+    vae_path: &str,
+    taesd_path: &str,
+    control_net_path: &str,
+    lora_model_dir: &str,
+    embed_dir: &str,
+    id_embed_dir: &str,
+    vae_decode_only: bool,
+    vae_tiling: bool,
+    n_threads: i32,
+    wtype: SdTypeT,
+    rng_type: RngTypeT,
+    schedule: ScheduleT,
+    clip_on_cpu: bool,
+    control_net_cpu: bool,
+    vae_on_cpu: bool,
+    session_id: *mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let model_path = HostSlice::c_str(model_path)?;
+    let (model_path_ptr, model_path_len) = model_path.raw();
+    let clip_l_path = HostSlice::c_str(clip_l_path)?;
+    let (clip_l_path_ptr, clip_l_path_len) = clip_l_path.raw();
+    let clip_g_path = HostSlice::c_str(clip_g_path)?;
+    let (clip_g_path_ptr, clip_g_path_len) = clip_g_path.raw();
+    let t5xxl_path = HostSlice::c_str(t5xxl_path)?;
+    let (t5xxl_path_ptr, t5xxl_path_len) = t5xxl_path.raw();
+    let diffusion_model_path = HostSlice::c_str(diffusion_model_path)?;
+    let (diffusion_model_path_ptr, diffusion_model_path_len) = diffusion_model_path.raw();
+    let vae_path = HostSlice::c_str(vae_path)?;
+    let (vae_path_ptr, vae_path_len) = vae_path.raw();
+    let taesd_path = HostSlice::c_str(taesd_path)?;
+    let (taesd_path_ptr, taesd_path_len) = taesd_path.raw();
+    let control_net_path = HostSlice::c_str(control_net_path)?;
+    let (control_net_path_ptr, control_net_path_len) = control_net_path.raw();
+    let lora_model_dir = HostSlice::c_str(lora_model_dir)?;
+    let (lora_model_dir_ptr, lora_model_dir_len) = lora_model_dir.raw();
+    let embed_dir = HostSlice::c_str(embed_dir)?;
+    let (embed_dir_ptr, embed_dir_len) = embed_dir.raw();
+    let id_embed_dir = HostSlice::c_str(id_embed_dir)?;
+    let (id_embed_dir_ptr, id_embed_dir_len) = id_embed_dir.raw();
+    let result = wasmedge_stablediffusion::create_context_v2(
+        model_path_ptr,
+        model_path_len,
+        clip_l_path_ptr,
+        clip_l_path_len,
+        clip_g_path_ptr,
+        clip_g_path_len,
+        t5xxl_path_ptr,
+        t5xxl_path_len,
+        diffusion_model_path_ptr,
+        diffusion_model_path_len,
+        vae_path_ptr,
+        vae_path_len,
+        taesd_path_ptr,
+        taesd_path_len,
+        control_net_path_ptr,
+        control_net_path_len,
+        lora_model_dir_ptr,
+        lora_model_dir_len,
+        embed_dir_ptr,
+        embed_dir_len,
+        id_embed_dir_ptr,
+        id_embed_dir_len,
+        vae_decode_only as i32,
+        vae_tiling as i32,
+        n_threads,
+        wtype as i32,
+        rng_type as i32,
+        schedule as i32,
+        clip_on_cpu as i32,
+        control_net_cpu as i32,
+        vae_on_cpu as i32,
+        session_id,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Sets the eta of the next generations on `session_id`, the noise DDIM-trailing
+/// and TCD add back at each step.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module.
+#[cfg(feature = "host-v2")]
+pub unsafe fn set_eta(session_id: u32, eta: f32) -> Result<(), WasmedgeSdErrno> {
+    let result = wasmedge_stablediffusion::set_eta(session_id as i32, eta);
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Asks the host which sampling methods and schedules it runs. Bit `i` of
+/// `sample_methods` and `schedules` is set when the host supports
+/// stable-diffusion.cpp id `i`.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. `sample_methods`
+/// and `schedules` must be valid for writes.
+#[cfg(feature = "host-v2")]
+pub unsafe fn capabilities(sample_methods: *mut u32, schedules: *mut u32) -> Result<(), WasmedgeSdErrno> {
+    let result = wasmedge_stablediffusion::capabilities(sample_methods, schedules);
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Sets the guidance used by the next generations on `session_id`:
+/// classifier-free guidance of the text and image conditions, distilled
+/// guidance and skip-layer guidance. `slg_layers` is sent as little-endian
+/// `i32`s.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+#[cfg(feature = "host-v2")]
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn set_guidance_params(
+    session_id: u32,
+    cfg_scale: f32,
+    img_cfg_scale: f32,
+    distilled_guidance: f32,
+    slg_layers: &[i32],
+    slg_start: f32,
+    slg_end: f32,
+    slg_scale: f32,
+) -> Result<(), WasmedgeSdErrno> {
+    let slg_layers = encode_i32s(slg_layers);
+    let slg_layers = HostSlice::bytes(&slg_layers)?;
+    let (slg_layers_ptr, slg_layers_len) = slg_layers.raw();
+    let result = wasmedge_stablediffusion::set_guidance_params(
+        session_id as i32,
+        cfg_scale,
+        img_cfg_scale,
+        distilled_guidance,
+        slg_layers_ptr,
+        slg_layers_len,
+        slg_start,
+        slg_end,
+        slg_scale,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+///
+/// The host writes the encoded image into `output_buf` and its length into
+/// `bytes_written`. When `output_buf` is too small the host fails with
+/// [`WASMEDGE_SD_ERRNO_MISSING_MEMORY`] and, if it knows, stores the size it
+/// needs in `bytes_written`.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn text_to_image(
     prompt: &str,
     session_id: u32,
@@ -327,27 +753,29 @@ pub unsafe fn text_to_image(
     upscale_model: &str,
     upscale_repeats: i32,
     output_path: &str,
-    output_buf: *mut u8,
-    out_buffer_max_size: i32,
-) -> Result<u32, WasmedgeSdErrno> {
-    let prompt_ptr = prompt.as_ptr() as i32;
-    let prompt_len = prompt.len() as i32;
+    output_buf: &mut [u8],
+    bytes_written: &mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let prompt = HostSlice::c_str(prompt)?;
+    let (prompt_ptr, prompt_len) = prompt.raw();
     let session_id = session_id as i32;
-    let (control_image_ptr, control_image_len) = parse_image(control_image);
-    let negative_prompt_ptr = negative_prompt.as_ptr() as i32;
-    let negative_prompt_len = negative_prompt.len() as i32;
-    let sample_method = sample_method as i32;
-    let input_id_images_dir_ptr = input_id_images_dir.as_ptr() as i32;
-    let input_id_images_dir_len = input_id_images_dir.len() as i32;
+    let control_image_data = encode_image(control_image)?;
+    let control_image = HostSlice::bytes(&control_image_data)?;
+    let (control_image_ptr, control_image_len) = control_image.raw();
+    let negative_prompt = HostSlice::c_str(negative_prompt)?;
+    let (negative_prompt_ptr, negative_prompt_len) = negative_prompt.raw();
+    let sample_method = host_sample_method(sample_method)?;
+    let input_id_images_dir = HostSlice::c_str(input_id_images_dir)?;
+    let (input_id_images_dir_ptr, input_id_images_dir_len) = input_id_images_dir.raw();
     let normalize_input = normalize_input as i32;
     let canny_preprocess = canny_preprocess as i32;
-    let upscale_model_path_ptr = upscale_model.as_ptr() as i32;
-    let upscale_model_path_len = upscale_model.len() as i32;
-    let output_path_ptr = output_path.as_ptr() as i32;
-    let output_path_len = output_path.len() as i32;
-    let output_buf_ptr = output_buf as i32;
-    let out_buffer_max_size = out_buffer_max_size as i32;
-    let mut write_bytes = MaybeUninit::<u32>::uninit();
+    let upscale_model = HostSlice::c_str(upscale_model)?;
+    let (upscale_model_path_ptr, upscale_model_path_len) = upscale_model.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
+    let output_buf = HostSliceMut::new(output_buf)?;
+    let (output_buf_ptr, out_buffer_max_size) = output_buf.raw();
+    *bytes_written = 0;
     let result = wasmedge_stablediffusion::text_to_image(
         prompt_ptr,
         prompt_len,
@@ -377,14 +805,24 @@ pub unsafe fn text_to_image(
         output_path_len,
         output_buf_ptr,
         out_buffer_max_size,
-        write_bytes.as_mut_ptr() as i32,
+        bytes_written as *mut u32,
     );
     if result != 0 {
         Err(WasmedgeSdErrno(result as u32))
     } else {
-        Ok(write_bytes.assume_init())
+        Ok(())
     }
 }
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+///
+/// The host writes the encoded image into `output_buf` and its length into
+/// `bytes_written`. When `output_buf` is too small the host fails with
+/// [`WASMEDGE_SD_ERRNO_MISSING_MEMORY`] and, if it knows, stores the size it
+/// needs in `bytes_written`.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn image_to_image(
     image: &ImageType,
     session_id: u32,
@@ -408,28 +846,32 @@ pub unsafe fn image_to_image(
     upscale_model_path: &str,
     upscale_repeats: i32,
     output_path: &str,
-    output_buf: *mut u8,
-    out_buffer_max_size: i32,
-) -> Result<u32, WasmedgeSdErrno> {
-    let (image_ptr, image_len) = parse_image(image);
-    let (control_image_ptr, control_image_len) = parse_image(control_image);
+    output_buf: &mut [u8],
+    bytes_written: &mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let image_data = encode_image(image)?;
+    let image = HostSlice::bytes(&image_data)?;
+    let (image_ptr, image_len) = image.raw();
+    let control_image_data = encode_image(control_image)?;
+    let control_image = HostSlice::bytes(&control_image_data)?;
+    let (control_image_ptr, control_image_len) = control_image.raw();
     let session_id = session_id as i32;
-    let prompt_ptr = prompt.as_ptr() as i32;
-    let prompt_len = prompt.len() as i32;
-    let negative_prompt_ptr = negative_prompt.as_ptr() as i32;
-    let negative_prompt_len = negative_prompt.len() as i32;
-    let sample_method = sample_method as i32;
+    let prompt = HostSlice::c_str(prompt)?;
+    let (prompt_ptr, prompt_len) = prompt.raw();
+    let negative_prompt = HostSlice::c_str(negative_prompt)?;
+    let (negative_prompt_ptr, negative_prompt_len) = negative_prompt.raw();
+    let sample_method = host_sample_method(sample_method)?;
     let normalize_input = normalize_input as i32;
-    let input_id_images_dir_ptr = input_id_images_dir.as_ptr() as i32;
-    let input_id_images_dir_len = input_id_images_dir.len() as i32;
+    let input_id_images_dir = HostSlice::c_str(input_id_images_dir)?;
+    let (input_id_images_dir_ptr, input_id_images_dir_len) = input_id_images_dir.raw();
     let canny_preprocess = canny_preprocess as i32;
-    let upscale_model_path_ptr = upscale_model_path.as_ptr() as i32;
-    let upscale_model_path_len = upscale_model_path.len() as i32;
-    let output_path_ptr = output_path.as_ptr() as i32;
-    let output_path_len = output_path.len() as i32;
-    let output_buf_ptr = output_buf as i32;
-    let out_buffer_max_size = out_buffer_max_size as i32;
-    let mut write_bytes = MaybeUninit::<u32>::uninit();
+    let upscale_model_path = HostSlice::c_str(upscale_model_path)?;
+    let (upscale_model_path_ptr, upscale_model_path_len) = upscale_model_path.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
+    let output_buf = HostSliceMut::new(output_buf)?;
+    let (output_buf_ptr, out_buffer_max_size) = output_buf.raw();
+    *bytes_written = 0;
     let result = wasmedge_stablediffusion::image_to_image(
         image_ptr,
         image_len,
@@ -462,31 +904,243 @@ pub unsafe fn image_to_image(
         output_path_len,
         output_buf_ptr,
         out_buffer_max_size,
-        write_bytes.as_mut_ptr() as i32,
+        bytes_written as *mut u32,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Regenerates the parts of `image` that are white in `mask`. The mask is
+/// blurred by `mask_blur` pixels first; with `keep_unmasked` the pixels
+/// outside the mask are copied from `image` unchanged.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+///
+/// The host writes the encoded image into `output_buf` and its length into
+/// `bytes_written`. When `output_buf` is too small the host fails with
+/// [`WASMEDGE_SD_ERRNO_MISSING_MEMORY`] and, if it knows, stores the size it
+/// needs in `bytes_written`.
+#[allow(clippy::too_many_arguments)]
+#[cfg(feature = "host-v2")]
+pub unsafe fn inpaint(
+    image: &ImageType,
+    mask: &ImageType,
+    session_id: u32,
+    width: i32,
+    height: i32,
+    control_image: &ImageType,
+    prompt: &str,
+    negative_prompt: &str,
+    clip_skip: i32,
+    cfg_scale: f32,
+    sample_method: SampleMethodT,
+    sample_steps: i32,
+    strength: f32,
+    mask_blur: i32,
+    keep_unmasked: bool,
+    seed: i32,
+    batch_count: i32,
+    control_strength: f32,
+    style_ratio: f32,
+    normalize_input: bool,
+    input_id_images_dir: &str,
+    canny_preprocess: bool,
+    upscale_model_path: &str,
+    upscale_repeats: i32,
+    output_path: &str,
+    output_buf: &mut [u8],
+    bytes_written: &mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let image_data = encode_image(image)?;
+    let image = HostSlice::bytes(&image_data)?;
+    let (image_ptr, image_len) = image.raw();
+    let mask_data = encode_image(mask)?;
+    let mask = HostSlice::bytes(&mask_data)?;
+    let (mask_ptr, mask_len) = mask.raw();
+    let control_image_data = encode_image(control_image)?;
+    let control_image = HostSlice::bytes(&control_image_data)?;
+    let (control_image_ptr, control_image_len) = control_image.raw();
+    let session_id = session_id as i32;
+    let prompt = HostSlice::c_str(prompt)?;
+    let (prompt_ptr, prompt_len) = prompt.raw();
+    let negative_prompt = HostSlice::c_str(negative_prompt)?;
+    let (negative_prompt_ptr, negative_prompt_len) = negative_prompt.raw();
+    let sample_method = host_sample_method(sample_method)?;
+    let normalize_input = normalize_input as i32;
+    let input_id_images_dir = HostSlice::c_str(input_id_images_dir)?;
+    let (input_id_images_dir_ptr, input_id_images_dir_len) = input_id_images_dir.raw();
+    let keep_unmasked = keep_unmasked as i32;
+    let canny_preprocess = canny_preprocess as i32;
+    let upscale_model_path = HostSlice::c_str(upscale_model_path)?;
+    let (upscale_model_path_ptr, upscale_model_path_len) = upscale_model_path.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
+    let output_buf = HostSliceMut::new(output_buf)?;
+    let (output_buf_ptr, out_buffer_max_size) = output_buf.raw();
+    *bytes_written = 0;
+    let result = wasmedge_stablediffusion::inpaint(
+        image_ptr,
+        image_len,
+        mask_ptr,
+        mask_len,
+        session_id,
+        width,
+        height,
+        control_image_ptr,
+        control_image_len,
+        prompt_ptr,
+        prompt_len,
+        negative_prompt_ptr,
+        negative_prompt_len,
+        clip_skip,
+        cfg_scale,
+        sample_method,
+        sample_steps,
+        strength,
+        mask_blur,
+        keep_unmasked,
+        seed,
+        batch_count,
+        control_strength,
+        style_ratio,
+        normalize_input,
+        input_id_images_dir_ptr,
+        input_id_images_dir_len,
+        canny_preprocess,
+        upscale_model_path_ptr,
+        upscale_model_path_len,
+        upscale_repeats,
+        output_path_ptr,
+        output_path_len,
+        output_buf_ptr,
+        out_buffer_max_size,
+        bytes_written as *mut u32,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Loads only an ESRGAN upscaler, without a diffusion model, and stores the
+/// new session id in `session_id`. Release it with [`free_context`].
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. `session_id` must be
+/// valid for writes.
+#[cfg(feature = "host-v2")]
+pub unsafe fn create_upscaler_context(
+    upscale_model_path: &str,
+    n_threads: i32,
+    wtype: SdTypeT,
+    session_id: *mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let upscale_model_path = HostSlice::c_str(upscale_model_path)?;
+    let (upscale_model_path_ptr, upscale_model_path_len) = upscale_model_path.raw();
+    let wtype = wtype as i32;
+    let session_id_ptr = session_id;
+    let result = wasmedge_stablediffusion::create_upscaler_context(
+        upscale_model_path_ptr,
+        upscale_model_path_len,
+        n_threads,
+        wtype,
+        session_id_ptr,
+    );
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
+    }
+}
+/// Runs the upscaler of an upscaler session `upscale_repeats` times over
+/// `image`.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. All borrowed arguments
+/// must stay alive until the call returns.
+///
+/// The host writes the encoded image into `output_buf` and its length into
+/// `bytes_written`. When `output_buf` is too small the host fails with
+/// [`WASMEDGE_SD_ERRNO_MISSING_MEMORY`] and, if it knows, stores the size it
+/// needs in `bytes_written`.
+#[cfg(feature = "host-v2")]
+pub unsafe fn upscale(
+    session_id: u32,
+    image: &ImageType,
+    upscale_repeats: i32,
+    output_path: &str,
+    output_buf: &mut [u8],
+    bytes_written: &mut u32,
+) -> Result<(), WasmedgeSdErrno> {
+    let session_id = session_id as i32;
+    let image_data = encode_image(image)?;
+    let image = HostSlice::bytes(&image_data)?;
+    let (image_ptr, image_len) = image.raw();
+    let output_path = HostSlice::c_str(output_path)?;
+    let (output_path_ptr, output_path_len) = output_path.raw();
+    let output_buf = HostSliceMut::new(output_buf)?;
+    let (output_buf_ptr, out_buffer_max_size) = output_buf.raw();
+    *bytes_written = 0;
+    let result = wasmedge_stablediffusion::upscale(
+        session_id,
+        image_ptr,
+        image_len,
+        upscale_repeats,
+        output_path_ptr,
+        output_path_len,
+        output_buf_ptr,
+        out_buffer_max_size,
+        bytes_written as *mut u32,
     );
     if result != 0 {
         Err(WasmedgeSdErrno(result as u32))
     } else {
-        Ok(write_bytes.assume_init())
+        Ok(())
+    }
+}
+/// Releases the host context behind `session_id`.
+///
+/// # Safety
+///
+/// Calls into the `wasmedge_stablediffusion` host module. `session_id` must not
+/// be used after this returns.
+#[cfg(feature = "host-v2")]
+pub unsafe fn free_context(session_id: u32) -> Result<(), WasmedgeSdErrno> {
+    let result = wasmedge_stablediffusion::free_context(session_id as i32);
+    if result != 0 {
+        Err(WasmedgeSdErrno(result as u32))
+    } else {
+        Ok(())
     }
 }
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use crate::no_host as wasmedge_stablediffusion;
+
+#[cfg(target_arch = "wasm32")]
 pub mod wasmedge_stablediffusion {
     #[link(wasm_import_module = "wasmedge_stablediffusion")]
     extern "C" {
         pub fn create_context(
-            model_path_ptr: i32,
+            model_path_ptr: *const u8,
             model_path_len: i32,
-            vae_path_ptr: i32,
+            vae_path_ptr: *const u8,
             vae_path_len: i32,
-            taesd_path_ptr: i32,
+            taesd_path_ptr: *const u8,
             taesd_path_len: i32,
-            control_net_path_ptr: i32,
+            control_net_path_ptr: *const u8,
             control_net_path_len: i32,
-            lora_model_dir_ptr: i32,
+            lora_model_dir_ptr: *const u8,
             lora_model_dir_len: i32,
-            embed_dir_ptr: i32,
+            embed_dir_ptr: *const u8,
             embed_dir_len: i32,
-            id_embed_dir_ptr: i32,
+            id_embed_dir_ptr: *const u8,
             id_embed_dir_len: i32,
             vae_decode_only: i32,
             vae_tiling: i32,
@@ -497,20 +1151,20 @@ pub mod wasmedge_stablediffusion {
             clip_on_cpu: i32,
             control_net_cpu: i32,
             vae_on_cpu: i32,
-            session_id_ptr: i32,
+            session_id_ptr: *mut u32,
         ) -> i32;
 
         pub fn image_to_image(
-            image_ptr: i32,
+            image_ptr: *const u8,
             image_len: i32,
             session_id: i32,
             width: i32,
             height: i32,
-            control_image_ptr: i32,
+            control_image_ptr: *const u8,
             control_image_len: i32,
-            prompt_ptr: i32,
+            prompt_ptr: *const u8,
             prompt_len: i32,
-            negative_prompt_ptr: i32,
+            negative_prompt_ptr: *const u8,
             negative_prompt_len: i32,
             clip_skip: i32,
             cfg_scale: f32,
@@ -522,26 +1176,26 @@ pub mod wasmedge_stablediffusion {
             control_strength: f32,
             style_ratio: f32,
             normalize_input: i32,
-            input_id_images_dir_ptr: i32,
+            input_id_images_dir_ptr: *const u8,
             input_id_images_dir_len: i32,
             canny_preprocess: i32,
-            upscale_model_path_ptr: i32,
+            upscale_model_path_ptr: *const u8,
             upscale_model_path_len: i32,
             upscale_repeats: i32,
-            output_path_ptr: i32,
+            output_path_ptr: *const u8,
             output_path_len: i32,
-            out_buffer_ptr: i32,
+            out_buffer_ptr: *mut u8,
             out_buffer_max_size: i32,
-            bytes_written_ptr: i32,
+            bytes_written_ptr: *mut u32,
         ) -> i32;
 
         pub fn text_to_image(
-            prompt_ptr: i32,
+            prompt_ptr: *const u8,
             prompt_len: i32,
             session_id: i32,
-            control_image_ptr: i32,
+            control_image_ptr: *const u8,
             control_image_len: i32,
-            negative_prompt_ptr: i32,
+            negative_prompt_ptr: *const u8,
             negative_prompt_len: i32,
             width: i32,
             height: i32,
@@ -554,27 +1208,239 @@ pub mod wasmedge_stablediffusion {
             control_strength: f32,
             style_ratio: f32,
             normalize_input: i32,
-            input_id_images_dir_ptr: i32,
+            input_id_images_dir_ptr: *const u8,
             input_id_images_dir_len: i32,
             canny_preprocess: i32,
-            upscale_model_path_ptr: i32,
+            upscale_model_path_ptr: *const u8,
             upscale_model_path_len: i32,
             upscale_repeats: i32,
-            output_path_ptr: i32,
+            output_path_ptr: *const u8,
             output_path_len: i32,
-            out_buffer_ptr: i32,
+            out_buffer_ptr: *mut u8,
             out_buffer_max_size: i32,
-            bytes_written_ptr: i32,
+            bytes_written_ptr: *mut u32,
         ) -> i32;
 
         pub fn convert(
-            model_path_ptr: i32,
+            model_path_ptr: *const u8,
+            model_path_len: i32,
+            vae_model_path_ptr: *const u8,
+            vae_model_path_len: i32,
+            output_path_ptr: *const u8,
+            output_path_len: i32,
+            wtype: i32,
+        ) -> i32;
+    }
+    /// Imported only with the `host-v2` feature, so modules built without it
+    /// still load in plugins that lack these functions.
+    #[cfg(feature = "host-v2")]
+    #[link(wasm_import_module = "wasmedge_stablediffusion")]
+    extern "C" {
+        pub fn create_context_v2(
+            model_path_ptr: *const u8,
+            model_path_len: i32,
+            clip_l_path_ptr: *const u8,
+            clip_l_path_len: i32,
+            clip_g_path_ptr: *const u8,
+            clip_g_path_len: i32,
+            t5xxl_path_ptr: *const u8,
+            t5xxl_path_len: i32,
+            diffusion_model_path_ptr: *const u8,
+            diffusion_model_path_len: i32,
+            vae_path_ptr: *const u8,
+            vae_path_len: i32,
+            taesd_path_ptr: *const u8,
+            taesd_path_len: i32,
+            control_net_path_ptr: *const u8,
+            control_net_path_len: i32,
+            lora_model_dir_ptr: *const u8,
+            lora_model_dir_len: i32,
+            embed_dir_ptr: *const u8,
+            embed_dir_len: i32,
+            id_embed_dir_ptr: *const u8,
+            id_embed_dir_len: i32,
+            vae_decode_only: i32,
+            vae_tiling: i32,
+            n_threads: i32,
+            wtype: i32,
+            rng_type: i32,
+            schedule: i32,
+            clip_on_cpu: i32,
+            control_net_cpu: i32,
+            vae_on_cpu: i32,
+            session_id_ptr: *mut u32,
+        ) -> i32;
+
+        pub fn set_guidance_params(
+            session_id: i32,
+            cfg_scale: f32,
+            img_cfg_scale: f32,
+            distilled_guidance: f32,
+            slg_layers_ptr: *const u8,
+            slg_layers_len: i32,
+            slg_start: f32,
+            slg_end: f32,
+            slg_scale: f32,
+        ) -> i32;
+
+        pub fn set_eta(session_id: i32, eta: f32) -> i32;
+
+        pub fn capabilities(sample_methods_ptr: *mut u32, schedules_ptr: *mut u32) -> i32;
+
+        pub fn inpaint(
+            image_ptr: *const u8,
+            image_len: i32,
+            mask_ptr: *const u8,
+            mask_len: i32,
+            session_id: i32,
+            width: i32,
+            height: i32,
+            control_image_ptr: *const u8,
+            control_image_len: i32,
+            prompt_ptr: *const u8,
+            prompt_len: i32,
+            negative_prompt_ptr: *const u8,
+            negative_prompt_len: i32,
+            clip_skip: i32,
+            cfg_scale: f32,
+            sample_method: i32,
+            sample_steps: i32,
+            strength: f32,
+            mask_blur: i32,
+            keep_unmasked: i32,
+            seed: i32,
+            batch_count: i32,
+            control_strength: f32,
+            style_ratio: f32,
+            normalize_input: i32,
+            input_id_images_dir_ptr: *const u8,
+            input_id_images_dir_len: i32,
+            canny_preprocess: i32,
+            upscale_model_path_ptr: *const u8,
+            upscale_model_path_len: i32,
+            upscale_repeats: i32,
+            output_path_ptr: *const u8,
+            output_path_len: i32,
+            out_buffer_ptr: *mut u8,
+            out_buffer_max_size: i32,
+            bytes_written_ptr: *mut u32,
+        ) -> i32;
+
+        pub fn create_upscaler_context(
+            upscale_model_path_ptr: *const u8,
+            upscale_model_path_len: i32,
+            n_threads: i32,
+            wtype: i32,
+            session_id_ptr: *mut u32,
+        ) -> i32;
+
+        pub fn upscale(
+            session_id: i32,
+            image_ptr: *const u8,
+            image_len: i32,
+            upscale_repeats: i32,
+            output_path_ptr: *const u8,
+            output_path_len: i32,
+            out_buffer_ptr: *mut u8,
+            out_buffer_max_size: i32,
+            bytes_written_ptr: *mut u32,
+        ) -> i32;
+
+        pub fn free_context(session_id: i32) -> i32;
+
+        pub fn convert_with_rules(
+            model_path_ptr: *const u8,
             model_path_len: i32,
-            vae_model_path_ptr: i32,
+            vae_model_path_ptr: *const u8,
             vae_model_path_len: i32,
-            output_path_ptr: i32,
+            output_path_ptr: *const u8,
             output_path_len: i32,
             wtype: i32,
+            tensor_type_rules_ptr: *const u8,
+            tensor_type_rules_len: i32,
         ) -> i32;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for wtype in SdTypeT::all() {
+            assert_eq!(wtype.to_string().parse::<SdTypeT>(), Ok(wtype));
+            assert_eq!(SdTypeT::try_from(wtype as i32), Ok(wtype));
+        }
+        assert_eq!(SdTypeT::ALL.len(), SdTypeT::NAMES.len());
+        assert_eq!("dpm++2m".parse::<SampleMethodT>(), Ok(SampleMethodT::DPMPP2M));
+        assert_eq!("ddim_trailing".parse::<SampleMethodT>(), Ok(SampleMethodT::DDIMTRAILING));
+        assert_eq!(SampleMethodT::try_from(11), Ok(SampleMethodT::TCD));
+        assert_eq!(SampleMethodT::from_index(7), Ok(SampleMethodT::IPNDM));
+        assert_eq!(SampleMethodT::from_index(9), Ok(SampleMethodT::LCM));
+        assert_eq!(ScheduleT::from_index(3), Ok(ScheduleT::EXPONENTIAL));
+        assert_eq!(ScheduleT::from_index(4), Ok(ScheduleT::AYS));
+        assert_eq!("cuda".parse::<RngTypeT>(), Ok(RngTypeT::CUDARng));
+        assert_eq!(SdTypeT::SdTypeQ4K.to_string(), "q4_k");
+        // The sentinel still displays, but is not a value.
+        assert_eq!(ScheduleT::NSCHEDULES.to_string(), "n_schedules");
+    }
+
+    #[test]
+    fn older_spellings_parse() {
+        assert_eq!("q4k".parse::<SdTypeT>(), Ok(SdTypeT::SdTypeQ4K));
+        assert_eq!("iq2Xxs".parse::<SdTypeT>(), Ok(SdTypeT::SdTypeIq2Xxs));
+        assert_eq!("iq4N1".parse::<SdTypeT>(), Ok(SdTypeT::SdTypeIq4Nl));
+        assert_eq!("count".parse::<SdTypeT>(), Ok(SdTypeT::SdTypeCount));
+        assert_eq!(SdTypeT::SdTypeCount.aliases(), ["count"]);
+        assert!(SdTypeT::SdTypeF16.aliases().is_empty());
+        assert!(!SdTypeT::NAMES.contains(&"q4k"));
+        assert_eq!(SdTypeT::SdTypeQ4K.to_string(), "q4_k");
+    }
+
+    #[test]
+    fn retired_ids_and_sentinels_are_errors() {
+        // 4 and 5 were q4_2 and q4_3, which ggml removed.
+        assert_eq!(SdTypeT::from_index(4), Err("Invalid weight type id: 4".to_string()));
+        assert!(SdTypeT::try_from(5).is_err());
+        assert!(SdTypeT::try_from(32).is_err());
+        // 31 is the count of the C enum, which the host takes as a value.
+        assert_eq!(SdTypeT::try_from(31), Ok(SdTypeT::SdTypeCount));
+        assert_eq!(
+            SampleMethodT::try_from(SampleMethodT::NSAMPLEMETHODS as i32),
+            Err("Invalid sampling method id: 12".to_string())
+        );
+        assert_eq!(ScheduleT::from_index(5), Ok(ScheduleT::GITS));
+        assert!(ScheduleT::from_index(6).is_err());
+        assert!(ScheduleT::from_index(usize::MAX).is_err());
+        assert!(SampleMethodT::try_from(-1).is_err());
+        assert_eq!(
+            "n_schedules".parse::<ScheduleT>(),
+            Err("Invalid schedule: n_schedules (expected one of default, discrete, karras, exponential, ays, gits)".to_string())
+        );
+        assert!(!SampleMethodT::all().any(|method| method == SampleMethodT::NSAMPLEMETHODS));
+    }
+
+    #[test]
+    fn older_plugins_use_legacy_ids() {
+        for method in SampleMethodT::all().take(7) {
+            assert_eq!(method.legacy_id(), Some(method as i32));
+        }
+        assert_eq!(SampleMethodT::LCM.legacy_id(), Some(7));
+        assert_eq!(SampleMethodT::IPNDM.legacy_id(), None);
+        assert_eq!(SampleMethodT::TCD.legacy_id(), None);
+        assert_eq!(ScheduleT::AYS.legacy_id(), Some(3));
+        assert_eq!(ScheduleT::KARRAS.legacy_id(), Some(2));
+        assert_eq!(ScheduleT::EXPONENTIAL.legacy_id(), None);
+        assert_eq!(ScheduleT::GITS.legacy_id(), None);
+
+        if cfg!(feature = "host-v2") {
+            assert_eq!(host_sample_method(SampleMethodT::LCM), Ok(9));
+            assert_eq!(host_schedule(ScheduleT::AYS), Ok(4));
+        } else {
+            assert_eq!(host_sample_method(SampleMethodT::LCM), Ok(7));
+            assert_eq!(host_schedule(ScheduleT::AYS), Ok(3));
+            assert_eq!(host_sample_method(SampleMethodT::TCD), Err(WASMEDGE_SD_ERRNO_INVALID_ARGUMENT));
+        }
+    }
+}
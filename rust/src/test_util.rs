@@ -0,0 +1,26 @@
+//! Helpers shared by the unit tests.
+use std::path::{Path, PathBuf};
+
+/// An empty directory under the system temp dir, deleted with its contents
+/// on drop, so a failing test does not leave it behind.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `sd-<name>-<pid>`, emptying it if a previous run left it.
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("sd-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
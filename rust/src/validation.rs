@@ -0,0 +1,313 @@
+//! Guest-side checks run before a generation is sent to the host.
+//!
+//! The host turns most bad values into an opaque `RUNTIME_ERROR`, or worse, a
+//! crash inside the plugin. [`validate_params`] catches them early and names
+//! the offending field.
+use crate::stable_diffusion_interface::SampleMethodT;
+use crate::{GenerationParams, GuidanceParams};
+use core::fmt;
+
+/// Steps above which LCM is outside what it was distilled for.
+const LCM_MAX_STEPS: i32 = 8;
+/// CFG scale above which LCM output burns out.
+const LCM_MAX_CFG_SCALE: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The host would fail or misbehave; generation is refused.
+    Error,
+    /// Allowed, but unlikely to give a good image.
+    Warning,
+}
+
+/// One rule a parameter breaks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub field: &'static str,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Violation {
+    pub fn error(field: &'static str, message: impl Into<String>) -> Violation {
+        Violation {
+            field,
+            message: message.into(),
+            severity: Severity::Error,
+        }
+    }
+    pub fn warning(field: &'static str, message: impl Into<String>) -> Violation {
+        Violation {
+            field,
+            message: message.into(),
+            severity: Severity::Warning,
+        }
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.field, self.message)
+    }
+}
+
+/// Checks a float lies in `range`, treating NaN as out of range.
+pub(crate) fn check_range(
+    violations: &mut Vec<Violation>,
+    field: &'static str,
+    value: f32,
+    range: core::ops::RangeInclusive<f32>,
+) {
+    if !range.contains(&value) {
+        violations.push(Violation::error(
+            field,
+            format!("must be in [{}, {}], got {}", range.start(), range.end(), value),
+        ));
+    }
+}
+
+/// Checks the parameters shared by every task.
+pub fn validate_params(params: &GenerationParams) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (field, value) in [("width", params.width), ("height", params.height)] {
+        if value <= 0 || value % 8 != 0 {
+            violations.push(Violation::error(
+                field,
+                format!("must be a positive multiple of 8, got {}", value),
+            ));
+        }
+    }
+    if params.sample_steps <= 0 {
+        violations.push(Violation::error(
+            "sample_steps",
+            format!("must be greater than 0, got {}", params.sample_steps),
+        ));
+    }
+    validate_guidance(&mut violations, &params.guidance);
+    if params.batch_count < 1 {
+        violations.push(Violation::error(
+            "batch_count",
+            format!("must be at least 1, got {}", params.batch_count),
+        ));
+    }
+    check_range(&mut violations, "eta", params.eta, 0.0..=1.0);
+    if params.eta != 0.0
+        && !matches!(params.sample_method, SampleMethodT::DDIMTRAILING | SampleMethodT::TCD)
+    {
+        violations.push(Violation::warning(
+            "eta",
+            format!("only ddim_trailing and tcd use eta, not {}", params.sample_method),
+        ));
+    }
+    check_range(&mut violations, "control_strength", params.control_strength, 0.0..=1.0);
+    check_range(&mut violations, "style_ratio", params.style_ratio, 0.0..=100.0);
+    if params.upscale_repeats < 1 {
+        violations.push(Violation::error(
+            "upscale_repeats",
+            format!("must be at least 1, got {}", params.upscale_repeats),
+        ));
+    }
+    match params.sample_method {
+        SampleMethodT::NSAMPLEMETHODS => {
+            violations.push(Violation::error("sample_method", "is not a sampling method"));
+        }
+        SampleMethodT::LCM => {
+            if params.sample_steps > LCM_MAX_STEPS {
+                violations.push(Violation::warning(
+                    "sample_steps",
+                    format!(
+                        "LCM is meant for 2 to {} steps, got {}",
+                        LCM_MAX_STEPS, params.sample_steps
+                    ),
+                ));
+            }
+            if params.guidance.cfg_scale > LCM_MAX_CFG_SCALE {
+                violations.push(Violation::warning(
+                    "cfg_scale",
+                    format!(
+                        "LCM is meant for a cfg_scale of 1.0 to {:.1}, got {}",
+                        LCM_MAX_CFG_SCALE, params.guidance.cfg_scale
+                    ),
+                ));
+            }
+        }
+        _ => {}
+    }
+    violations
+}
+
+/// Checks the guidance scales and the skip-layer guidance window and layers.
+fn validate_guidance(violations: &mut Vec<Violation>, guidance: &GuidanceParams) {
+    let scales = [
+        ("cfg_scale", Some(guidance.cfg_scale)),
+        ("img_cfg_scale", guidance.img_cfg_scale),
+        ("distilled_guidance", Some(guidance.distilled_guidance)),
+        ("slg_scale", Some(guidance.slg_scale)),
+    ];
+    for (field, value) in scales {
+        if let Some(value) = value.filter(|value| !(*value >= 0.0 && value.is_finite())) {
+            violations.push(Violation::error(
+                field,
+                format!("must be a non-negative number, got {}", value),
+            ));
+        }
+    }
+    check_range(violations, "slg_start", guidance.slg_start, 0.0..=1.0);
+    check_range(violations, "slg_end", guidance.slg_end, 0.0..=1.0);
+    if guidance.slg_end < guidance.slg_start {
+        violations.push(Violation::error(
+            "slg_end",
+            format!(
+                "must not be before slg_start ({}), got {}",
+                guidance.slg_start, guidance.slg_end
+            ),
+        ));
+    }
+    if guidance.slg_scale > 0.0 && guidance.slg_layers.is_empty() {
+        violations.push(Violation::error("slg_layers", "must not be empty when slg_scale is set"));
+    }
+    for (i, &layer) in guidance.slg_layers.iter().enumerate() {
+        if layer < 0 {
+            violations.push(Violation::error(
+                "slg_layers",
+                format!("must not be negative, got {}", layer),
+            ));
+        } else if guidance.slg_layers[..i].contains(&layer) {
+            violations.push(Violation::error(
+                "slg_layers",
+                format!("lists layer {} more than once", layer),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(violations: &[Violation]) -> Vec<&'static str> {
+        violations.iter().map(|violation| violation.field).collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(validate_params(&GenerationParams::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_every_bad_field() {
+        let params = GenerationParams {
+            width: 500,
+            height: 0,
+            sample_steps: 0,
+            guidance: GuidanceParams {
+                cfg_scale: f32::NAN,
+                distilled_guidance: -1.0,
+                ..GuidanceParams::default()
+            },
+            batch_count: -1,
+            sample_method: SampleMethodT::TCD,
+            eta: 1.5,
+            control_strength: 1.5,
+            style_ratio: -1.0,
+            upscale_repeats: 0,
+            ..GenerationParams::default()
+        };
+        let violations = validate_params(&params);
+        assert!(violations.iter().all(Violation::is_error));
+        assert_eq!(
+            fields(&violations),
+            vec![
+                "width",
+                "height",
+                "sample_steps",
+                "cfg_scale",
+                "distilled_guidance",
+                "batch_count",
+                "eta",
+                "control_strength",
+                "style_ratio",
+                "upscale_repeats",
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "error: width: must be a positive multiple of 8, got 500"
+        );
+    }
+
+    #[test]
+    fn lcm_warns_about_high_steps_and_cfg() {
+        let params = GenerationParams {
+            sample_method: SampleMethodT::LCM,
+            ..GenerationParams::default()
+        };
+        let violations = validate_params(&params);
+        assert_eq!(fields(&violations), vec!["sample_steps", "cfg_scale"]);
+        assert!(!violations.iter().any(Violation::is_error));
+
+        let params = GenerationParams {
+            sample_steps: 4,
+            guidance: GuidanceParams {
+                cfg_scale: 1.0,
+                ..GuidanceParams::default()
+            },
+            ..params
+        };
+        assert!(validate_params(&params).is_empty());
+    }
+
+    #[test]
+    fn eta_only_applies_to_ddim_and_tcd() {
+        let params = GenerationParams {
+            eta: 0.3,
+            ..GenerationParams::default()
+        };
+        let violations = validate_params(&params);
+        assert_eq!(fields(&violations), vec!["eta"]);
+        assert_eq!(violations[0].message, "only ddim_trailing and tcd use eta, not euler_a");
+        assert!(!violations[0].is_error());
+        for sample_method in [SampleMethodT::DDIMTRAILING, SampleMethodT::TCD] {
+            assert!(validate_params(&GenerationParams { sample_method, ..params.clone() }).is_empty());
+        }
+    }
+
+    #[test]
+    fn checks_skip_layer_guidance() {
+        let guidance = GuidanceParams {
+            img_cfg_scale: Some(f32::INFINITY),
+            slg_layers: vec![7, -1, 7],
+            slg_start: 0.5,
+            slg_end: 0.25,
+            slg_scale: 2.5,
+            ..GuidanceParams::default()
+        };
+        let violations = validate_params(&GenerationParams {
+            guidance,
+            ..GenerationParams::default()
+        });
+        assert_eq!(fields(&violations), vec!["img_cfg_scale", "slg_end", "slg_layers", "slg_layers"]);
+        assert_eq!(violations[1].message, "must not be before slg_start (0.5), got 0.25");
+        assert_eq!(violations[3].message, "lists layer 7 more than once");
+
+        let guidance = GuidanceParams {
+            slg_layers: Vec::new(),
+            ..GuidanceParams::default()
+        };
+        let params = GenerationParams {
+            guidance,
+            ..GenerationParams::default()
+        };
+        assert!(validate_params(&params).is_empty());
+        let mut params = params;
+        params.guidance.slg_scale = 2.5;
+        assert_eq!(fields(&validate_params(&params)), vec!["slg_layers"]);
+    }
+}